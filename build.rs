@@ -1,31 +1,44 @@
 use std::process::Command;
 
+/// (source, entry point, output)
+const SHADERS: &[(&str, &str, &str)] = &[
+    ("main.slang", "fragmentMain", "triangle.fragment.spv"),
+    ("main.slang", "vertexMain", "triangle.vertex.spv"),
+    ("shadow.slang", "vertexMain", "shadow.vertex.spv"),
+    ("id.slang", "vertexMain", "id.vertex.spv"),
+    ("id.slang", "fragmentMain", "id.fragment.spv"),
+    ("overlay.slang", "vertexMain", "overlay.vertex.spv"),
+    ("overlay.slang", "fragmentMain", "overlay.fragment.spv"),
+    ("text.slang", "vertexMain", "text.vertex.spv"),
+    ("text.slang", "fragmentMain", "text.fragment.spv"),
+    (
+        "post_process.slang",
+        "vertexMain",
+        "post_process.vertex.spv",
+    ),
+    (
+        "post_process.slang",
+        "fragmentMain",
+        "post_process.fragment.spv",
+    ),
+];
+
 fn main() {
     let shaders_dir = "src/shaders";
     println!("cargo:rerun-if-changed={shaders_dir}");
 
-    Command::new("slangc")
-        .args([
-            "src/shaders/main.slang",
-            "-target",
-            "spirv",
-            "-o",
-            "assets/shaders/triangle.fragment.spv",
-            "-entry",
-            "fragmentMain",
-        ])
-        .status()
-        .expect("Failed to run command");
-    Command::new("slangc")
-        .args([
-            "src/shaders/main.slang",
-            "-target",
-            "spirv",
-            "-o",
-            "assets/shaders/triangle.vertex.spv",
-            "-entry",
-            "vertexMain",
-        ])
-        .status()
-        .expect("Failed to run command");
+    for (source, entry, output) in SHADERS {
+        Command::new("slangc")
+            .args([
+                &format!("{shaders_dir}/{source}"),
+                "-target",
+                "spirv",
+                "-o",
+                &format!("assets/shaders/{output}"),
+                "-entry",
+                entry,
+            ])
+            .status()
+            .expect("Failed to run command");
+    }
 }
@@ -0,0 +1,67 @@
+//! Renders the default scene offscreen, without a window, and reports how long the frames took.
+//!
+//! Run with `cargo run --example headless_render`.
+
+use std::{path::Path, time::Instant};
+
+use ash::vk;
+use train::{
+    actions::Bindings,
+    assets::DEFAULT_ASSET_ROOT,
+    graphics::{Graphics, GraphicsSettings},
+    input::Input,
+    scene_file::{SceneFile, DEFAULT_SCENE_PATH},
+    time::TICK_DURATION,
+};
+
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 640,
+    height: 360,
+};
+const FRAME_COUNT: u32 = 120;
+
+fn main() {
+    let scene_file = SceneFile::load(&Path::new(DEFAULT_ASSET_ROOT).join(DEFAULT_SCENE_PATH))
+        .unwrap_or_else(|e| {
+            eprintln!("Couldn't load scene: {e}");
+            std::process::exit(1);
+        });
+
+    let mut graphics = Graphics::headless(EXTENT, &GraphicsSettings::default());
+    graphics.camera.set_pose(scene_file.camera);
+    graphics.lighting = scene_file.lighting;
+
+    let targets = graphics.render_targets();
+    println!(
+        "Rendering {FRAME_COUNT} frames at {}x{} with {}x MSAA",
+        targets.extent.width,
+        targets.extent.height,
+        targets.samples.as_raw()
+    );
+
+    // No input arrives, so the camera stays where the scene put it
+    let input = Input::new(Bindings::default());
+    let cubes = scene_file.scene.draw_list().cubes;
+    let start = Instant::now();
+    for _ in 0..FRAME_COUNT {
+        graphics.update(TICK_DURATION, &input, scene_file.camera);
+        graphics.draw(1., &cubes);
+    }
+    graphics.wait_idle();
+
+    let elapsed = start.elapsed();
+    println!(
+        "{:.2}ms per frame, {} draw calls each",
+        elapsed.as_secs_f64() * 1000. / FRAME_COUNT as f64,
+        graphics.draw_calls()
+    );
+    for scope in graphics.profiler().gpu_history() {
+        let indent = 2 * (scope.depth as usize + 1);
+        println!(
+            "{:indent$}{}: {:.3}ms",
+            "",
+            scope.name,
+            scope.average().as_secs_f64() * 1000.
+        );
+    }
+}
@@ -0,0 +1,99 @@
+//! The smallest windowed app built on the engine: it loads the default scene and flies the camera
+//! around it with the keyboard.
+//!
+//! Run with `cargo run --example minimal_window`, then move with WASD, Space and Left Control.
+
+use std::path::Path;
+
+use train::{
+    actions::Bindings,
+    assets::DEFAULT_ASSET_ROOT,
+    graphics::{Graphics, GraphicsSettings},
+    input::{Input, InputEvent},
+    scene_file::{SceneFile, DEFAULT_SCENE_PATH},
+    time::{Clock, TICK_DURATION},
+};
+use winit::{
+    application::ApplicationHandler,
+    event::WindowEvent,
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
+    window::{Window, WindowId},
+};
+
+struct App {
+    scene_file: SceneFile,
+    graphics: Option<Graphics>,
+    input: Input,
+    clock: Clock,
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        let window = event_loop
+            .create_window(Window::default_attributes().with_title("Minimal window"))
+            .unwrap();
+
+        let mut graphics = Graphics::new(window, &GraphicsSettings::default());
+        graphics.camera.set_pose(self.scene_file.camera);
+        graphics.lighting = self.scene_file.lighting;
+        self.graphics = Some(graphics);
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _: WindowId, event: WindowEvent) {
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::Resized(_) => {
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.resized();
+                }
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                if let Some(event) = InputEvent::from_keyboard_event(&event) {
+                    self.input.handle_event(event);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn about_to_wait(&mut self, _: &ActiveEventLoop) {
+        let App {
+            scene_file,
+            graphics: Some(graphics),
+            input,
+            clock,
+        } = self
+        else {
+            return;
+        };
+
+        // Nothing in the scene moves, so the camera's target is just where it started
+        let frame = clock.advance();
+        for _ in 0..frame.ticks {
+            graphics.update(TICK_DURATION, input, scene_file.camera);
+            input.end_tick();
+        }
+
+        graphics.draw(frame.alpha, &scene_file.scene.draw_list().cubes);
+        input.end_frame();
+    }
+}
+
+fn main() {
+    let scene_file = SceneFile::load(&Path::new(DEFAULT_ASSET_ROOT).join(DEFAULT_SCENE_PATH))
+        .unwrap_or_else(|e| {
+            eprintln!("Couldn't load scene: {e}");
+            std::process::exit(1);
+        });
+
+    let event_loop = EventLoop::new().unwrap();
+    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop
+        .run_app(&mut App {
+            scene_file,
+            graphics: None,
+            input: Input::new(Bindings::default()),
+            clock: Clock::default(),
+        })
+        .unwrap();
+}
@@ -0,0 +1,520 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use winit::{event::MouseButton, keyboard::KeyCode};
+
+use crate::input::Button;
+
+/// Something the player can do. Gameplay code asks [`crate::input::Input`] about these rather than
+/// about specific keys, so they can be rebound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    /// Held to grab the cursor and look around with the mouse.
+    Look,
+    /// Picks the object under the cursor.
+    Select,
+    ToggleGpuPicking,
+    FlyCamera,
+    OrbitCamera,
+    ChaseCamera,
+    CabCamera,
+    MapCamera,
+    ToggleProjection,
+    /// Starts or stops recording the camera's path.
+    RecordCameraPath,
+    PlayCameraPath,
+    CaptureTrace,
+    /// Shows or hides the developer overlay.
+    ToggleOverlay,
+    Throttle,
+    Brake,
+    /// Draws only the edges of the scene's faces.
+    ToggleWireframe,
+    TogglePause,
+    StepSimulation,
+    SlowDown,
+    SpeedUp,
+    CycleMsaa,
+    CycleTonemapper,
+    ToggleVsync,
+}
+
+/// A modifier key, which matches either the left or right key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+    Super,
+}
+
+impl Modifier {
+    fn keys(self) -> [KeyCode; 2] {
+        match self {
+            Modifier::Shift => [KeyCode::ShiftLeft, KeyCode::ShiftRight],
+            Modifier::Control => [KeyCode::ControlLeft, KeyCode::ControlRight],
+            Modifier::Alt => [KeyCode::AltLeft, KeyCode::AltRight],
+            Modifier::Super => [KeyCode::SuperLeft, KeyCode::SuperRight],
+        }
+    }
+}
+
+/// One part of a [`Binding`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Trigger {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Modifier(Modifier),
+}
+
+impl Trigger {
+    /// Is this trigger in `buttons`?
+    fn is_in(self, buttons: &HashSet<Button>) -> bool {
+        match self {
+            Trigger::Key(key_code) => buttons.contains(&Button::Key(key_code)),
+            Trigger::Mouse(button) => buttons.contains(&Button::Mouse(button)),
+            Trigger::Modifier(modifier) => modifier
+                .keys()
+                .into_iter()
+                .any(|key_code| buttons.contains(&Button::Key(key_code))),
+        }
+    }
+}
+
+/// A chord of triggers that must all be held at once, eg. `[Modifier(Control), Key(KeyS)]`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Binding(pub Vec<Trigger>);
+
+impl Binding {
+    fn is_held(&self, held: &HashSet<Button>) -> bool {
+        !self.0.is_empty() && self.0.iter().all(|trigger| trigger.is_in(held))
+    }
+
+    /// Does this binding include every trigger in `other`, and more besides?
+    fn is_superset_of(&self, other: &Binding) -> bool {
+        self.0.len() > other.0.len() && other.0.iter().all(|t| self.0.contains(t))
+    }
+
+    fn is_same_chord(&self, other: &Binding) -> bool {
+        self.0.len() == other.0.len() && other.0.iter().all(|t| self.0.contains(t))
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, trigger) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "+")?;
+            }
+            match trigger {
+                Trigger::Key(key_code) => write!(f, "{key_code:?}")?,
+                Trigger::Mouse(button) => write!(f, "{button:?}Mouse")?,
+                Trigger::Modifier(modifier) => write!(f, "{modifier:?}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maps each [`Action`] to any number of [`Binding`]s.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Bindings {
+    actions: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Trigger::Key;
+
+        let actions = [
+            (Action::MoveForward, vec![Binding(vec![Key(KeyCode::KeyW)])]),
+            (Action::MoveBack, vec![Binding(vec![Key(KeyCode::KeyS)])]),
+            (Action::MoveLeft, vec![Binding(vec![Key(KeyCode::KeyA)])]),
+            (Action::MoveRight, vec![Binding(vec![Key(KeyCode::KeyD)])]),
+            (Action::MoveUp, vec![Binding(vec![Key(KeyCode::Space)])]),
+            (
+                Action::MoveDown,
+                vec![Binding(vec![Key(KeyCode::ControlLeft)])],
+            ),
+            (
+                Action::Look,
+                vec![Binding(vec![Trigger::Mouse(MouseButton::Right)])],
+            ),
+            (
+                Action::Select,
+                vec![Binding(vec![Trigger::Mouse(MouseButton::Left)])],
+            ),
+            (
+                Action::ToggleGpuPicking,
+                vec![Binding(vec![Key(KeyCode::KeyG)])],
+            ),
+            (Action::FlyCamera, vec![Binding(vec![Key(KeyCode::Digit1)])]),
+            (
+                Action::OrbitCamera,
+                vec![Binding(vec![Key(KeyCode::Digit2)])],
+            ),
+            (
+                Action::ChaseCamera,
+                vec![Binding(vec![Key(KeyCode::Digit3)])],
+            ),
+            (Action::CabCamera, vec![Binding(vec![Key(KeyCode::Digit4)])]),
+            (Action::MapCamera, vec![Binding(vec![Key(KeyCode::Digit5)])]),
+            (
+                Action::ToggleProjection,
+                vec![Binding(vec![Key(KeyCode::KeyO)])],
+            ),
+            (
+                Action::RecordCameraPath,
+                vec![Binding(vec![Key(KeyCode::F5)])],
+            ),
+            (
+                Action::PlayCameraPath,
+                vec![Binding(vec![Key(KeyCode::F6)])],
+            ),
+            (Action::CaptureTrace, vec![Binding(vec![Key(KeyCode::F9)])]),
+            (Action::ToggleOverlay, vec![Binding(vec![Key(KeyCode::F3)])]),
+            (Action::Throttle, vec![Binding(vec![Key(KeyCode::ArrowUp)])]),
+            (Action::Brake, vec![Binding(vec![Key(KeyCode::ArrowDown)])]),
+            (
+                Action::ToggleWireframe,
+                vec![Binding(vec![Key(KeyCode::F1)])],
+            ),
+            (Action::TogglePause, vec![Binding(vec![Key(KeyCode::KeyP)])]),
+            (
+                Action::StepSimulation,
+                vec![Binding(vec![Key(KeyCode::Period)])],
+            ),
+            (
+                Action::SlowDown,
+                vec![Binding(vec![Key(KeyCode::BracketLeft)])],
+            ),
+            (
+                Action::SpeedUp,
+                vec![Binding(vec![Key(KeyCode::BracketRight)])],
+            ),
+            (Action::CycleMsaa, vec![Binding(vec![Key(KeyCode::KeyM)])]),
+            (
+                Action::CycleTonemapper,
+                vec![Binding(vec![Key(KeyCode::KeyT)])],
+            ),
+            (Action::ToggleVsync, vec![Binding(vec![Key(KeyCode::KeyV)])]),
+        ];
+
+        Self {
+            actions: actions.into_iter().collect(),
+        }
+    }
+}
+
+impl Bindings {
+    /// Loads bindings from a RON file mapping actions to lists of chords. Actions the file doesn't
+    /// mention keep their default bindings, and a missing file just gives the defaults.
+    pub fn load(path: &Path) -> Result<Self, BindingsError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(BindingsError::Io(path.to_owned(), e)),
+        };
+
+        Self::from_ron(&contents).map_err(|e| BindingsError::Parse(path.to_owned(), Box::new(e)))
+    }
+
+    /// Parses bindings in the format [`Bindings::load`] reads, on top of the defaults.
+    pub fn from_ron(contents: &str) -> Result<Self, ron::error::SpannedError> {
+        let overrides: BTreeMap<Action, Vec<Binding>> = ron::from_str(contents)?;
+        let mut bindings = Self::default();
+        bindings.actions.extend(overrides);
+
+        Ok(bindings)
+    }
+
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.actions.get(&action).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Is one of `action`'s bindings held? If `pressed` is given, one of that binding's triggers
+    /// must also be in it: ie. the chord was only just completed.
+    ///
+    /// A binding doesn't count while a longer chord that contains it is held, so if Control+P were
+    /// bound, holding Control and pressing P would trigger only that action, not the P one.
+    pub(crate) fn is_active(
+        &self,
+        action: Action,
+        held: &HashSet<Button>,
+        pressed: Option<&HashSet<Button>>,
+    ) -> bool {
+        self.bindings(action).iter().any(|binding| {
+            binding.is_held(held)
+                && pressed.is_none_or(|pressed| binding.0.iter().any(|t| t.is_in(pressed)))
+                && !self
+                    .actions
+                    .values()
+                    .flatten()
+                    .any(|other| other.is_superset_of(binding) && other.is_held(held))
+        })
+    }
+
+    /// Finds chords that are bound to more than one action.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts: Vec<Conflict> = Vec::new();
+
+        for (&action, bindings) in &self.actions {
+            for binding in bindings {
+                match conflicts
+                    .iter_mut()
+                    .find(|c| c.binding.is_same_chord(binding))
+                {
+                    Some(conflict) => {
+                        if !conflict.actions.contains(&action) {
+                            conflict.actions.push(action)
+                        }
+                    }
+                    None => conflicts.push(Conflict {
+                        binding: binding.clone(),
+                        actions: vec![action],
+                    }),
+                }
+            }
+        }
+
+        conflicts.retain(|c| c.actions.len() > 1);
+        conflicts
+    }
+}
+
+/// The same chord is bound to several actions, so pressing it triggers all of them.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    pub binding: Binding,
+    pub actions: Vec<Action>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is bound to {:?}", self.binding, self.actions)
+    }
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, Box<ron::error::SpannedError>),
+}
+
+impl fmt::Display for BindingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingsError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            BindingsError::Parse(path, e) => write!(f, "{}:{e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for BindingsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(keys: &[KeyCode]) -> HashSet<Button> {
+        keys.iter().map(|&key_code| Button::Key(key_code)).collect()
+    }
+
+    /// Bindings with only `actions` bound, so the defaults don't get in the way.
+    fn bindings(actions: &[(Action, Vec<Binding>)]) -> Bindings {
+        Bindings {
+            actions: actions.iter().cloned().collect(),
+        }
+    }
+
+    fn save_chord() -> Binding {
+        Binding(vec![
+            Trigger::Modifier(Modifier::Control),
+            Trigger::Key(KeyCode::KeyS),
+        ])
+    }
+
+    #[test]
+    fn chord_needs_every_trigger_held() {
+        let bindings = bindings(&[(Action::CaptureTrace, vec![save_chord()])]);
+
+        assert!(!bindings.is_active(Action::CaptureTrace, &held(&[KeyCode::KeyS]), None));
+        assert!(!bindings.is_active(Action::CaptureTrace, &held(&[KeyCode::ControlLeft]), None));
+        assert!(bindings.is_active(
+            Action::CaptureTrace,
+            &held(&[KeyCode::ControlLeft, KeyCode::KeyS]),
+            None
+        ));
+        // Either Control key will do
+        assert!(bindings.is_active(
+            Action::CaptureTrace,
+            &held(&[KeyCode::ControlRight, KeyCode::KeyS]),
+            None
+        ));
+    }
+
+    #[test]
+    fn chord_is_pressed_when_its_last_trigger_is() {
+        let bindings = bindings(&[(Action::CaptureTrace, vec![save_chord()])]);
+        let all = held(&[KeyCode::ControlLeft, KeyCode::KeyS]);
+
+        assert!(bindings.is_active(Action::CaptureTrace, &all, Some(&held(&[KeyCode::KeyS]))));
+        assert!(!bindings.is_active(Action::CaptureTrace, &all, Some(&HashSet::new())));
+    }
+
+    #[test]
+    fn any_binding_triggers_the_action() {
+        let bindings = bindings(&[(
+            Action::Select,
+            vec![
+                Binding(vec![Trigger::Mouse(MouseButton::Left)]),
+                Binding(vec![Trigger::Key(KeyCode::Enter)]),
+            ],
+        )]);
+
+        assert!(bindings.is_active(Action::Select, &held(&[KeyCode::Enter]), None));
+        let mouse = HashSet::from([Button::Mouse(MouseButton::Left)]);
+        assert!(bindings.is_active(Action::Select, &mouse, None));
+        assert!(!bindings.is_active(Action::Select, &HashSet::new(), None));
+    }
+
+    #[test]
+    fn empty_binding_is_never_held() {
+        let bindings = bindings(&[(Action::Select, vec![Binding(vec![])])]);
+        assert!(!bindings.is_active(Action::Select, &held(&[KeyCode::KeyA]), None));
+    }
+
+    #[test]
+    fn longer_chord_suppresses_the_one_it_contains() {
+        let bindings = bindings(&[
+            (
+                Action::MoveBack,
+                vec![Binding(vec![Trigger::Key(KeyCode::KeyS)])],
+            ),
+            (Action::CaptureTrace, vec![save_chord()]),
+        ]);
+
+        let s = held(&[KeyCode::KeyS]);
+        assert!(bindings.is_active(Action::MoveBack, &s, None));
+        assert!(!bindings.is_active(Action::CaptureTrace, &s, None));
+
+        let control_s = held(&[KeyCode::ControlLeft, KeyCode::KeyS]);
+        assert!(!bindings.is_active(Action::MoveBack, &control_s, None));
+        assert!(bindings.is_active(Action::CaptureTrace, &control_s, None));
+    }
+
+    #[test]
+    fn conflicts_are_the_chords_bound_more_than_once() {
+        let bindings = bindings(&[
+            (
+                Action::MoveBack,
+                vec![Binding(vec![Trigger::Key(KeyCode::KeyS)])],
+            ),
+            (
+                Action::Brake,
+                vec![Binding(vec![Trigger::Key(KeyCode::KeyS)])],
+            ),
+            (Action::CaptureTrace, vec![save_chord()]),
+            // The same chord in a different order
+            (
+                Action::ToggleOverlay,
+                vec![Binding(vec![
+                    Trigger::Key(KeyCode::KeyS),
+                    Trigger::Modifier(Modifier::Control),
+                ])],
+            ),
+            (
+                Action::Select,
+                vec![Binding(vec![Trigger::Key(KeyCode::Enter)])],
+            ),
+        ]);
+
+        let conflicts = bindings.conflicts();
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(
+            conflicts[0].binding,
+            Binding(vec![Trigger::Key(KeyCode::KeyS)])
+        );
+        assert_eq!(conflicts[0].actions, [Action::MoveBack, Action::Brake]);
+        assert_eq!(conflicts[1].binding, save_chord());
+        assert_eq!(
+            conflicts[1].actions,
+            [Action::CaptureTrace, Action::ToggleOverlay]
+        );
+    }
+
+    #[test]
+    fn an_action_bound_twice_to_one_chord_isnt_a_conflict() {
+        let key = Binding(vec![Trigger::Key(KeyCode::KeyS)]);
+        let bindings = bindings(&[(Action::MoveBack, vec![key.clone(), key])]);
+        assert!(bindings.conflicts().is_empty());
+    }
+
+    #[test]
+    fn default_bindings_dont_conflict() {
+        assert!(Bindings::default().conflicts().is_empty());
+    }
+
+    #[test]
+    fn bindings_file_overrides_only_the_actions_it_mentions() {
+        let bindings =
+            Bindings::from_ron("{ MoveForward: [[Key(ArrowUp)], [Modifier(Shift), Key(KeyW)]] }")
+                .unwrap();
+
+        assert_eq!(
+            bindings.bindings(Action::MoveForward),
+            [
+                Binding(vec![Trigger::Key(KeyCode::ArrowUp)]),
+                Binding(vec![
+                    Trigger::Modifier(Modifier::Shift),
+                    Trigger::Key(KeyCode::KeyW)
+                ]),
+            ]
+        );
+        assert_eq!(
+            bindings.bindings(Action::MoveBack),
+            [Binding(vec![Trigger::Key(KeyCode::KeyS)])]
+        );
+    }
+
+    #[test]
+    fn bindings_file_can_unbind_an_action() {
+        let bindings = Bindings::from_ron("{ ToggleVsync: [] }").unwrap();
+        assert!(bindings.bindings(Action::ToggleVsync).is_empty());
+    }
+
+    #[test]
+    fn bindings_file_errors_name_the_file_and_line() {
+        assert!(Bindings::from_ron("{ NotAnAction: [] }").is_err());
+
+        let path = std::env::temp_dir().join(format!("train-bindings-{}.ron", std::process::id()));
+        std::fs::write(&path, "{\n    MoveForward: [[Key(NotAKey)]],\n}").unwrap();
+        let error = Bindings::load(&path).unwrap_err().to_string();
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            error.starts_with(&format!("{}:2:", path.display())),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn a_missing_bindings_file_gives_the_defaults() {
+        let bindings = Bindings::load(Path::new("/nonexistent/bindings.ron")).unwrap();
+        assert_eq!(
+            bindings.bindings(Action::MoveBack),
+            Bindings::default().bindings(Action::MoveBack)
+        );
+    }
+}
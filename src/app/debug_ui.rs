@@ -0,0 +1,197 @@
+use std::{collections::VecDeque, time::Instant};
+
+use glam::EulerRot;
+
+use crate::graphics::{Graphics, Profiler, Projection, Tonemapper};
+
+/// How many frame times the graph shows.
+const FRAME_HISTORY_LENGTH: usize = 240;
+/// The frame time at the top of the graph, unless a frame took longer, in milliseconds.
+const GRAPH_MIN_SCALE_MS: f32 = 1000. / 30.;
+const GRAPH_HEIGHT: f32 = 60.;
+const MSAA_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// The developer overlay's contents: performance, the camera, and render settings.
+#[derive(Debug)]
+pub struct DebugUi {
+    /// How long each recent frame took, in milliseconds, oldest first.
+    frame_times: VecDeque<f32>,
+    last_frame: Option<Instant>,
+}
+
+impl Default for DebugUi {
+    fn default() -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(FRAME_HISTORY_LENGTH),
+            last_frame: None,
+        }
+    }
+}
+
+impl DebugUi {
+    /// Builds this frame's UI. Call once per frame, whether or not the overlay is visible, so the
+    /// frame time graph is up to date when it's shown.
+    pub fn show(&mut self, graphics: &mut Graphics) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            if self.frame_times.len() == FRAME_HISTORY_LENGTH {
+                self.frame_times.pop_front();
+            }
+            self.frame_times
+                .push_back((now - last_frame).as_secs_f32() * 1000.);
+        }
+
+        let Some(ctx) = graphics.begin_overlay() else {
+            return;
+        };
+
+        egui::Window::new("Developer")
+            .default_pos([10., 10.])
+            .show(&ctx, |ui| {
+                egui::CollapsingHeader::new("Performance")
+                    .default_open(true)
+                    .show(ui, |ui| self.performance(ui, graphics));
+                egui::CollapsingHeader::new("Camera")
+                    .default_open(true)
+                    .show(ui, |ui| camera(ui, graphics));
+                egui::CollapsingHeader::new("Rendering")
+                    .default_open(true)
+                    .show(ui, |ui| rendering(ui, graphics));
+            });
+
+        graphics.end_overlay();
+    }
+
+    fn performance(&self, ui: &mut egui::Ui, graphics: &Graphics) {
+        let average_ms = match self.frame_times.len() {
+            0 => 0.,
+            len => self.frame_times.iter().sum::<f32>() / len as f32,
+        };
+        if average_ms > 0. {
+            ui.label(format!("{:.0} FPS ({average_ms:.2}ms)", 1000. / average_ms));
+        }
+        ui.label(format!("{} draw calls", graphics.draw_calls()));
+        self.frame_time_graph(ui);
+        profile(ui, graphics.profiler());
+    }
+
+    /// Plots recent frame times, newest on the right.
+    fn frame_time_graph(&self, ui: &mut egui::Ui) {
+        let size = egui::vec2(ui.available_width(), GRAPH_HEIGHT);
+        let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 2., ui.visuals().extreme_bg_color);
+
+        let scale_ms = self
+            .frame_times
+            .iter()
+            .copied()
+            .fold(GRAPH_MIN_SCALE_MS, f32::max);
+        let step = rect.width() / (FRAME_HISTORY_LENGTH - 1) as f32;
+        let start = FRAME_HISTORY_LENGTH - self.frame_times.len();
+        let points = self
+            .frame_times
+            .iter()
+            .enumerate()
+            .map(|(i, &ms)| {
+                egui::pos2(
+                    rect.left() + (start + i) as f32 * step,
+                    rect.bottom() - ms / scale_ms * rect.height(),
+                )
+            })
+            .collect();
+
+        painter.add(egui::Shape::line(
+            points,
+            egui::Stroke::new(1., ui.visuals().text_color()),
+        ));
+        painter.text(
+            rect.left_top() + egui::vec2(4., 2.),
+            egui::Align2::LEFT_TOP,
+            format!("{scale_ms:.1}ms"),
+            egui::FontId::monospace(10.),
+            ui.visuals().weak_text_color(),
+        );
+    }
+}
+
+/// Lists how long each profiler scope has taken recently, on average.
+fn profile(ui: &mut egui::Ui, profiler: &Profiler) {
+    for (kind, history) in [
+        ("CPU", profiler.cpu_history()),
+        ("GPU", profiler.gpu_history()),
+    ] {
+        ui.label(kind);
+        for scope in history {
+            let indent = "  ".repeat(scope.depth as usize + 1);
+            ui.monospace(format!(
+                "{indent}{}: {:.3}ms",
+                scope.name,
+                scope.average().as_secs_f64() * 1000.
+            ));
+        }
+    }
+}
+
+fn camera(ui: &mut egui::Ui, graphics: &mut Graphics) {
+    let camera = &mut graphics.camera;
+    let position = camera.position();
+    let (yaw, pitch, roll) = camera.rotation().to_euler(EulerRot::YXZ);
+    ui.label(format!(
+        "Position: {:.2}, {:.2}, {:.2}",
+        position.x, position.y, position.z
+    ));
+    ui.label(format!(
+        "Yaw {:.1}°, pitch {:.1}°, roll {:.1}°",
+        yaw.to_degrees(),
+        pitch.to_degrees(),
+        roll.to_degrees()
+    ));
+
+    let settings = &mut camera.controller_settings;
+    ui.add(egui::Slider::new(&mut settings.movement_speed, 0.5..=100.).text("Movement speed"));
+    ui.add(egui::Slider::new(&mut settings.look_speed, 0.05..=2.).text("Look speed"));
+
+    let mut orthographic = matches!(camera.projection, Projection::Orthographic { .. });
+    if ui.checkbox(&mut orthographic, "Orthographic").changed() {
+        camera.toggle_projection();
+    }
+}
+
+fn rendering(ui: &mut egui::Ui, graphics: &mut Graphics) {
+    let mut samples = graphics.msaa_samples();
+    egui::ComboBox::from_label("MSAA")
+        .selected_text(format!("{samples}x"))
+        .show_ui(ui, |ui| {
+            for count in MSAA_SAMPLE_COUNTS {
+                ui.selectable_value(&mut samples, count, format!("{count}x"));
+            }
+        });
+    if samples != graphics.msaa_samples() {
+        graphics.set_msaa_samples(samples);
+    }
+
+    let post_process = &mut graphics.post_process;
+    egui::ComboBox::from_label("Tonemapper")
+        .selected_text(format!("{:?}", post_process.tonemapper))
+        .show_ui(ui, |ui| {
+            for tonemapper in Tonemapper::ALL {
+                ui.selectable_value(
+                    &mut post_process.tonemapper,
+                    tonemapper,
+                    format!("{tonemapper:?}"),
+                );
+            }
+        });
+    ui.add(
+        egui::Slider::new(&mut post_process.exposure, 0.05..=8.)
+            .logarithmic(true)
+            .text("Exposure"),
+    );
+
+    let mut settings = *graphics.display_settings();
+    ui.checkbox(&mut settings.vsync, "VSync");
+    if settings != *graphics.display_settings() {
+        graphics.set_display_settings(settings);
+    }
+}
@@ -0,0 +1,42 @@
+use glam::{Vec2, Vec4};
+
+use crate::{
+    graphics::{Graphics, Placement},
+    locomotive::Locomotive,
+    scene::{EntityId, Scene},
+};
+
+/// The height of the HUD's text, and its distance from the edge of the window, in pixels.
+const TEXT_SIZE: f32 = 32.;
+const MARGIN: f32 = 16.;
+/// The height of the locomotive's label, and how far above the locomotive it floats, in metres.
+const LABEL_SIZE: f32 = 1.5;
+const LABEL_GAP: f32 = 1.5;
+
+/// Draws a speedometer in the bottom left corner of the window, and labels the locomotive, which
+/// is `locomotive_entity` in `scene`.
+pub fn draw(
+    graphics: &mut Graphics,
+    locomotive: &Locomotive,
+    scene: &Scene,
+    locomotive_entity: EntityId,
+) {
+    let height = graphics.camera.extent.height as f32;
+    let km_per_hour = locomotive.speed() * 3.6;
+    graphics.draw_text(
+        format!("{km_per_hour:.0} km/h"),
+        Placement::Screen(Vec2::new(MARGIN, height - MARGIN - TEXT_SIZE)),
+        TEXT_SIZE,
+        Vec4::ONE,
+    );
+
+    if let Some(bounds) = scene.world_bounds(locomotive_entity) {
+        let centre = (bounds.min + bounds.max) / 2.;
+        graphics.draw_text(
+            scene.entity(locomotive_entity).name.clone(),
+            Placement::World(centre.with_y(bounds.max.y + LABEL_GAP)),
+            LABEL_SIZE,
+            Vec4::ONE,
+        );
+    }
+}
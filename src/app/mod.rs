@@ -0,0 +1,336 @@
+//! The game itself, as the `train` binary runs it, on top of the rest of the engine.
+
+mod debug_ui;
+mod hud;
+mod selection;
+
+use std::path::{Path, PathBuf};
+
+use debug_ui::DebugUi;
+use selection::Selection;
+use tracing::{debug_span, error, info, info_span};
+use winit::{
+    application::ApplicationHandler,
+    dpi::PhysicalSize,
+    event::{DeviceEvent, ElementState, MouseScrollDelta, WindowEvent},
+    window::{Fullscreen, Window},
+};
+
+use crate::{
+    actions::{Action, Bindings},
+    config::Config,
+    graphics::{Camera, Graphics, Lighting, Pose, Profiler},
+    input::{Button, InputEvent},
+    logging::SpanCapture,
+    replay::Replay,
+    scene::{EntityId, Scene},
+    scene_file::SceneFile,
+    simulation::Simulation,
+};
+
+/// Where captured traces are saved.
+const TRACE_PATH: &str = "trace.json";
+/// Roughly how many pixels a touchpad has to scroll to count as one line of a mouse wheel.
+const PIXELS_PER_LINE: f32 = 20.;
+
+/// The game, run by a winit event loop: a window showing the scene, driven by the simulation.
+pub struct App {
+    config: Config,
+    /// Records spans into the profiler's trace while one's being captured.
+    span_capture: SpanCapture,
+    graphics: Option<Graphics>,
+    pub simulation: Simulation,
+    scene: Scene,
+    /// The locomotive's entity in `scene`.
+    locomotive_entity: EntityId,
+    /// Where the camera starts and how the scene is lit, from the scene file. They're applied
+    /// once the window is open.
+    camera_start: Pose,
+    lighting: Lighting,
+    /// Where to save the input recorded this session, if it's being recorded.
+    record_path: Option<PathBuf>,
+    /// Whether the cursor is grabbed for mouse look.
+    cursor_grabbed: bool,
+    selection: Selection,
+    debug_ui: DebugUi,
+}
+
+impl App {
+    /// Records input to `record_path` if there is one, or plays back `replay`. The window opens
+    /// once the event loop resumes.
+    pub fn new(
+        config: Config,
+        span_capture: SpanCapture,
+        scene_file: SceneFile,
+        bindings: Bindings,
+        record_path: Option<PathBuf>,
+        replay: Option<Replay>,
+    ) -> Self {
+        let mut simulation = Simulation::new(bindings, replay);
+        if record_path.is_some() {
+            simulation.start_recording();
+        }
+        let SceneFile {
+            mut scene,
+            camera,
+            lighting,
+        } = scene_file;
+        let locomotive_entity = simulation.locomotive.spawn(&mut scene);
+
+        Self {
+            config,
+            span_capture,
+            graphics: None,
+            simulation,
+            scene,
+            locomotive_entity,
+            camera_start: camera,
+            lighting,
+            record_path,
+            cursor_grabbed: false,
+            selection: Selection::default(),
+            debug_ui: DebugUi::default(),
+        }
+    }
+
+    /// Handles actions that control the app itself rather than the game. These respond as soon as
+    /// they're pressed, even while the simulation is paused. The clock and camera actions are
+    /// handled by the [`Simulation`], so replays see them too.
+    fn handle_app_actions(&mut self) {
+        let input = &self.simulation.input;
+
+        if input.was_pressed_this_frame(Action::ToggleGpuPicking) {
+            let selection = &mut self.selection;
+            selection.gpu_picking = !selection.gpu_picking;
+            info!(gpu_picking = selection.gpu_picking, "Toggled GPU picking");
+        }
+
+        let Some(graphics) = &mut self.graphics else {
+            return;
+        };
+
+        let looking = input.is_held(Action::Look);
+        if looking != self.cursor_grabbed {
+            graphics.set_cursor_grabbed(looking);
+            self.cursor_grabbed = looking;
+        }
+
+        if input.was_pressed_this_frame(Action::CaptureTrace) {
+            let profiler = graphics.profiler_mut();
+            if let Some(mut trace) = profiler.stop_trace() {
+                for span in self.span_capture.stop() {
+                    trace.push_span(span.name, span.start, span.duration);
+                }
+                match trace.save(Path::new(TRACE_PATH)) {
+                    Ok(()) => info!("Saved trace to {TRACE_PATH}"),
+                    Err(e) => error!("Couldn't save trace to {TRACE_PATH}: {e}"),
+                }
+                log_profile(graphics.profiler());
+            } else {
+                profiler.start_trace();
+                self.span_capture.start();
+                info!("Capturing trace");
+            }
+        }
+        if input.was_pressed_this_frame(Action::ToggleOverlay) {
+            graphics.toggle_overlay();
+        }
+        if input.was_pressed_this_frame(Action::ToggleWireframe) {
+            graphics.toggle_wireframe();
+        }
+        if input.was_pressed_this_frame(Action::CycleMsaa) {
+            graphics.cycle_msaa_samples();
+        }
+        if input.was_pressed_this_frame(Action::CycleTonemapper) {
+            graphics.post_process.tonemapper = graphics.post_process.tonemapper.next();
+        }
+        if input.was_pressed_this_frame(Action::ToggleVsync) {
+            let mut settings = *graphics.display_settings();
+            settings.vsync = !settings.vsync;
+            graphics.set_display_settings(settings);
+        }
+    }
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let attributes = Window::default_attributes()
+            .with_inner_size(PhysicalSize::new(self.config.width, self.config.height))
+            .with_fullscreen(
+                self.config
+                    .fullscreen
+                    .then_some(Fullscreen::Borderless(None)),
+            );
+        let _span = info_span!("startup").entered();
+        let window = event_loop.create_window(attributes).unwrap();
+        let mut graphics = Graphics::new(window, &self.config.graphics_settings());
+        graphics.camera.set_pose(self.camera_start);
+        graphics.lighting = self.lighting;
+        self.graphics = Some(graphics);
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &winit::event_loop::ActiveEventLoop,
+        _window_id: winit::window::WindowId,
+        event: winit::event::WindowEvent,
+    ) {
+        // The overlay sees events first, so typing into it doesn't also move the camera
+        let consumed = self
+            .graphics
+            .as_mut()
+            .is_some_and(|graphics| graphics.handle_overlay_event(&event));
+
+        let input_event = match event {
+            WindowEvent::CloseRequested => {
+                event_loop.exit();
+                None
+            }
+            WindowEvent::Resized(_) => {
+                if let Some(graphics) = &mut self.graphics {
+                    graphics.resized();
+                }
+                None
+            }
+            WindowEvent::Focused(false) => Some(InputEvent::ReleaseAll),
+            WindowEvent::MouseInput { state, button, .. } => Some(InputEvent::Button {
+                button: Button::Mouse(button),
+                state,
+            }),
+            WindowEvent::CursorMoved { position, .. } => Some(InputEvent::CursorMoved {
+                x: position.x,
+                y: position.y,
+            }),
+            WindowEvent::CursorLeft { .. } => Some(InputEvent::CursorLeft),
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                Some(InputEvent::Scroll { lines })
+            }
+            WindowEvent::KeyboardInput { event, .. } => InputEvent::from_keyboard_event(&event),
+            _ => None,
+        };
+
+        if let Some(input_event) = input_event.filter(|event| !consumed || passes_overlay(event)) {
+            self.simulation.handle_input(input_event);
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        _: &winit::event_loop::ActiveEventLoop,
+        _: winit::event::DeviceId,
+        event: winit::event::DeviceEvent,
+    ) {
+        // A locked cursor doesn't move, so looking around has to use raw motion rather than
+        // `WindowEvent::CursorMoved`. It's ignored unless the cursor is grabbed, since it keeps
+        // arriving while other windows are in use.
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event {
+            if self.cursor_grabbed {
+                self.simulation
+                    .handle_input(InputEvent::MouseMotion { x, y })
+            }
+        }
+    }
+
+    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        if self.graphics.is_none() {
+            return;
+        }
+
+        let _span = debug_span!("frame", index = self.simulation.clock.frame_count()).entered();
+        let graphics = self.graphics.as_mut().unwrap();
+        let was_replaying = self.simulation.is_replaying();
+        let scope = graphics.profiler_mut().begin_cpu("simulate");
+        let frame = self.simulation.run_frame(&mut graphics.camera, None);
+        graphics.profiler_mut().end_cpu(scope);
+
+        if was_replaying && !self.simulation.is_replaying() {
+            graphics.camera.interpolate(1.);
+            print_camera(&graphics.camera);
+        }
+        self.handle_app_actions();
+
+        self.simulation
+            .locomotive
+            .place(&mut self.scene, self.locomotive_entity, frame.alpha);
+        let mut draw_list = self.scene.draw_list();
+        let graphics = self.graphics.as_mut().unwrap();
+        self.selection
+            .update(graphics, &self.simulation.input, &mut draw_list);
+
+        hud::draw(
+            graphics,
+            &self.simulation.locomotive,
+            &self.scene,
+            self.locomotive_entity,
+        );
+        self.debug_ui.show(graphics);
+        graphics.draw(frame.alpha, &draw_list.cubes);
+        self.simulation.end_frame();
+    }
+
+    fn exiting(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        let (Some(path), Some(recording)) = (&self.record_path, self.simulation.take_recording())
+        else {
+            return;
+        };
+
+        match recording.save(path) {
+            Ok(()) => info!(
+                "Recorded {} ticks to {}",
+                recording.tick_count,
+                path.display()
+            ),
+            Err(e) => error!("Couldn't save recording to {}: {e}", path.display()),
+        }
+
+        if let Some(graphics) = &mut self.graphics {
+            graphics.camera.interpolate(1.);
+            print_camera(&graphics.camera);
+        }
+    }
+}
+
+/// Whether `event` should reach the game even when the overlay has used it. Releases always get
+/// through, so nothing stays held when the overlay takes over, and so does the cursor position.
+fn passes_overlay(event: &InputEvent) -> bool {
+    matches!(
+        event,
+        InputEvent::Button {
+            state: ElementState::Released,
+            ..
+        } | InputEvent::ReleaseAll
+            | InputEvent::CursorMoved { .. }
+            | InputEvent::CursorLeft
+    )
+}
+
+/// Prints the camera's transform, so a replay can be checked against the original session.
+pub fn print_camera(camera: &Camera) {
+    println!(
+        "Camera at {:?}, rotation {:?}",
+        camera.position(),
+        camera.rotation()
+    );
+}
+
+/// Logs the average time of each profiled scope, indented to show how they nest.
+pub fn log_profile(profiler: &Profiler) {
+    for (kind, history) in [
+        ("CPU", profiler.cpu_history()),
+        ("GPU", profiler.gpu_history()),
+    ] {
+        for scope in history {
+            let indent = 2 * scope.depth as usize;
+            info!(
+                "{kind} {:indent$}{}: {:.3}ms",
+                "",
+                scope.name,
+                scope.average().as_secs_f64() * 1000.
+            );
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use tracing::debug;
+
+use crate::{
+    actions::Action,
+    graphics::{self, Graphics, Hit},
+    input::Input,
+    scene::{DrawList, EntityId},
+};
+
+/// Which entity the player has clicked on, if any.
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub selected: Option<EntityId>,
+    /// Whether to select with the GPU ID buffer rather than by casting rays.
+    pub gpu_picking: bool,
+}
+
+impl Selection {
+    /// Picks whatever's under the cursor when the select action is pressed, and highlights the
+    /// selected entity.
+    pub fn update(&mut self, graphics: &mut Graphics, input: &Input, draw_list: &mut DrawList) {
+        if let Some(cursor) = input
+            .cursor_position
+            .filter(|_| input.was_pressed_this_frame(Action::Select))
+        {
+            let ray = graphics.camera.ray_through_pixel(cursor);
+            let hit = graphics::pick(&ray, &draw_list.cubes);
+            debug!(?hit, "Picked with a ray");
+
+            if self.gpu_picking {
+                graphics.request_gpu_pick(cursor.as_uvec2());
+            } else {
+                self.selected = match hit {
+                    Some(Hit::Object { index, .. }) => Some(draw_list.entities[index]),
+                    _ => None,
+                };
+            }
+        }
+
+        // The scene doesn't change order, so the index is still valid a frame or two later
+        if let Some(picked) = graphics.take_gpu_pick() {
+            debug!(?picked, "Picked with the GPU");
+            self.selected = picked.and_then(|index| draw_list.entities.get(index).copied());
+        }
+
+        if let Some(index) = self.selected.and_then(|entity| draw_list.index_of(entity)) {
+            let cube = &mut draw_list.cubes[index];
+            cube.colour = cube.colour.lerp(glam::Vec4::ONE, 0.5);
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use tracing::{debug, info, warn};
+
+/// Where assets are loaded from unless another root is configured.
+pub const DEFAULT_ASSET_ROOT: &str = "assets";
+
+/// How often files are checked for changes when hot reloading.
+const HOT_RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+/// Loading is mostly waiting on the disk, so more threads than this don't help.
+const MAX_WORKERS: usize = 4;
+
+/// Something that can be loaded from a file by an [`AssetServer`].
+pub trait Asset: Sized + Send + Sync + 'static {
+    /// Turns a file's contents into the asset, or explains why they can't be. This runs on a
+    /// worker thread, so it's the place for slow work like parsing and decoding.
+    fn decode(bytes: Vec<u8>) -> Result<Self, String>;
+}
+
+/// Refers to an asset of type `T` loaded by an [`AssetServer`]. Loading the same file as the same
+/// type again returns the same handle.
+pub struct Handle<T> {
+    index: usize,
+    asset: PhantomData<fn() -> T>,
+}
+
+// Derives would only implement these where `T` does
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Handle").field(&self.index).finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    /// On a worker thread, waiting to be read or decoded.
+    Loading,
+    Loaded,
+    /// Why the file couldn't be read or decoded. If it changes while hot reloading, it's tried
+    /// again.
+    Failed(String),
+}
+
+type AnyAsset = Arc<dyn Any + Send + Sync>;
+type Decode = fn(Vec<u8>) -> Result<AnyAsset, String>;
+
+fn decode<T: Asset>(bytes: Vec<u8>) -> Result<AnyAsset, String> {
+    T::decode(bytes).map(|asset| Arc::new(asset) as AnyAsset)
+}
+
+struct Slot {
+    /// Relative to the root.
+    path: PathBuf,
+    decode: Decode,
+    state: LoadState,
+    /// The latest version that loaded. It's kept if a reload fails.
+    asset: Option<AnyAsset>,
+    /// The number of times the asset has loaded.
+    version: u32,
+    /// When the file was last modified, as of the latest attempt to load it.
+    modified: Option<SystemTime>,
+    /// Set while a reload is queued, so it isn't queued twice.
+    reloading: bool,
+}
+
+struct Job {
+    index: usize,
+    path: PathBuf,
+    decode: Decode,
+}
+
+struct Finished {
+    index: usize,
+    modified: Option<SystemTime>,
+    result: Result<AnyAsset, String>,
+}
+
+/// Loads assets from files under a root directory. Files are read and decoded on worker threads,
+/// and each is only loaded once however many times it's asked for. Whatever's made from the
+/// results, such as GPU resources, is made on the thread that owns the server, which picks up
+/// finished loads in [`AssetServer::update`].
+pub struct AssetServer {
+    root: PathBuf,
+    slots: Vec<Slot>,
+    indices: HashMap<(PathBuf, TypeId), usize>,
+    /// Taken when the server is dropped, which stops the workers.
+    jobs: Option<mpsc::Sender<Job>>,
+    finished: mpsc::Receiver<Finished>,
+    workers: Vec<thread::JoinHandle<()>>,
+    hot_reload: bool,
+    last_checked: Instant,
+    /// Paths of assets replaced by a reload since [`AssetServer::update`] last returned them,
+    /// including any picked up while waiting.
+    reloaded: Vec<PathBuf>,
+}
+
+impl AssetServer {
+    /// Loads assets from under `root`. With `hot_reload`, [`AssetServer::update`] loads files
+    /// again when they change.
+    pub fn new(root: impl Into<PathBuf>, hot_reload: bool) -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let (finished_sender, finished) = mpsc::channel();
+
+        let worker_count = thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(MAX_WORKERS);
+        let workers = (0..worker_count)
+            .map(|i| {
+                let jobs = job_receiver.clone();
+                let finished = finished_sender.clone();
+                thread::Builder::new()
+                    .name(format!("asset worker {i}"))
+                    .spawn(move || work(&jobs, &finished))
+                    .unwrap()
+            })
+            .collect();
+
+        Self {
+            root: root.into(),
+            slots: Vec::new(),
+            indices: HashMap::new(),
+            jobs: Some(jobs),
+            finished,
+            workers,
+            hot_reload,
+            last_checked: Instant::now(),
+            reloaded: Vec::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Starts loading the file at `path`, relative to the root, unless it's already been loaded
+    /// as a `T`. Returns straight away.
+    pub fn load<T: Asset>(&mut self, path: impl AsRef<Path>) -> Handle<T> {
+        let key = (path.as_ref().to_owned(), TypeId::of::<T>());
+        let index = match self.indices.get(&key) {
+            Some(&index) => index,
+            None => {
+                let index = self.slots.len();
+                self.slots.push(Slot {
+                    path: key.0.clone(),
+                    decode: decode::<T>,
+                    state: LoadState::Loading,
+                    asset: None,
+                    version: 0,
+                    modified: None,
+                    reloading: false,
+                });
+                self.indices.insert(key, index);
+                self.queue(index);
+                index
+            }
+        };
+
+        Handle {
+            index,
+            asset: PhantomData,
+        }
+    }
+
+    pub fn state<T>(&self, handle: Handle<T>) -> &LoadState {
+        &self.slots[handle.index].state
+    }
+
+    /// Returns the latest version of the asset, if it's loaded.
+    pub fn get<T: Asset>(&self, handle: Handle<T>) -> Option<Arc<T>> {
+        let asset = self.slots[handle.index].asset.clone()?;
+        Some(asset.downcast().expect("Handles match their asset's type"))
+    }
+
+    /// The number of times the asset has loaded, including reloads. Anything made from it should
+    /// be made again when this changes.
+    pub fn version<T>(&self, handle: Handle<T>) -> u32 {
+        self.slots[handle.index].version
+    }
+
+    /// Blocks until the asset has loaded, picking up any other loads that finish in the meantime.
+    pub fn wait<T: Asset>(&mut self, handle: Handle<T>) -> Result<Arc<T>, AssetError> {
+        loop {
+            let slot = &self.slots[handle.index];
+            match &slot.state {
+                LoadState::Loaded => return Ok(self.get(handle).unwrap()),
+                LoadState::Failed(message) => {
+                    return Err(AssetError {
+                        path: self.root.join(&slot.path),
+                        message: message.clone(),
+                    })
+                }
+                LoadState::Loading => {
+                    let finished = self
+                        .finished
+                        .recv()
+                        .expect("Workers run until the server is dropped");
+                    self.finish(finished);
+                }
+            }
+        }
+    }
+
+    /// Picks up loads that have finished, and with hot reloading, starts loading any files that
+    /// have changed again. Call this regularly on the thread that uses the assets. Returns the
+    /// paths of the assets that have been replaced by a reload since the last call.
+    pub fn update(&mut self) -> Vec<PathBuf> {
+        while let Ok(finished) = self.finished.try_recv() {
+            self.finish(finished);
+        }
+
+        if self.hot_reload && self.last_checked.elapsed() >= HOT_RELOAD_INTERVAL {
+            self.last_checked = Instant::now();
+            for index in 0..self.slots.len() {
+                let slot = &self.slots[index];
+                if slot.state == LoadState::Loading || slot.reloading {
+                    continue;
+                }
+
+                if modified(&self.root.join(&slot.path)) != slot.modified {
+                    info!("{} changed, reloading it", slot.path.display());
+                    self.slots[index].reloading = true;
+                    self.queue(index);
+                }
+            }
+        }
+
+        std::mem::take(&mut self.reloaded)
+    }
+
+    fn queue(&self, index: usize) {
+        let slot = &self.slots[index];
+        debug!("Loading {}", slot.path.display());
+
+        let job = Job {
+            index,
+            path: self.root.join(&slot.path),
+            decode: slot.decode,
+        };
+        self.jobs
+            .as_ref()
+            .expect("Jobs are only taken when the server is dropped")
+            .send(job)
+            .expect("Workers run until the server is dropped");
+    }
+
+    /// Stores a finished load, noting the asset's path for [`AssetServer::update`] if it replaced
+    /// an earlier version.
+    fn finish(&mut self, finished: Finished) {
+        let slot = &mut self.slots[finished.index];
+        slot.reloading = false;
+        slot.modified = finished.modified;
+
+        match finished.result {
+            Ok(asset) => {
+                debug!("Loaded {}", slot.path.display());
+                slot.asset = Some(asset);
+                slot.state = LoadState::Loaded;
+                slot.version += 1;
+                if slot.version > 1 {
+                    self.reloaded.push(slot.path.clone());
+                }
+            }
+            // Carry on with the version that worked until the file's fixed
+            Err(message) if slot.asset.is_some() => {
+                warn!(
+                    "Couldn't reload {}, so keeping the last version: {message}",
+                    slot.path.display()
+                );
+            }
+            Err(message) => {
+                warn!("Couldn't load {}: {message}", slot.path.display());
+                slot.state = LoadState::Failed(message);
+            }
+        }
+    }
+}
+
+impl Drop for AssetServer {
+    fn drop(&mut self) {
+        // Closing the channel lets each worker finish its current job and stop
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// Loads jobs until the server is dropped.
+fn work(jobs: &Mutex<mpsc::Receiver<Job>>, finished: &mpsc::Sender<Finished>) {
+    loop {
+        // The lock is only held while waiting for a job, not while loading it
+        let Ok(job) = jobs.lock().unwrap().recv() else {
+            return;
+        };
+
+        // Read before the contents, so a change made while loading is seen by the next check
+        let modified = modified(&job.path);
+        let result = std::fs::read(&job.path)
+            .map_err(|e| e.to_string())
+            .and_then(job.decode);
+
+        let finished_job = Finished {
+            index: job.index,
+            modified,
+            result,
+        };
+        if finished.send(finished_job).is_err() {
+            return;
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// An asset that couldn't be read or decoded.
+#[derive(Debug)]
+pub struct AssetError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for AssetError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Text(String);
+
+    impl Asset for Text {
+        fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+            String::from_utf8(bytes)
+                .map(Text)
+                .map_err(|e| e.to_string())
+        }
+    }
+
+    /// Another type to load the same files as, which takes long enough to decode that loads
+    /// queued before it are sure to finish first.
+    struct Length(usize);
+
+    impl Asset for Length {
+        fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+            thread::sleep(Duration::from_millis(100));
+            Ok(Length(bytes.len()))
+        }
+    }
+
+    /// An empty directory of its own for each test.
+    fn temp_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("train-assets-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root
+    }
+
+    /// Writes `contents` to `path`, marking it as modified `seconds` after the epoch so changes
+    /// are seen however coarse the file system's timestamps are.
+    fn write(path: &Path, contents: &[u8], seconds: u64) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+            .unwrap();
+    }
+
+    /// Has the next [`AssetServer::update`] check for changes, however recently it last did.
+    fn check_next_update(server: &mut AssetServer) {
+        server.last_checked = Instant::now() - HOT_RELOAD_INTERVAL;
+    }
+
+    /// Calls [`AssetServer::update`] until `done`, returning every path it says was reloaded.
+    fn update_until(server: &mut AssetServer, done: impl Fn(&AssetServer) -> bool) -> Vec<PathBuf> {
+        let start = Instant::now();
+        let mut reloaded = Vec::new();
+        while !done(server) {
+            assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+            thread::sleep(Duration::from_millis(10));
+            reloaded.extend(server.update());
+        }
+        reloaded
+    }
+
+    #[test]
+    fn files_load_once_for_each_type() {
+        let root = temp_root("dedupe");
+        write(&root.join("a.txt"), b"a", 1);
+        write(&root.join("b.txt"), b"b", 1);
+        let mut server = AssetServer::new(&root, false);
+
+        let a = server.load::<Text>("a.txt");
+        assert_eq!(server.load::<Text>("a.txt"), a);
+        let b = server.load::<Text>("b.txt");
+        assert_ne!(b, a);
+        let a_length = server.load::<Length>("a.txt");
+        assert_ne!(a_length.index, a.index);
+        assert_eq!(server.slots.len(), 3);
+
+        assert_eq!(*server.wait(a).unwrap(), Text("a".into()));
+        assert_eq!(server.wait(a_length).unwrap().0, 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_root() {
+        let root = temp_root("root");
+        std::fs::create_dir(root.join("nested")).unwrap();
+        write(&root.join("nested/a.txt"), b"nested", 1);
+        let mut server = AssetServer::new(&root, false);
+
+        let handle = server.load::<Text>("nested/a.txt");
+        assert_eq!(*server.wait(handle).unwrap(), Text("nested".into()));
+        assert_eq!(server.state(handle), &LoadState::Loaded);
+        assert_eq!(server.version(handle), 1);
+
+        let missing = server.load::<Text>("a.txt");
+        let error = server.wait(missing).unwrap_err();
+        assert_eq!(error.path, root.join("a.txt"));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn files_that_cant_be_read_or_decoded_fail() {
+        let root = temp_root("failed");
+        write(&root.join("bad.txt"), &[0xff, 0xfe], 1);
+        let mut server = AssetServer::new(&root, false);
+
+        let missing = server.load::<Text>("missing.txt");
+        assert!(server.wait(missing).is_err());
+        assert!(matches!(server.state(missing), LoadState::Failed(_)));
+        assert!(server.get(missing).is_none());
+
+        let bad = server.load::<Text>("bad.txt");
+        let error = server.wait(bad).unwrap_err();
+        assert_eq!(server.state(bad), &LoadState::Failed(error.message));
+        assert_eq!(server.version(bad), 0);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reloads_bump_the_version_and_keep_the_last_good_one() {
+        let root = temp_root("reload");
+        let path = root.join("a.txt");
+        write(&path, b"one", 1);
+        let mut server = AssetServer::new(&root, true);
+        let handle = server.load::<Text>("a.txt");
+        server.wait(handle).unwrap();
+        assert_eq!(server.version(handle), 1);
+
+        write(&path, &[0xff], 2);
+        check_next_update(&mut server);
+        let reloaded = update_until(&mut server, |server| {
+            server.slots[handle.index].modified == modified(&path)
+        });
+        assert!(reloaded.is_empty());
+        assert_eq!(server.state(handle), &LoadState::Loaded);
+        assert_eq!(server.version(handle), 1);
+        assert_eq!(*server.get(handle).unwrap(), Text("one".into()));
+
+        write(&path, b"two", 3);
+        check_next_update(&mut server);
+        let reloaded = update_until(&mut server, |server| server.version(handle) == 2);
+        assert_eq!(reloaded, [PathBuf::from("a.txt")]);
+        assert_eq!(*server.get(handle).unwrap(), Text("two".into()));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn reloads_picked_up_while_waiting_are_returned_by_the_next_update() {
+        let root = temp_root("wait");
+        write(&root.join("a.txt"), b"one", 1);
+        let mut server = AssetServer::new(&root, true);
+        let a = server.load::<Text>("a.txt");
+        server.wait(a).unwrap();
+
+        write(&root.join("a.txt"), b"two", 2);
+        check_next_update(&mut server);
+        assert!(server.update().is_empty());
+        // The reload is queued first and decodes faster, so it finishes during the wait
+        let slow = server.load::<Length>("a.txt");
+        server.wait(slow).unwrap();
+        assert_eq!(server.version(a), 2);
+
+        assert_eq!(server.update(), [PathBuf::from("a.txt")]);
+        assert!(server.update().is_empty());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ash::vk;
+use glam::Vec3;
+use serde::Serialize;
+
+use crate::{
+    actions::Bindings,
+    graphics::{CameraPath, Graphics, GraphicsSettings, Trace},
+    input::Input,
+    locomotive::Locomotive,
+    scene_file::SceneFile,
+    time::TICK_DURATION,
+};
+
+/// The size of the image rendered by benchmarks.
+const EXTENT: vk::Extent2D = vk::Extent2D {
+    width: 1280,
+    height: 720,
+};
+/// The camera circles the scene at this distance and height, in metres.
+const ORBIT_RADIUS: f32 = 45.;
+const ORBIT_HEIGHT: f32 = 20.;
+
+/// What was measured for a single frame.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FrameSample {
+    /// How long the CPU spent updating and drawing the frame, in milliseconds. This includes any
+    /// time spent waiting for the GPU to finish the frame before.
+    pub cpu_ms: f64,
+    /// How long the GPU spent on the frame, in milliseconds. `None` if the device doesn't support
+    /// timestamps.
+    pub gpu_ms: Option<f64>,
+    pub draw_calls: u32,
+}
+
+/// A summary of one measurement across every frame.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Statistics {
+    pub min: f64,
+    pub max: f64,
+    pub average: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+impl Statistics {
+    /// Returns `None` if there are no values.
+    fn new(values: impl IntoIterator<Item = f64>) -> Option<Self> {
+        let mut values: Vec<f64> = values.into_iter().collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+
+        // Nearest-rank percentiles, so each is a value that was actually measured
+        let percentile = |p: f64| {
+            let rank = (p * values.len() as f64).ceil() as usize;
+            values[rank.saturating_sub(1)]
+        };
+
+        Some(Self {
+            min: values[0],
+            max: values[values.len() - 1],
+            average: values.iter().sum::<f64>() / values.len() as f64,
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+        })
+    }
+}
+
+/// The results of a benchmark run.
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub width: u32,
+    pub height: u32,
+    pub msaa_samples: u32,
+    /// How long the run took in real time, in seconds.
+    pub elapsed_seconds: f64,
+    pub cpu_ms: Option<Statistics>,
+    pub gpu_ms: Option<Statistics>,
+    pub draw_calls: Option<Statistics>,
+    pub frames: Vec<FrameSample>,
+}
+
+impl Report {
+    fn new(graphics: &Graphics, elapsed: Duration, frames: Vec<FrameSample>) -> Self {
+        Self {
+            width: EXTENT.width,
+            height: EXTENT.height,
+            msaa_samples: graphics.msaa_samples(),
+            elapsed_seconds: elapsed.as_secs_f64(),
+            cpu_ms: Statistics::new(frames.iter().map(|frame| frame.cpu_ms)),
+            gpu_ms: Statistics::new(frames.iter().filter_map(|frame| frame.gpu_ms)),
+            draw_calls: Statistics::new(frames.iter().map(|frame| frame.draw_calls as f64)),
+            frames,
+        }
+    }
+
+    /// Writes the report to `path`. A `.csv` file gets just the statistics, one row per
+    /// measurement; anything else gets the whole report, including every frame, as JSON.
+    pub fn save(&self, path: &Path) -> Result<(), ReportError> {
+        let contents = match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => self.to_csv(),
+            _ => serde_json::to_string_pretty(self).map_err(ReportError::Json)?,
+        };
+        std::fs::write(path, contents).map_err(ReportError::Io)
+    }
+
+    fn statistics(&self) -> impl Iterator<Item = (&'static str, Statistics)> {
+        [
+            ("cpu_ms", self.cpu_ms),
+            ("gpu_ms", self.gpu_ms),
+            ("draw_calls", self.draw_calls),
+        ]
+        .into_iter()
+        .filter_map(|(name, statistics)| Some((name, statistics?)))
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("measurement,min,max,average,p95,p99\n");
+        for (name, statistics) in self.statistics() {
+            let Statistics {
+                min,
+                max,
+                average,
+                p95,
+                p99,
+            } = statistics;
+            csv += &format!("{name},{min},{max},{average},{p95},{p99}\n");
+        }
+        csv
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} frames at {}x{} with {}x MSAA in {:.2}s",
+            self.frames.len(),
+            self.width,
+            self.height,
+            self.msaa_samples,
+            self.elapsed_seconds
+        )?;
+        for (name, statistics) in self.statistics() {
+            let Statistics {
+                min,
+                max,
+                average,
+                p95,
+                p99,
+            } = statistics;
+            writeln!(
+                f,
+                "{name:>10}: min {min:.3}, max {max:.3}, avg {average:.3}, p95 {p95:.3}, \
+                 p99 {p99:.3}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ReportError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for ReportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReportError::Io(e) => write!(f, "{e}"),
+            ReportError::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ReportError {}
+
+/// Renders `scene_file`'s scene offscreen with `settings` while the camera circles it, for
+/// `duration` seconds of simulated time. Each frame runs exactly one tick, so every run draws the
+/// same frames however fast the machine is. If `trace` is set, a trace of every profiler scope is
+/// returned too.
+pub fn run(
+    scene_file: SceneFile,
+    settings: &GraphicsSettings,
+    duration: f32,
+    trace: bool,
+) -> (Report, Option<Trace>) {
+    let mut graphics = Graphics::headless(EXTENT, settings);
+    if trace {
+        graphics.profiler_mut().start_trace();
+    }
+    let input = Input::new(Bindings::default());
+    let mut locomotive = Locomotive::default();
+    let SceneFile {
+        mut scene,
+        lighting,
+        ..
+    } = scene_file;
+    graphics.lighting = lighting;
+    let locomotive_entity = locomotive.spawn(&mut scene);
+    graphics.camera.play_path(CameraPath::orbit(
+        Vec3::ZERO,
+        ORBIT_RADIUS,
+        ORBIT_HEIGHT,
+        duration,
+    ));
+
+    let frame_count = (duration / TICK_DURATION).ceil() as usize;
+    let mut frames: Vec<FrameSample> = Vec::with_capacity(frame_count);
+    let start = Instant::now();
+
+    for index in 0..frame_count {
+        let _span = tracing::debug_span!("frame", index).entered();
+        let frame_start = Instant::now();
+
+        let scope = graphics.profiler_mut().begin_cpu("simulate");
+        locomotive.update(TICK_DURATION, &input);
+        graphics.update(TICK_DURATION, &input, locomotive.pose());
+        graphics.profiler_mut().end_cpu(scope);
+        locomotive.place(&mut scene, locomotive_entity, 1.);
+        graphics.draw(1., &scene.draw_list().cubes);
+
+        frames.push(FrameSample {
+            cpu_ms: as_millis(frame_start.elapsed()),
+            gpu_ms: None,
+            draw_calls: graphics.draw_calls(),
+        });
+
+        // GPU times arrive a frame or more later, once the GPU has finished with them
+        record_gpu_times(&mut graphics, &mut frames);
+    }
+
+    graphics.wait_idle();
+    record_gpu_times(&mut graphics, &mut frames);
+
+    let report = Report::new(&graphics, start.elapsed(), frames);
+    (report, graphics.profiler_mut().stop_trace())
+}
+
+/// Every frame is drawn, so the profiler's frame indices match those of `frames`.
+fn record_gpu_times(graphics: &mut Graphics, frames: &mut [FrameSample]) {
+    for (index, gpu_time) in graphics.profiler_mut().take_frame_times() {
+        if let Some(frame) = frames.get_mut(index as usize) {
+            frame.gpu_ms = Some(as_millis(gpu_time));
+        }
+    }
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_values_have_no_statistics() {
+        assert!(Statistics::new([]).is_none());
+    }
+
+    #[test]
+    fn one_value_is_every_statistic() {
+        let statistics = Statistics::new([4.]).unwrap();
+        assert_eq!(
+            [
+                statistics.min,
+                statistics.max,
+                statistics.average,
+                statistics.p95,
+                statistics.p99
+            ],
+            [4.; 5]
+        );
+    }
+
+    #[test]
+    fn min_max_and_average_ignore_order() {
+        let statistics = Statistics::new([3., 1., 4., 1., 5., 9., 2., 6.]).unwrap();
+        assert_eq!(statistics.min, 1.);
+        assert_eq!(statistics.max, 9.);
+        assert_eq!(statistics.average, 31. / 8.);
+    }
+
+    #[test]
+    fn percentiles_are_nearest_rank() {
+        // 1 to 100, shuffled
+        let values = (0..100).map(|i| ((i * 37) % 100 + 1) as f64);
+        let statistics = Statistics::new(values).unwrap();
+        assert_eq!(statistics.p95, 95.);
+        assert_eq!(statistics.p99, 99.);
+
+        // With 10 values the 95th percentile's rank is 9.5, which rounds up to the largest value
+        let statistics = Statistics::new((1..=10).map(f64::from)).unwrap();
+        assert_eq!(statistics.p95, 10.);
+        assert_eq!(statistics.p99, 10.);
+
+        // With 20 values it's exactly the 19th
+        let statistics = Statistics::new((1..=20).map(f64::from)).unwrap();
+        assert_eq!(statistics.p95, 19.);
+        assert_eq!(statistics.p99, 20.);
+    }
+}
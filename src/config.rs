@@ -0,0 +1,589 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::DEFAULT_ASSET_ROOT,
+    graphics::{DisplaySettings, GraphicsSettings},
+    scene_file::DEFAULT_SCENE_PATH,
+};
+
+/// Environment variables that override settings are named with this prefix and the setting's name
+/// in capitals, like `TRAIN_MSAA_SAMPLES`.
+const ENV_PREFIX: &str = "TRAIN_";
+const MSAA_SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// How the game starts up. Each setting comes from the config file if it's there, then from an
+/// environment variable, then from the command line, each overriding the last.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The size of the window's contents in physical pixels, when it isn't fullscreen.
+    pub width: u32,
+    pub height: u32,
+    /// Cover the whole of the current monitor, without changing its video mode.
+    pub fullscreen: bool,
+    /// The GPU to render with: its index in the list Vulkan gives, or part of its name.
+    pub gpu: Option<String>,
+    pub present_mode: PresentMode,
+    /// How many images the swapchain should have, or `None` for one more than the least the
+    /// surface allows.
+    pub image_count: Option<u32>,
+    /// The most frames to draw per second, or `None` for no limit besides vsync.
+    pub frame_limit: Option<f32>,
+    /// 1, 2, 4 or 8, clamped to what the GPU supports.
+    pub msaa_samples: u32,
+    /// Relative to the asset root.
+    pub scene: PathBuf,
+    /// The directory shaders, fonts, scenes and bindings are loaded from.
+    pub asset_root: PathBuf,
+    /// Load shaders and fonts again when their files change.
+    pub hot_reload: bool,
+    /// Run a replay as fast as possible without opening a window.
+    pub headless: bool,
+    pub log_level: LogLevel,
+    /// Also write logs to this file, replacing whatever it held.
+    pub log_file: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let graphics = GraphicsSettings::default();
+        let display = DisplaySettings::default();
+        Self {
+            width: 1280,
+            height: 720,
+            fullscreen: false,
+            gpu: graphics.gpu,
+            present_mode: PresentMode::Mailbox,
+            image_count: display.image_count,
+            frame_limit: display.frame_limit,
+            msaa_samples: graphics.msaa_samples,
+            scene: PathBuf::from(DEFAULT_SCENE_PATH),
+            asset_root: graphics.asset_root,
+            hot_reload: graphics.hot_reload,
+            headless: false,
+            log_level: LogLevel::Info,
+            log_file: None,
+        }
+    }
+}
+
+/// How finished frames are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PresentMode {
+    /// Wait for vertical blank, queueing frames up if they're drawn faster than that.
+    Fifo,
+    /// Wait for vertical blank, but replace the waiting frame with newer ones instead of queueing
+    /// them. Falls back to `Fifo` where it isn't supported.
+    Mailbox,
+    /// Show frames as soon as they're ready, which allows tearing.
+    Immediate,
+}
+
+impl PresentMode {
+    const ALL: [PresentMode; 3] = [
+        PresentMode::Fifo,
+        PresentMode::Mailbox,
+        PresentMode::Immediate,
+    ];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 5] = [
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+        LogLevel::Trace,
+    ];
+}
+
+/// Parses one of `variants` from its name, ignoring case, dashes and underscores.
+fn parse_variant<T: Copy + fmt::Debug>(variants: &[T], value: &str) -> Result<T, String> {
+    let value = value.replace(['-', '_'], "");
+    variants
+        .iter()
+        .copied()
+        .find(|variant| format!("{variant:?}").eq_ignore_ascii_case(&value))
+        .ok_or_else(|| {
+            let names: Vec<String> = variants
+                .iter()
+                .map(|variant| format!("{variant:?}").to_lowercase())
+                .collect();
+            format!("expected one of {}", names.join(", "))
+        })
+}
+
+impl FromStr for PresentMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_variant(&PresentMode::ALL, value)
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_variant(&LogLevel::ALL, value)
+    }
+}
+
+/// A setting that can be overridden from the environment or the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Width,
+    Height,
+    Fullscreen,
+    Gpu,
+    PresentMode,
+    ImageCount,
+    FrameLimit,
+    MsaaSamples,
+    Scene,
+    AssetRoot,
+    HotReload,
+    Headless,
+    LogLevel,
+    LogFile,
+}
+
+impl Setting {
+    pub const ALL: [Setting; 14] = [
+        Setting::Width,
+        Setting::Height,
+        Setting::Fullscreen,
+        Setting::Gpu,
+        Setting::PresentMode,
+        Setting::ImageCount,
+        Setting::FrameLimit,
+        Setting::MsaaSamples,
+        Setting::Scene,
+        Setting::AssetRoot,
+        Setting::HotReload,
+        Setting::Headless,
+        Setting::LogLevel,
+        Setting::LogFile,
+    ];
+
+    /// The setting's name in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Setting::Width => "width",
+            Setting::Height => "height",
+            Setting::Fullscreen => "fullscreen",
+            Setting::Gpu => "gpu",
+            Setting::PresentMode => "present_mode",
+            Setting::ImageCount => "image_count",
+            Setting::FrameLimit => "frame_limit",
+            Setting::MsaaSamples => "msaa_samples",
+            Setting::Scene => "scene",
+            Setting::AssetRoot => "asset_root",
+            Setting::HotReload => "hot_reload",
+            Setting::Headless => "headless",
+            Setting::LogLevel => "log_level",
+            Setting::LogFile => "log_file",
+        }
+    }
+
+    pub fn env_var(self) -> String {
+        format!("{ENV_PREFIX}{}", self.name().to_uppercase())
+    }
+
+    pub fn flag(self) -> String {
+        format!("--{}", self.name().replace('_', "-"))
+    }
+
+    /// Whether the setting is on or off, so its flag can be given without a value to turn it on.
+    pub fn is_switch(self) -> bool {
+        matches!(
+            self,
+            Setting::Fullscreen | Setting::HotReload | Setting::Headless
+        )
+    }
+}
+
+impl Config {
+    /// Loads the config file at `path`, then applies any overrides from the environment and then
+    /// `overrides`, which are from the command line. A missing file is only an error if it's
+    /// `required`; otherwise the defaults are used.
+    pub fn load(
+        path: &Path,
+        required: bool,
+        overrides: &[(Setting, String)],
+    ) -> Result<Self, ConfigError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => Some(contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !required => None,
+            Err(e) => return Err(ConfigError::Io(path.to_owned(), e)),
+        };
+
+        Self::from_layers(
+            contents.as_deref().map(|contents| (path, contents)),
+            |env_var| std::env::var(env_var),
+            overrides,
+        )
+    }
+
+    /// Builds the config from the `contents` of the config file at `path`, if there is one, then
+    /// environment variables looked up with `env`, and then `overrides`.
+    fn from_layers(
+        file: Option<(&Path, &str)>,
+        env: impl Fn(&str) -> Result<String, std::env::VarError>,
+        overrides: &[(Setting, String)],
+    ) -> Result<Self, ConfigError> {
+        let mut config = match file {
+            Some((path, contents)) => {
+                let config: Config = ron::from_str(contents)
+                    .map_err(|e| ConfigError::Parse(path.to_owned(), Box::new(e)))?;
+                config
+                    .validate()
+                    .map_err(|(setting, message)| ConfigError::Invalid {
+                        source: format!("{}: {}", path.display(), setting.name()),
+                        message,
+                    })?;
+                config
+            }
+            None => Config::default(),
+        };
+
+        for setting in Setting::ALL {
+            let env_var = setting.env_var();
+            match env(&env_var) {
+                Ok(value) => {
+                    config
+                        .set(setting, &value)
+                        .map_err(|message| ConfigError::Invalid {
+                            source: env_var,
+                            message,
+                        })?
+                }
+                Err(std::env::VarError::NotPresent) => {}
+                Err(e) => {
+                    return Err(ConfigError::Invalid {
+                        source: env_var,
+                        message: e.to_string(),
+                    })
+                }
+            }
+        }
+
+        for (setting, value) in overrides {
+            config
+                .set(*setting, value)
+                .map_err(|message| ConfigError::Invalid {
+                    source: setting.flag(),
+                    message,
+                })?;
+        }
+
+        Ok(config)
+    }
+
+    /// Where the scene file is, under the asset root.
+    pub fn scene_path(&self) -> PathBuf {
+        self.asset_root.join(&self.scene)
+    }
+
+    /// Parses `value` into `setting`, returning what's wrong with it if it doesn't fit.
+    fn set(&mut self, setting: Setting, value: &str) -> Result<(), String> {
+        fn parse<T: FromStr>(value: &str, expected: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("expected {expected}, not {value:?}"))
+        }
+
+        /// An empty value unsets an optional setting.
+        fn parse_optional<T: FromStr>(value: &str, expected: &str) -> Result<Option<T>, String> {
+            (!value.is_empty())
+                .then(|| parse(value, expected))
+                .transpose()
+        }
+
+        match setting {
+            Setting::Width => self.width = parse(value, "a number of pixels")?,
+            Setting::Height => self.height = parse(value, "a number of pixels")?,
+            Setting::Fullscreen => self.fullscreen = parse(value, "true or false")?,
+            Setting::Gpu => self.gpu = Some(value.to_owned()).filter(|gpu| !gpu.is_empty()),
+            Setting::PresentMode => self.present_mode = value.parse()?,
+            Setting::ImageCount => self.image_count = parse_optional(value, "a number of images")?,
+            Setting::FrameLimit => {
+                self.frame_limit = parse_optional(value, "a number of frames per second")?
+            }
+            Setting::MsaaSamples => self.msaa_samples = parse(value, "a number of samples")?,
+            Setting::Scene => self.scene = PathBuf::from(value),
+            Setting::AssetRoot => self.asset_root = PathBuf::from(value),
+            Setting::HotReload => self.hot_reload = parse(value, "true or false")?,
+            Setting::Headless => self.headless = parse(value, "true or false")?,
+            Setting::LogLevel => self.log_level = value.parse()?,
+            Setting::LogFile => self.log_file = (!value.is_empty()).then(|| PathBuf::from(value)),
+        }
+
+        self.validate().map_err(|(_, message)| message)
+    }
+
+    /// Checks that every setting makes sense, returning the first that doesn't and why.
+    fn validate(&self) -> Result<(), (Setting, String)> {
+        if self.width == 0 {
+            return Err((Setting::Width, "must be more than zero".into()));
+        }
+        if self.height == 0 {
+            return Err((Setting::Height, "must be more than zero".into()));
+        }
+        if self.image_count == Some(0) {
+            return Err((Setting::ImageCount, "must be more than zero".into()));
+        }
+        if self
+            .frame_limit
+            .is_some_and(|frame_limit| !(frame_limit.is_finite() && frame_limit > 0.))
+        {
+            return Err((Setting::FrameLimit, "must be more than zero".into()));
+        }
+        if !MSAA_SAMPLE_COUNTS.contains(&self.msaa_samples) {
+            return Err((Setting::MsaaSamples, "must be 1, 2, 4 or 8".into()));
+        }
+        Ok(())
+    }
+
+    pub fn graphics_settings(&self) -> GraphicsSettings {
+        let (vsync, prefer_mailbox) = match self.present_mode {
+            PresentMode::Fifo => (true, false),
+            PresentMode::Mailbox => (true, true),
+            PresentMode::Immediate => (false, false),
+        };
+
+        GraphicsSettings {
+            gpu: self.gpu.clone(),
+            msaa_samples: self.msaa_samples,
+            display: DisplaySettings {
+                vsync,
+                prefer_mailbox,
+                image_count: self.image_count,
+                frame_limit: self.frame_limit,
+            },
+            asset_root: self.asset_root.clone(),
+            hot_reload: self.hot_reload,
+        }
+    }
+
+    /// The config as it would be written in a config file.
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("Config is always serialisable")
+    }
+}
+
+/// The asset root to read the config file from, before the file's been read: the one given in
+/// `overrides` from the command line, or else in the environment, or else the default.
+pub fn initial_asset_root(overrides: &[(Setting, String)]) -> PathBuf {
+    overrides
+        .iter()
+        .rev()
+        .find(|(setting, _)| *setting == Setting::AssetRoot)
+        .map(|(_, value)| PathBuf::from(value))
+        .or_else(|| std::env::var_os(Setting::AssetRoot.env_var()).map(PathBuf::from))
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_ASSET_ROOT))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, Box<ron::error::SpannedError>),
+    /// `source` is where the bad value came from: the config file and setting, the environment
+    /// variable or the command-line flag.
+    Invalid {
+        source: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            // The error starts with the line and column
+            ConfigError::Parse(path, e) => write!(f, "{}:{e}", path.display()),
+            ConfigError::Invalid { source, message } => write!(f, "{source}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, env::VarError};
+
+    use super::*;
+
+    const PATH: &str = "train.ron";
+
+    /// Loads a config from in-memory layers instead of the real file and environment.
+    fn load(
+        file: Option<&str>,
+        env: &[(Setting, &str)],
+        overrides: &[(Setting, &str)],
+    ) -> Result<Config, String> {
+        let env: HashMap<String, String> = env
+            .iter()
+            .map(|(setting, value)| (setting.env_var(), value.to_string()))
+            .collect();
+        let overrides: Vec<(Setting, String)> = overrides
+            .iter()
+            .map(|(setting, value)| (*setting, value.to_string()))
+            .collect();
+
+        Config::from_layers(
+            file.map(|contents| (Path::new(PATH), contents)),
+            |env_var| env.get(env_var).cloned().ok_or(VarError::NotPresent),
+            &overrides,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn defaults_without_any_layers() {
+        let config = load(None, &[], &[]).unwrap();
+        assert_eq!(config.width, Config::default().width);
+        assert_eq!(config.present_mode, PresentMode::Mailbox);
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let file = Some("(width: 800, height: 600, msaa_samples: 2)");
+        let env = [(Setting::Width, "1024"), (Setting::MsaaSamples, "8")];
+        let cli = [(Setting::Width, "1920")];
+
+        let config = load(file, &env, &cli).unwrap();
+        assert_eq!(config.width, 1920);
+        assert_eq!(config.msaa_samples, 8);
+        assert_eq!(config.height, 600);
+
+        // Settings left out of the file keep their defaults
+        assert_eq!(config.fullscreen, Config::default().fullscreen);
+    }
+
+    #[test]
+    fn the_last_override_wins() {
+        let cli = [(Setting::MsaaSamples, "2"), (Setting::MsaaSamples, "1")];
+        assert_eq!(load(None, &[], &cli).unwrap().msaa_samples, 1);
+    }
+
+    #[test]
+    fn errors_name_where_the_bad_value_came_from() {
+        assert_eq!(
+            load(Some("(msaa_samples: 3)"), &[], &[]).unwrap_err(),
+            "train.ron: msaa_samples: must be 1, 2, 4 or 8"
+        );
+        assert_eq!(
+            load(None, &[(Setting::Width, "wide")], &[]).unwrap_err(),
+            "TRAIN_WIDTH: expected a number of pixels, not \"wide\""
+        );
+        assert_eq!(
+            load(None, &[], &[(Setting::Height, "0")]).unwrap_err(),
+            "--height: must be more than zero"
+        );
+
+        let error = load(Some("(colour_depth: 10)"), &[], &[]).unwrap_err();
+        assert!(error.starts_with("train.ron:1:"), "{error}");
+    }
+
+    #[test]
+    fn switches_and_choices_parse() {
+        let cli = [
+            (Setting::Fullscreen, "true"),
+            (Setting::PresentMode, "IMMEDIATE"),
+            (Setting::LogLevel, "debug"),
+        ];
+        let config = load(None, &[], &cli).unwrap();
+        assert!(config.fullscreen);
+        assert_eq!(config.present_mode, PresentMode::Immediate);
+        assert_eq!(config.log_level, LogLevel::Debug);
+
+        assert_eq!(
+            load(None, &[], &[(Setting::PresentMode, "vsync")]).unwrap_err(),
+            "--present-mode: expected one of fifo, mailbox, immediate"
+        );
+        assert_eq!(
+            load(None, &[], &[(Setting::Headless, "yes")]).unwrap_err(),
+            "--headless: expected true or false, not \"yes\""
+        );
+    }
+
+    #[test]
+    fn empty_values_unset_optional_settings() {
+        let file = Some(
+            "#![enable(implicit_some)]\n\
+             (gpu: \"NVIDIA\", log_file: \"train.log\", image_count: 3, frame_limit: 30.0)",
+        );
+        let config = load(file, &[], &[]).unwrap();
+        assert_eq!(config.gpu.as_deref(), Some("NVIDIA"));
+        assert_eq!(config.image_count, Some(3));
+        assert_eq!(config.frame_limit, Some(30.));
+
+        let env = [(Setting::Gpu, ""), (Setting::LogFile, "")];
+        let cli = [(Setting::ImageCount, ""), (Setting::FrameLimit, "")];
+        let config = load(file, &env, &cli).unwrap();
+        assert_eq!(config.gpu, None);
+        assert_eq!(config.log_file, None);
+        assert_eq!(config.image_count, None);
+        assert_eq!(config.frame_limit, None);
+    }
+
+    #[test]
+    fn display_settings_reach_the_graphics_settings() {
+        let cli = [
+            (Setting::PresentMode, "fifo"),
+            (Setting::ImageCount, "2"),
+            (Setting::FrameLimit, "144"),
+        ];
+        let display = load(None, &[], &cli).unwrap().graphics_settings().display;
+        assert!(display.vsync && !display.prefer_mailbox);
+        assert_eq!(display.image_count, Some(2));
+        assert_eq!(display.frame_limit, Some(144.));
+
+        assert_eq!(
+            load(None, &[], &[(Setting::ImageCount, "0")]).unwrap_err(),
+            "--image-count: must be more than zero"
+        );
+        assert_eq!(
+            load(None, &[], &[(Setting::FrameLimit, "-1")]).unwrap_err(),
+            "--frame-limit: must be more than zero"
+        );
+    }
+
+    #[test]
+    fn printed_config_loads_back_the_same() {
+        let cli = [(Setting::Width, "640"), (Setting::FrameLimit, "75")];
+        let config = load(None, &[], &cli).unwrap();
+        let reloaded = load(Some(&config.to_ron()), &[], &[]).unwrap();
+        assert_eq!(reloaded.to_ron(), config.to_ron());
+    }
+
+    #[test]
+    fn setting_names() {
+        assert_eq!(Setting::MsaaSamples.env_var(), "TRAIN_MSAA_SAMPLES");
+        assert_eq!(Setting::MsaaSamples.flag(), "--msaa-samples");
+        for setting in Setting::ALL {
+            assert!(
+                Config::default().to_ron().contains(setting.name()),
+                "{setting:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{marker::PhantomData, sync::Arc};
+
+use ash::vk;
+
+use super::context::Context;
+
+/// A host-visible, persistently mapped buffer holding a single `T`.
+pub struct Buffer<T> {
+    pub handle: vk::Buffer,
+    #[allow(unused)]
+    pub memory: vk::DeviceMemory,
+    ptr: std::ptr::NonNull<T>,
+    context: Arc<Context>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T: Copy> Buffer<T> {
+    pub(crate) fn new(context: Arc<Context>, usage: vk::BufferUsageFlags) -> Self {
+        let size = std::mem::size_of::<T>() as vk::DeviceSize;
+        let (handle, memory, ptr) = allocate(&context, size, usage);
+
+        Self {
+            handle,
+            memory,
+            ptr: ptr.cast(),
+            context,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Writes `value` into the buffer. The caller must ensure the GPU is not reading from it.
+    pub fn write(&self, value: T) {
+        unsafe { self.ptr.as_ptr().write(value) }
+    }
+
+    /// Reads the buffer's contents. The caller must ensure the GPU is not writing to it.
+    pub fn read(&self) -> T {
+        unsafe { self.ptr.as_ptr().read() }
+    }
+
+    pub fn size(&self) -> vk::DeviceSize {
+        std::mem::size_of::<T>() as _
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.unmap_memory(self.memory);
+            device.destroy_buffer(self.handle, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// A host-visible, persistently mapped buffer holding up to `capacity` values of `T`.
+pub struct ArrayBuffer<T> {
+    pub handle: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    ptr: std::ptr::NonNull<T>,
+    capacity: usize,
+    context: Arc<Context>,
+}
+
+impl<T: Copy> ArrayBuffer<T> {
+    pub(crate) fn new(context: Arc<Context>, usage: vk::BufferUsageFlags, capacity: usize) -> Self {
+        // Vulkan doesn't allow empty buffers
+        let capacity = capacity.max(1);
+        let size = (capacity * std::mem::size_of::<T>()) as vk::DeviceSize;
+        let (handle, memory, ptr) = allocate(&context, size, usage);
+
+        Self {
+            handle,
+            memory,
+            ptr: ptr.cast(),
+            capacity,
+            context,
+        }
+    }
+
+    /// Writes `values` into the buffer, starting at `offset` values in. The caller must ensure the
+    /// GPU is not reading from that part of it.
+    pub fn write(&self, offset: usize, values: &[T]) {
+        assert!(offset + values.len() <= self.capacity, "Buffer overflow");
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                values.as_ptr(),
+                self.ptr.as_ptr().add(offset),
+                values.len(),
+            )
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<T> Drop for ArrayBuffer<T> {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.unmap_memory(self.memory);
+            device.destroy_buffer(self.handle, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+/// Creates a buffer of `size` bytes in host-visible memory, and maps it.
+fn allocate(
+    context: &Context,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+) -> (
+    vk::Buffer,
+    vk::DeviceMemory,
+    std::ptr::NonNull<std::ffi::c_void>,
+) {
+    let device = &context.device;
+
+    let handle = unsafe {
+        device.create_buffer(
+            &vk::BufferCreateInfo::default()
+                .size(size)
+                .usage(usage)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            None,
+        )
+    }
+    .unwrap();
+
+    let memory_requirements = unsafe { device.get_buffer_memory_requirements(handle) };
+
+    let memory_type_index = context
+        .find_memory_type_index(
+            &memory_requirements,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )
+        .expect("No host visible memory type - impossible");
+
+    let memory = unsafe {
+        device.allocate_memory(
+            &vk::MemoryAllocateInfo::default()
+                .allocation_size(memory_requirements.size)
+                .memory_type_index(memory_type_index),
+            None,
+        )
+    }
+    .expect("Failed to allocate memory - impossible");
+
+    unsafe { device.bind_buffer_memory(handle, memory, 0) }.unwrap();
+
+    let ptr = unsafe { device.map_memory(memory, 0, size, vk::MemoryMapFlags::empty()) }.unwrap();
+
+    (handle, memory, std::ptr::NonNull::new(ptr).unwrap())
+}
@@ -1,63 +1,403 @@
 use ash::vk;
-use dolly::prelude::{CameraRig, Position, Smooth, YawPitch};
-use glam::Quat;
+use dolly::prelude::CameraRig;
 
+use super::{
+    camera_controller::{CameraController, ControllerKind, ControllerSettings},
+    camera_path::{CameraPath, PathController, PathRecorder},
+    picking::Ray,
+};
 use crate::input::Input;
 
+/// Draws the scene from wherever its [`CameraController`] puts it. Switching controllers blends
+/// smoothly from the old pose to the new one.
 #[derive(Debug)]
 pub struct Camera {
-    rig: CameraRig,
+    controller: Box<dyn CameraController>,
+    /// Set while blending from one controller to another.
+    blend: Option<Blend>,
+    /// Where the camera was at the end of the previous tick.
+    previous: Pose,
+    /// Where the camera is at the end of the latest tick.
+    current: Pose,
+    /// Where the camera is drawn from this frame, between `previous` and `current`.
+    render: Pose,
+    /// Set while the camera's path is being recorded.
+    path_recorder: Option<PathRecorder>,
+    pub controller_settings: ControllerSettings,
+    pub projection: Projection,
+    /// The projection that [`Camera::toggle_projection`] switches to, so its settings are kept.
+    other_projection: Projection,
     pub extent: vk::Extent2D,
 }
 
-const MOVEMENT_SPEED: f32 = 10.;
-const LOOK_SPEED: f32 = 0.5;
+/// How the view is projected onto the screen. Both kinds are reverse-Z, so depth is 1 at the near
+/// plane and falls towards 0 in the distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Things get smaller as they get further away. There's no far plane.
+    Perspective {
+        /// The vertical field of view, in degrees.
+        fov_degrees: f32,
+        near: f32,
+    },
+    /// Parallel lines stay parallel, for maps and planning.
+    Orthographic {
+        /// How much of the world fits vertically on screen, in metres.
+        height: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective {
+            fov_degrees: 60.,
+            near: 0.01,
+        }
+    }
+}
+
+impl Projection {
+    /// The default orthographic projection.
+    pub fn orthographic() -> Self {
+        Projection::Orthographic {
+            height: 100.,
+            near: 0.,
+            far: 1000.,
+        }
+    }
+
+    /// The half width and height of the view, `depth` metres in front of the camera.
+    fn half_extents(&self, depth: f32, aspect_ratio: f32) -> glam::Vec2 {
+        let half_height = match *self {
+            Projection::Perspective { fov_degrees, .. } => {
+                depth * (fov_degrees.to_radians() / 2.).tan()
+            }
+            Projection::Orthographic { height, .. } => height / 2.,
+        };
+
+        glam::Vec2::new(half_height * aspect_ratio, half_height)
+    }
+
+    pub(crate) fn ndc_from_view(&self, aspect_ratio: f32) -> glam::Mat4 {
+        let mut ndc_from_view = match *self {
+            Projection::Perspective { fov_degrees, near } => {
+                glam::Mat4::perspective_infinite_reverse_rh(
+                    fov_degrees.to_radians(),
+                    aspect_ratio,
+                    near,
+                )
+            }
+            Projection::Orthographic { near, far, .. } => {
+                let half_extents = self.half_extents(0., aspect_ratio);
+                // Swapping near and far gives reverse-Z
+                glam::Mat4::orthographic_rh(
+                    -half_extents.x,
+                    half_extents.x,
+                    -half_extents.y,
+                    half_extents.y,
+                    far,
+                    near,
+                )
+            }
+        };
+
+        // adjust for wulkan
+        ndc_from_view.y_axis *= -1.0;
+        ndc_from_view
+    }
+}
+
+/// A position and orientation in the world.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pose {
+    pub position: glam::Vec3,
+    pub rotation: glam::Quat,
+}
+
+impl Pose {
+    pub(crate) fn from_rig(rig: &CameraRig) -> Self {
+        Self {
+            position: rig.final_transform.position.into(),
+            rotation: rig.final_transform.rotation.into(),
+        }
+    }
+
+    pub fn lerp(self, other: Pose, t: f32) -> Pose {
+        Pose {
+            position: self.position.lerp(other.position, t),
+            rotation: self.rotation.slerp(other.rotation, t),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Blend {
+    /// The pose the camera was in when the switch happened.
+    from: Pose,
+    elapsed: f32,
+}
+
+/// How long it takes to blend between controllers, in seconds.
+const BLEND_DURATION: f32 = 0.75;
+/// The orthographic height is multiplied by this for each line scrolled.
+const ORTHOGRAPHIC_ZOOM_FACTOR: f32 = 0.9;
+const ORTHOGRAPHIC_HEIGHT_RANGE: (f32, f32) = (1., 5000.);
 
 impl Camera {
     pub fn new(extent: vk::Extent2D) -> Camera {
-        let initial_position = glam::Vec3::new(0., 10.0, 10.);
+        let pose = Pose {
+            position: glam::Vec3::new(0., 10.0, 10.),
+            rotation: glam::Quat::IDENTITY,
+        };
+
         Camera {
-            rig: CameraRig::builder()
-                .with(Position::new(initial_position))
-                .with(YawPitch::new())
-                .with(Smooth::new_position_rotation(1.0, 1.0))
-                .build(),
+            controller: ControllerKind::Fly.create(pose, pose),
+            blend: None,
+            previous: pose,
+            current: pose,
+            render: pose,
+            path_recorder: None,
+            controller_settings: ControllerSettings::default(),
+            projection: Projection::default(),
+            other_projection: Projection::orthographic(),
             extent,
         }
     }
 
-    pub(crate) fn update(&mut self, dt: f32, input: &Input) {
-        self.rig.driver_mut::<YawPitch>().rotate_yaw_pitch(
-            input.yaw_degrees * LOOK_SPEED,
-            input.pitch_degrees * LOOK_SPEED,
-        );
+    /// Moves the camera to `pose` straight away, flying freely from there.
+    pub fn set_pose(&mut self, pose: Pose) {
+        self.controller = ControllerKind::Fly.create(pose, pose);
+        self.blend = None;
+        self.previous = pose;
+        self.current = pose;
+        self.render = pose;
+    }
 
-        // simple fly-cam impl
-        let move_vec = Quat::from(self.rig.final_transform.rotation) * input.get_movement();
+    /// Switches to a different kind of controller, blending over from the current pose.
+    pub fn set_controller(&mut self, kind: ControllerKind, target: Pose) {
+        if Some(kind) == self.controller.kind() {
+            return;
+        }
 
-        self.rig
-            .driver_mut::<Position>()
-            .translate(move_vec * dt * MOVEMENT_SPEED);
-        self.rig.update(dt);
+        self.controller = kind.create(self.current, target);
+        self.blend = Some(Blend {
+            from: self.current,
+            elapsed: 0.,
+        });
     }
 
-    pub(crate) fn ndc_from_world(&self) -> glam::Mat4 {
-        // Get the transform of the camera rig
-        let (translation, rotation) = self.rig.final_transform.into_position_rotation();
+    /// Flies the camera along `path` from its start, then holds it at the end until another
+    /// controller is chosen.
+    pub fn play_path(&mut self, path: CameraPath) {
+        self.controller = Box::new(PathController::new(path));
+        self.blend = None;
+    }
 
-        // Build up the perspective matrix
-        let aspect_ratio = self.extent.width as f32 / self.extent.height as f32;
-        let mut perspective =
-            glam::Mat4::perspective_infinite_reverse_rh(60_f32.to_radians(), aspect_ratio, 0.01);
+    /// Starts taking keyframes of the camera's pose every so often.
+    pub fn start_recording_path(&mut self) {
+        self.path_recorder = Some(PathRecorder::default());
+    }
 
-        // adjust for wulkan
-        perspective.y_axis *= -1.0;
+    /// Stops recording, and returns the recorded path if there was one.
+    pub fn stop_recording_path(&mut self) -> Option<CameraPath> {
+        self.path_recorder.take().map(PathRecorder::finish)
+    }
+
+    /// Switches between perspective and orthographic projection.
+    pub fn toggle_projection(&mut self) {
+        std::mem::swap(&mut self.projection, &mut self.other_projection);
+    }
+
+    /// Advances the camera by one simulation tick of `dt` seconds. `target` is the pose of
+    /// whatever the camera is following.
+    pub fn update(&mut self, dt: f32, input: &Input, target: Pose) {
+        self.previous = self.current;
+
+        if let Some(kind) = ControllerKind::ALL
+            .into_iter()
+            .find(|kind| input.was_pressed(kind.action()))
+        {
+            self.set_controller(kind, target);
+        }
+
+        // Moving an orthographic camera forwards doesn't make anything bigger, so scrolling
+        // zooms it instead, and the controller doesn't see the scroll
+        let unscrolled;
+        let input = match &mut self.projection {
+            Projection::Orthographic { height, .. } if input.scroll_lines != 0. => {
+                let (min_height, max_height) = ORTHOGRAPHIC_HEIGHT_RANGE;
+                *height = (*height * ORTHOGRAPHIC_ZOOM_FACTOR.powf(input.scroll_lines))
+                    .clamp(min_height, max_height);
+                unscrolled = {
+                    let mut input = input.clone();
+                    input.scroll_lines = 0.;
+                    input
+                };
+                &unscrolled
+            }
+            _ => input,
+        };
+
+        let pose = self
+            .controller
+            .update(dt, input, &self.controller_settings, target);
+
+        self.current = match &mut self.blend {
+            Some(blend) => {
+                blend.elapsed += dt;
+                let t = (blend.elapsed / BLEND_DURATION).min(1.);
+                let from = blend.from;
+                if t == 1. {
+                    self.blend = None;
+                }
+
+                // Ease in and out
+                from.lerp(pose, t * t * (3. - 2. * t))
+            }
+            None => pose,
+        };
+
+        if let Some(recorder) = &mut self.path_recorder {
+            recorder.record(dt, self.current);
+        }
+    }
+
+    /// Places the camera `alpha` of the way between the last two ticks, for rendering.
+    pub fn interpolate(&mut self, alpha: f32) {
+        self.render = self.previous.lerp(self.current, alpha);
+    }
+
+    /// The position the camera is rendered from.
+    pub fn position(&self) -> glam::Vec3 {
+        self.render.position
+    }
+
+    /// The rotation the camera is rendered with.
+    pub fn rotation(&self) -> glam::Quat {
+        self.render.rotation
+    }
+
+    /// The direction the camera is looking in, in world space.
+    pub fn forward(&self) -> glam::Vec3 {
+        self.rotation() * glam::Vec3::NEG_Z
+    }
+
+    pub fn aspect_ratio(&self) -> f32 {
+        self.extent.width as f32 / self.extent.height as f32
+    }
+
+    /// Returns the world-space corners of the slice of the view frustum between `near` and `far`
+    /// metres in front of the camera: the four near corners, followed by the four far corners.
+    pub(crate) fn frustum_corners(&self, near: f32, far: f32) -> [glam::Vec3; 8] {
+        let aspect_ratio = self.aspect_ratio();
+        let world_from_view =
+            glam::Affine3A::from_rotation_translation(self.rotation(), self.position());
+
+        let mut corners = [glam::Vec3::ZERO; 8];
+        for (i, depth) in [near, far].into_iter().enumerate() {
+            let half_extents = self.projection.half_extents(depth, aspect_ratio);
+            for (j, (x, y)) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)]
+                .into_iter()
+                .enumerate()
+            {
+                corners[i * 4 + j] = world_from_view.transform_point3(glam::Vec3::new(
+                    x * half_extents.x,
+                    y * half_extents.y,
+                    -depth,
+                ));
+            }
+        }
+
+        corners
+    }
+
+    /// Returns the ray through `pixel`, in physical pixels from the top left of the window, as the
+    /// camera was last drawn.
+    pub fn ray_through_pixel(&self, pixel: glam::Vec2) -> Ray {
+        let extent = glam::Vec2::new(self.extent.width as f32, self.extent.height as f32);
+
+        // The Y flip in the projection means NDC already runs top to bottom, like pixels do
+        let ndc = pixel / extent * 2. - 1.;
+
+        // With reverse-Z the near plane is at depth 1. Depth 0 is at infinity in perspective, so
+        // use a point in between for the direction.
+        let world_from_ndc = self.ndc_from_world().inverse();
+        let near = world_from_ndc.project_point3(ndc.extend(1.));
+        let further = world_from_ndc.project_point3(ndc.extend(0.5));
+
+        Ray {
+            origin: near,
+            direction: (further - near).normalize(),
+        }
+    }
+
+    pub(crate) fn ndc_from_world(&self) -> glam::Mat4 {
+        // Get the transform of the camera
+        let Pose {
+            position: translation,
+            rotation,
+        } = self.render;
+
+        // Build up the projection matrix
+        let ndc_from_view = self.projection.ndc_from_view(self.aspect_ratio());
 
         // Get view_from_world
         let world_from_view = glam::Affine3A::from_rotation_translation(rotation, translation);
         let view_from_world = world_from_view.inverse();
 
         // Combine the matrices
-        perspective * view_from_world
+        ndc_from_view * view_from_world
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{input::InputEvent, time::TICK_DURATION};
+
+    fn camera() -> Camera {
+        let mut camera = Camera::new(vk::Extent2D {
+            width: 1280,
+            height: 720,
+        });
+        camera.set_pose(Pose {
+            position: glam::Vec3::new(0., 10., 10.),
+            rotation: glam::Quat::IDENTITY,
+        });
+        camera
+    }
+
+    fn scrolled(lines: f32) -> Input {
+        let mut input = Input::default();
+        input.handle_event(InputEvent::Scroll { lines });
+        input
+    }
+
+    #[test]
+    fn scrolling_moves_a_perspective_camera() {
+        let mut camera = camera();
+        let before = camera.current;
+
+        camera.update(TICK_DURATION, &scrolled(1.), before);
+
+        assert!(camera.current.position.z < before.position.z);
+        assert_eq!(camera.projection, Projection::default());
+    }
+
+    #[test]
+    fn scrolling_only_zooms_an_orthographic_camera() {
+        let mut camera = camera();
+        camera.toggle_projection();
+        let before = camera.current;
+
+        camera.update(TICK_DURATION, &scrolled(1.), before);
+
+        assert_eq!(camera.current.position, before.position);
+        let Projection::Orthographic { height, .. } = camera.projection else {
+            panic!("expected an orthographic projection");
+        };
+        assert_eq!(height, 100. * ORTHOGRAPHIC_ZOOM_FACTOR);
     }
 }
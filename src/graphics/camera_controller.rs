@@ -0,0 +1,626 @@
+use std::f32::consts::FRAC_PI_2;
+
+use dolly::prelude::{Arm, CameraRig, LookAt, Position, Rotation, Smooth, YawPitch};
+use glam::{EulerRot, Quat, Vec3};
+
+use super::camera::Pose;
+use crate::{actions::Action, input::Input, locomotive::CAB_POSITION};
+
+const MOVEMENT_SPEED: f32 = 10.;
+const LOOK_SPEED: f32 = 0.5;
+/// How far the fly camera moves forward for each line scrolled, in metres.
+const ZOOM_SPEED: f32 = 1.;
+
+/// The orbit camera's distance from its target is multiplied by this for each line scrolled.
+const ORBIT_ZOOM_FACTOR: f32 = 0.9;
+const ORBIT_DISTANCE_RANGE: (f32, f32) = (3., 200.);
+/// The sine of the smallest angle from vertical the orbit camera can be started looking at the
+/// target from.
+const MIN_ORBIT_TILT: f32 = 1e-3;
+
+/// Where the chase camera sits, relative to the target. The target faces -Z.
+const CHASE_OFFSET: Vec3 = Vec3::new(0., 4., 14.);
+/// How far the cab camera can look around, in degrees.
+const CAB_YAW_LIMIT: f32 = 120.;
+const CAB_PITCH_LIMIT: f32 = 60.;
+
+/// The map camera's height is multiplied by this for each line scrolled.
+const MAP_ZOOM_FACTOR: f32 = 0.9;
+const MAP_HEIGHT_RANGE: (f32, f32) = (10., 500.);
+const DEFAULT_MAP_HEIGHT: f32 = 80.;
+
+/// Tunable speeds shared by every controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ControllerSettings {
+    /// How fast the camera moves, in metres per second.
+    pub movement_speed: f32,
+    /// How far the camera turns per unit of mouse movement, in degrees.
+    pub look_speed: f32,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        Self {
+            movement_speed: MOVEMENT_SPEED,
+            look_speed: LOOK_SPEED,
+        }
+    }
+}
+
+/// The different ways the camera can be controlled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControllerKind {
+    /// Flies freely around the world.
+    Fly,
+    /// Circles the target at a distance.
+    Orbit,
+    /// Follows behind the target.
+    Chase,
+    /// Rides along in the target's cab.
+    Cab,
+    /// Looks straight down on the target from above.
+    Map,
+}
+
+impl ControllerKind {
+    pub const ALL: [ControllerKind; 5] = [
+        ControllerKind::Fly,
+        ControllerKind::Orbit,
+        ControllerKind::Chase,
+        ControllerKind::Cab,
+        ControllerKind::Map,
+    ];
+
+    /// The action that switches to this controller.
+    pub fn action(self) -> Action {
+        match self {
+            ControllerKind::Fly => Action::FlyCamera,
+            ControllerKind::Orbit => Action::OrbitCamera,
+            ControllerKind::Chase => Action::ChaseCamera,
+            ControllerKind::Cab => Action::CabCamera,
+            ControllerKind::Map => Action::MapCamera,
+        }
+    }
+
+    /// Creates a controller of this kind, starting as close as it can to `from`.
+    pub fn create(self, from: Pose, target: Pose) -> Box<dyn CameraController> {
+        match self {
+            ControllerKind::Fly => Box::new(FlyController::new(from)),
+            ControllerKind::Orbit => Box::new(OrbitController::new(from, target)),
+            ControllerKind::Chase => Box::new(ChaseController::new(target)),
+            ControllerKind::Cab => Box::new(CabController::default()),
+            ControllerKind::Map => Box::new(MapController::default()),
+        }
+    }
+}
+
+/// Decides where the camera goes each tick.
+pub trait CameraController: std::fmt::Debug {
+    /// Returns `None` for controllers that can't be switched to directly, like path playback.
+    fn kind(&self) -> Option<ControllerKind>;
+
+    /// Advances the controller by one tick of `dt` seconds and returns the camera's new pose.
+    /// `target` is the pose of whatever the camera is following.
+    fn update(
+        &mut self,
+        dt: f32,
+        input: &Input,
+        settings: &ControllerSettings,
+        target: Pose,
+    ) -> Pose;
+}
+
+/// A free-flying camera, moved with the movement actions and turned with the mouse.
+#[derive(Debug)]
+struct FlyController {
+    rig: CameraRig,
+}
+
+impl FlyController {
+    fn new(from: Pose) -> Self {
+        let rig = CameraRig::builder()
+            .with(Position::new(from.position))
+            .with(YawPitch::new().rotation_quat(from.rotation))
+            .with(Smooth::new_position_rotation(1.0, 1.0))
+            .build();
+
+        Self { rig }
+    }
+}
+
+impl CameraController for FlyController {
+    fn kind(&self) -> Option<ControllerKind> {
+        Some(ControllerKind::Fly)
+    }
+
+    fn update(
+        &mut self,
+        dt: f32,
+        input: &Input,
+        settings: &ControllerSettings,
+        _target: Pose,
+    ) -> Pose {
+        self.rig.driver_mut::<YawPitch>().rotate_yaw_pitch(
+            input.yaw_degrees * settings.look_speed,
+            input.pitch_degrees * settings.look_speed,
+        );
+
+        // simple fly-cam impl
+        let rotation = Quat::from(self.rig.final_transform.rotation);
+        let move_vec = rotation * input.get_movement();
+        let zoom = rotation * Vec3::NEG_Z * input.scroll_lines * ZOOM_SPEED;
+
+        self.rig
+            .driver_mut::<Position>()
+            .translate(move_vec * dt * settings.movement_speed + zoom);
+        self.rig.update(dt);
+
+        Pose::from_rig(&self.rig)
+    }
+}
+
+/// Circles the target, turned with the mouse and zoomed with the wheel.
+#[derive(Debug)]
+struct OrbitController {
+    rig: CameraRig,
+}
+
+impl OrbitController {
+    fn new(from: Pose, target: Pose) -> Self {
+        let (min_distance, max_distance) = ORBIT_DISTANCE_RANGE;
+        let distance = from
+            .position
+            .distance(target.position)
+            .clamp(min_distance, max_distance);
+
+        // Start out looking at the target from wherever the camera is now. There's no telling
+        // which way is up when that's straight up or down, eg. coming from the map camera, so
+        // then it keeps the camera's rotation instead.
+        let to_target = (target.position - from.position).normalize_or_zero();
+        let rotation = if to_target.cross(Vec3::Y).length() > MIN_ORBIT_TILT {
+            let view_from_world = glam::Mat4::look_at_rh(from.position, target.position, Vec3::Y);
+            Quat::from_mat4(&view_from_world.inverse())
+        } else {
+            from.rotation
+        };
+
+        let rig = CameraRig::builder()
+            .with(Position::new(target.position))
+            .with(YawPitch::new().rotation_quat(rotation))
+            .with(Smooth::new_rotation(1.0))
+            .with(Arm::new(Vec3::Z * distance))
+            .build();
+
+        Self { rig }
+    }
+}
+
+impl CameraController for OrbitController {
+    fn kind(&self) -> Option<ControllerKind> {
+        Some(ControllerKind::Orbit)
+    }
+
+    fn update(
+        &mut self,
+        dt: f32,
+        input: &Input,
+        settings: &ControllerSettings,
+        target: Pose,
+    ) -> Pose {
+        self.rig.driver_mut::<Position>().position = target.position.into();
+        self.rig.driver_mut::<YawPitch>().rotate_yaw_pitch(
+            input.yaw_degrees * settings.look_speed,
+            input.pitch_degrees * settings.look_speed,
+        );
+
+        let (min_distance, max_distance) = ORBIT_DISTANCE_RANGE;
+        let arm = self.rig.driver_mut::<Arm>();
+        arm.offset.z = (arm.offset.z * ORBIT_ZOOM_FACTOR.powf(input.scroll_lines))
+            .clamp(min_distance, max_distance);
+
+        self.rig.update(dt);
+
+        Pose::from_rig(&self.rig)
+    }
+}
+
+/// Follows behind the target, lagging a little when it turns or speeds up.
+#[derive(Debug)]
+struct ChaseController {
+    rig: CameraRig,
+}
+
+impl ChaseController {
+    fn new(target: Pose) -> Self {
+        let rig = CameraRig::builder()
+            .with(Position::new(target.position))
+            .with(Rotation::new(target.rotation))
+            .with(Smooth::new_position_rotation(1.25, 1.25))
+            .with(Arm::new(CHASE_OFFSET))
+            .with(
+                LookAt::new(target.position + Vec3::Y)
+                    .tracking_smoothness(1.25)
+                    .tracking_predictive(true),
+            )
+            .build();
+
+        Self { rig }
+    }
+}
+
+impl CameraController for ChaseController {
+    fn kind(&self) -> Option<ControllerKind> {
+        Some(ControllerKind::Chase)
+    }
+
+    fn update(
+        &mut self,
+        dt: f32,
+        _input: &Input,
+        _settings: &ControllerSettings,
+        target: Pose,
+    ) -> Pose {
+        self.rig.driver_mut::<Position>().position = target.position.into();
+        self.rig.driver_mut::<Rotation>().rotation = target.rotation.into();
+        self.rig.driver_mut::<LookAt>().target = (target.position + Vec3::Y).into();
+        self.rig.update(dt);
+
+        Pose::from_rig(&self.rig)
+    }
+}
+
+/// Sits in the target's cab, facing forwards, and can look around with the mouse.
+#[derive(Debug, Default)]
+struct CabController {
+    yaw_degrees: f32,
+    pitch_degrees: f32,
+}
+
+impl CameraController for CabController {
+    fn kind(&self) -> Option<ControllerKind> {
+        Some(ControllerKind::Cab)
+    }
+
+    fn update(
+        &mut self,
+        _dt: f32,
+        input: &Input,
+        settings: &ControllerSettings,
+        target: Pose,
+    ) -> Pose {
+        self.yaw_degrees = (self.yaw_degrees + input.yaw_degrees * settings.look_speed)
+            .clamp(-CAB_YAW_LIMIT, CAB_YAW_LIMIT);
+        self.pitch_degrees = (self.pitch_degrees + input.pitch_degrees * settings.look_speed)
+            .clamp(-CAB_PITCH_LIMIT, CAB_PITCH_LIMIT);
+
+        let look = Quat::from_euler(
+            EulerRot::YXZ,
+            self.yaw_degrees.to_radians(),
+            self.pitch_degrees.to_radians(),
+            0.,
+        );
+
+        Pose {
+            position: target.position + target.rotation * CAB_POSITION,
+            rotation: target.rotation * look,
+        }
+    }
+}
+
+/// Looks straight down on the target, with -Z at the top of the screen. The movement actions pan
+/// and the wheel zooms.
+#[derive(Debug)]
+struct MapController {
+    /// How far the view has been panned from the target.
+    offset: Vec3,
+    height: f32,
+}
+
+impl Default for MapController {
+    fn default() -> Self {
+        Self {
+            offset: Vec3::ZERO,
+            height: DEFAULT_MAP_HEIGHT,
+        }
+    }
+}
+
+impl CameraController for MapController {
+    fn kind(&self) -> Option<ControllerKind> {
+        Some(ControllerKind::Map)
+    }
+
+    fn update(
+        &mut self,
+        dt: f32,
+        input: &Input,
+        settings: &ControllerSettings,
+        target: Pose,
+    ) -> Pose {
+        let (min_height, max_height) = MAP_HEIGHT_RANGE;
+        self.height =
+            (self.height * MAP_ZOOM_FACTOR.powf(input.scroll_lines)).clamp(min_height, max_height);
+
+        // Pan faster when zoomed out, so it always takes about as long to cross the screen
+        let movement = input.get_movement() * Vec3::new(1., 0., 1.);
+        self.offset += movement * dt * settings.movement_speed * self.height / DEFAULT_MAP_HEIGHT;
+
+        Pose {
+            position: target.position * Vec3::new(1., 0., 1.) + self.offset + Vec3::Y * self.height,
+            rotation: Quat::from_rotation_x(-FRAC_PI_2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk;
+    use winit::{event::ElementState, keyboard::KeyCode};
+
+    use super::*;
+    use crate::{
+        graphics::Camera,
+        input::{Button, InputEvent},
+        time::TICK_DURATION,
+    };
+
+    const EPSILON: f32 = 1e-3;
+
+    fn target() -> Pose {
+        Pose {
+            position: Vec3::new(5., 1., -3.),
+            rotation: Quat::from_rotation_y(0.6),
+        }
+    }
+
+    fn press(input: &mut Input, key_code: KeyCode) {
+        input.handle_event(InputEvent::Button {
+            button: Button::Key(key_code),
+            state: ElementState::Pressed,
+        });
+    }
+
+    fn forward(pose: Pose) -> Vec3 {
+        pose.rotation * Vec3::NEG_Z
+    }
+
+    fn is_finite(pose: Pose) -> bool {
+        pose.position.is_finite() && pose.rotation.is_finite()
+    }
+
+    /// Runs `ticks` ticks with `input`, which only has its per-tick state cleared after the first.
+    fn run(
+        controller: &mut dyn CameraController,
+        input: &mut Input,
+        target: Pose,
+        ticks: u32,
+    ) -> Pose {
+        let mut pose = None;
+        for _ in 0..ticks {
+            pose = Some(controller.update(
+                TICK_DURATION,
+                input,
+                &ControllerSettings::default(),
+                target,
+            ));
+            input.end_tick();
+        }
+        pose.unwrap()
+    }
+
+    #[test]
+    fn fly_moves_the_way_it_faces() {
+        let from = Pose {
+            position: Vec3::new(0., 10., 10.),
+            rotation: Quat::from_rotation_y(FRAC_PI_2),
+        };
+        let mut controller = FlyController::new(from);
+
+        let pose = run(&mut controller, &mut Input::default(), target(), 10);
+        assert!(pose.position.distance(from.position) < EPSILON, "{pose:?}");
+
+        // Facing -X
+        let mut input = Input::default();
+        press(&mut input, KeyCode::KeyW);
+        let pose = run(&mut controller, &mut input, target(), 60);
+        assert!(pose.position.x < from.position.x - 5., "{pose:?}");
+        assert!(
+            (pose.position.z - from.position.z).abs() < EPSILON,
+            "{pose:?}"
+        );
+    }
+
+    #[test]
+    fn fly_turns_with_the_mouse() {
+        let from = Pose {
+            position: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+        };
+        let mut controller = FlyController::new(from);
+
+        let mut input = Input::default();
+        input.handle_event(InputEvent::MouseMotion { x: 90., y: 0. });
+        let pose = run(&mut controller, &mut input, target(), 120);
+
+        // Turned right, without pitching
+        assert!(forward(pose).x > 0.5, "{pose:?}");
+        assert!(forward(pose).y.abs() < EPSILON, "{pose:?}");
+    }
+
+    #[test]
+    fn orbit_starts_where_the_camera_was_looking_at_the_target() {
+        let from = Pose {
+            position: Vec3::new(-5., 9., 12.),
+            rotation: Quat::IDENTITY,
+        };
+        let target = target();
+        let mut controller = OrbitController::new(from, target);
+
+        let pose = run(&mut controller, &mut Input::default(), target, 1);
+        assert!(pose.position.distance(from.position) < EPSILON, "{pose:?}");
+        let to_target = (target.position - pose.position).normalize();
+        assert!(forward(pose).dot(to_target) > 1. - EPSILON, "{pose:?}");
+    }
+
+    #[test]
+    fn orbit_zoom_is_clamped() {
+        let from = Pose {
+            position: Vec3::new(0., 10., 10.),
+            rotation: Quat::IDENTITY,
+        };
+        let target = target();
+        let mut controller = OrbitController::new(from, target);
+        let (min_distance, max_distance) = ORBIT_DISTANCE_RANGE;
+
+        for (lines, expected) in [(100., min_distance), (-100., max_distance)] {
+            let mut input = Input::default();
+            input.handle_event(InputEvent::Scroll { lines });
+            let pose = run(&mut controller, &mut input, target, 1);
+            let distance = pose.position.distance(target.position);
+            assert!((distance - expected).abs() < EPSILON, "{distance}");
+        }
+    }
+
+    #[test]
+    fn chase_settles_behind_the_target() {
+        let target = target();
+        let mut controller = ChaseController::new(target);
+
+        let pose = run(&mut controller, &mut Input::default(), target, 600);
+        let expected = target.position + target.rotation * CHASE_OFFSET;
+        assert!(pose.position.distance(expected) < 0.01, "{pose:?}");
+        let to_target = (target.position + Vec3::Y - pose.position).normalize();
+        assert!(forward(pose).dot(to_target) > 1. - EPSILON, "{pose:?}");
+    }
+
+    #[test]
+    fn cab_sits_in_the_cab_facing_forwards() {
+        let target = target();
+        let mut controller = CabController::default();
+
+        let pose = run(&mut controller, &mut Input::default(), target, 1);
+        assert_eq!(
+            pose.position,
+            target.position + target.rotation * CAB_POSITION
+        );
+        assert!(
+            forward(pose).dot(forward(target)) > 1. - EPSILON,
+            "{pose:?}"
+        );
+    }
+
+    #[test]
+    fn cab_looking_around_is_limited() {
+        let mut controller = CabController::default();
+        let mut input = Input::default();
+        input.handle_event(InputEvent::MouseMotion {
+            x: -10000.,
+            y: 10000.,
+        });
+        run(&mut controller, &mut input, target(), 1);
+
+        assert_eq!(controller.yaw_degrees, CAB_YAW_LIMIT);
+        assert_eq!(controller.pitch_degrees, -CAB_PITCH_LIMIT);
+    }
+
+    #[test]
+    fn map_looks_straight_down_on_the_target() {
+        let target = target();
+        let mut controller = MapController::default();
+
+        let pose = run(&mut controller, &mut Input::default(), target, 1);
+        let expected = Vec3::new(target.position.x, DEFAULT_MAP_HEIGHT, target.position.z);
+        assert!(pose.position.distance(expected) < EPSILON, "{pose:?}");
+        assert!(forward(pose).distance(Vec3::NEG_Y) < EPSILON, "{pose:?}");
+
+        // -Z is at the top of the screen, so moving forward pans that way
+        let mut input = Input::default();
+        press(&mut input, KeyCode::KeyW);
+        let pose = run(&mut controller, &mut input, target, 10);
+        assert!(pose.position.z < expected.z, "{pose:?}");
+        assert_eq!(pose.position.y, DEFAULT_MAP_HEIGHT);
+    }
+
+    #[test]
+    fn map_zoom_is_clamped() {
+        let mut controller = MapController::default();
+        let (min_height, max_height) = MAP_HEIGHT_RANGE;
+
+        for (lines, expected) in [(100., min_height), (-100., max_height)] {
+            let mut input = Input::default();
+            input.handle_event(InputEvent::Scroll { lines });
+            run(&mut controller, &mut input, target(), 1);
+            assert_eq!(controller.height, expected);
+        }
+    }
+
+    #[test]
+    fn orbit_from_the_map_keeps_looking_down() {
+        let target = target();
+        let mut map = MapController::default();
+        let from = run(&mut map, &mut Input::default(), target, 1);
+
+        let mut controller = OrbitController::new(from, target);
+        let pose = run(&mut controller, &mut Input::default(), target, 1);
+        assert!(is_finite(pose), "{pose:?}");
+
+        // Orbiting stops a fraction short of vertical
+        assert!(pose.position.distance(from.position) < 0.1, "{pose:?}");
+        assert!(forward(pose).distance(Vec3::NEG_Y) < 0.01, "{pose:?}");
+    }
+
+    #[test]
+    fn fly_from_an_orbit_starts_where_it_was() {
+        let target = target();
+        let from = Pose {
+            position: Vec3::new(-5., 9., 12.),
+            rotation: Quat::IDENTITY,
+        };
+        let mut orbit = OrbitController::new(from, target);
+        let from = run(&mut orbit, &mut Input::default(), target, 1);
+
+        let mut controller = FlyController::new(from);
+        let pose = run(&mut controller, &mut Input::default(), target, 1);
+        assert!(pose.position.distance(from.position) < EPSILON, "{pose:?}");
+        assert!(
+            pose.rotation.dot(from.rotation).abs() > 1. - EPSILON,
+            "{pose:?}"
+        );
+    }
+
+    #[test]
+    fn switching_between_every_controller_keeps_the_camera_finite() {
+        let target = target();
+        let mut camera = Camera::new(vk::Extent2D {
+            width: 1280,
+            height: 720,
+        });
+
+        // Map then orbit without panning is straight above the target, the awkward case
+        for key_code in [
+            KeyCode::Digit5,
+            KeyCode::Digit2,
+            KeyCode::Digit1,
+            KeyCode::Digit3,
+            KeyCode::Digit4,
+            KeyCode::Digit2,
+            KeyCode::Digit5,
+            KeyCode::Digit1,
+        ] {
+            let mut input = Input::default();
+            press(&mut input, key_code);
+            for _ in 0..60 {
+                camera.update(TICK_DURATION, &input, target);
+                input.end_tick();
+            }
+            camera.interpolate(1.);
+
+            assert!(
+                camera.position().is_finite() && camera.rotation().is_finite(),
+                "after {key_code:?}: {:?} {:?}",
+                camera.position(),
+                camera.rotation()
+            );
+        }
+    }
+}
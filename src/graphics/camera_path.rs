@@ -0,0 +1,474 @@
+use std::{f32::consts::TAU, fmt, path::Path};
+
+use glam::{Mat4, Quat, Vec3};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    camera::Pose,
+    camera_controller::{CameraController, ControllerKind, ControllerSettings},
+};
+use crate::input::Input;
+
+/// How often a keyframe is taken while recording, in seconds.
+const KEYFRAME_INTERVAL: f32 = 0.25;
+/// A path needs a start and an end.
+const MIN_KEYFRAMES: usize = 2;
+
+/// Where the camera should be at a given time.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Keyframe {
+    /// Seconds since the start of the path.
+    pub time: f32,
+    pub position: Vec3,
+    pub rotation: Quat,
+}
+
+/// A smooth path for the camera through a list of keyframes, for fly-throughs. Positions follow a
+/// Catmull-Rom spline and rotations use squad, so both move smoothly through each keyframe.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CameraPath {
+    /// In order of time.
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> Result<Self, CameraPathError> {
+        let contents = std::fs::read_to_string(path).map_err(CameraPathError::Io)?;
+        Self::from_ron(&contents)
+    }
+
+    /// Parses a path as it's written in a file, putting its keyframes in order.
+    pub fn from_ron(contents: &str) -> Result<Self, CameraPathError> {
+        let mut camera_path: CameraPath =
+            ron::from_str(contents).map_err(|e| CameraPathError::Parse(Box::new(e)))?;
+        if camera_path.keyframes.len() < MIN_KEYFRAMES {
+            return Err(CameraPathError::TooFewKeyframes(
+                camera_path.keyframes.len(),
+            ));
+        }
+
+        camera_path
+            .keyframes
+            .sort_by(|a, b| a.time.total_cmp(&b.time));
+        camera_path.align_rotations();
+        Ok(camera_path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), CameraPathError> {
+        let contents = ron::ser::to_string_pretty(self, Default::default())
+            .map_err(CameraPathError::Serialise)?;
+        std::fs::write(path, contents).map_err(CameraPathError::Io)
+    }
+
+    /// A path that circles once around `centre` at the given radius and height, always looking
+    /// at it, taking `duration` seconds.
+    pub fn orbit(centre: Vec3, radius: f32, height: f32, duration: f32) -> Self {
+        const KEYFRAME_COUNT: u32 = 16;
+
+        let keyframes = (0..=KEYFRAME_COUNT)
+            .map(|i| {
+                let t = i as f32 / KEYFRAME_COUNT as f32;
+                let angle = t * TAU;
+                let position =
+                    centre + Vec3::new(angle.cos() * radius, height, angle.sin() * radius);
+                let view_from_world = Mat4::look_at_rh(position, centre, Vec3::Y);
+
+                Keyframe {
+                    time: t * duration,
+                    position,
+                    rotation: Quat::from_mat4(&view_from_world.inverse()),
+                }
+            })
+            .collect();
+
+        let mut path = CameraPath { keyframes };
+        path.align_rotations();
+        path
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0., |keyframe| keyframe.time)
+    }
+
+    /// Returns the pose `time` seconds along the path, clamped to its ends.
+    pub fn sample(&self, time: f32) -> Option<Pose> {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len().checked_sub(1)?;
+
+        if time <= keyframes[0].time {
+            return Some(keyframes[0].pose());
+        }
+        if time >= keyframes[last].time {
+            return Some(keyframes[last].pose());
+        }
+
+        // Find the segment containing `time`, between keyframes `i` and `i + 1`
+        let i = keyframes.partition_point(|keyframe| keyframe.time <= time) - 1;
+
+        let (k1, k2) = (&keyframes[i], &keyframes[i + 1]);
+        let duration = k2.time - k1.time;
+        if duration <= 0. {
+            return Some(k2.pose());
+        }
+        let t = (time - k1.time) / duration;
+
+        // Cubic Hermite, with Catmull-Rom tangents that account for uneven keyframe spacing
+        let tangent = |i: usize| {
+            let before = &keyframes[i.saturating_sub(1)];
+            let after = &keyframes[(i + 1).min(last)];
+            let span = after.time - before.time;
+            if span > 0. {
+                (after.position - before.position) / span
+            } else {
+                Vec3::ZERO
+            }
+        };
+        let t2 = t * t;
+        let t3 = t2 * t;
+        let position = (2. * t3 - 3. * t2 + 1.) * k1.position
+            + (t3 - 2. * t2 + t) * duration * tangent(i)
+            + (-2. * t3 + 3. * t2) * k2.position
+            + (t3 - t2) * duration * tangent(i + 1);
+
+        let (q1, q2) = (k1.rotation, k2.rotation);
+        let (_, s1) = self.squad_control_points(i);
+        let (s2, _) = self.squad_control_points(i + 1);
+        let rotation = q1.slerp(q2, t).slerp(s1.slerp(s2, t), 2. * t * (1. - t));
+
+        Some(Pose { position, rotation })
+    }
+
+    /// Flips rotations where needed so each is in the same hemisphere as the one before it, and
+    /// interpolating between them takes the short way around.
+    fn align_rotations(&mut self) {
+        for i in 1..self.keyframes.len() {
+            let previous = self.keyframes[i - 1].rotation;
+            let rotation = &mut self.keyframes[i].rotation;
+            if previous.dot(*rotation) < 0. {
+                *rotation = -*rotation;
+            }
+        }
+    }
+
+    /// The inner control points for squad at keyframe `i`: one for the segment ending there and one
+    /// for the segment starting there. They're placed so the angular velocity is the same on both
+    /// sides, however unevenly the keyframes are spaced, just like the positions' tangents.
+    fn squad_control_points(&self, i: usize) -> (Quat, Quat) {
+        let keyframes = &self.keyframes;
+        let last = keyframes.len() - 1;
+        let keyframe = &keyframes[i];
+        let before = &keyframes[i.saturating_sub(1)];
+        let after = &keyframes[(i + 1).min(last)];
+
+        // Everything is in the tangent space at this keyframe's rotation
+        let inverse = keyframe.rotation.inverse();
+        let to_before = quat_log(inverse * before.rotation);
+        let to_after = quat_log(inverse * after.rotation);
+        let (time_before, time_after) = (keyframe.time - before.time, after.time - keyframe.time);
+        let span = time_before + time_after;
+        let velocity = if span > 0. {
+            (to_after - to_before) / span
+        } else {
+            Vec3::ZERO
+        };
+
+        let incoming = -(velocity * time_before + to_before) / 2.;
+        let outgoing = (velocity * time_after - to_after) / 2.;
+        (
+            (keyframe.rotation * quat_exp(incoming)).normalize(),
+            (keyframe.rotation * quat_exp(outgoing)).normalize(),
+        )
+    }
+}
+
+impl Keyframe {
+    fn pose(&self) -> Pose {
+        Pose {
+            position: self.position,
+            rotation: self.rotation,
+        }
+    }
+}
+
+/// The logarithm of a unit quaternion: its rotation axis scaled by half its angle.
+fn quat_log(q: Quat) -> Vec3 {
+    let v = Vec3::new(q.x, q.y, q.z);
+    let length = v.length();
+    if length < 1e-6 {
+        return v;
+    }
+    v / length * length.atan2(q.w)
+}
+
+/// The inverse of [`quat_log`].
+fn quat_exp(v: Vec3) -> Quat {
+    let angle = v.length();
+    if angle < 1e-6 {
+        return Quat::from_xyzw(v.x, v.y, v.z, 1.).normalize();
+    }
+    let v = v / angle * angle.sin();
+    Quat::from_xyzw(v.x, v.y, v.z, angle.cos())
+}
+
+/// Takes keyframes of the camera's pose at regular intervals.
+#[derive(Debug, Default)]
+pub(crate) struct PathRecorder {
+    path: CameraPath,
+    elapsed: f32,
+    /// Time since the last keyframe was taken.
+    since_keyframe: f32,
+    /// The most recent pose, which becomes the final keyframe.
+    latest: Option<Keyframe>,
+}
+
+impl PathRecorder {
+    pub fn record(&mut self, dt: f32, pose: Pose) {
+        let keyframe = Keyframe {
+            time: self.elapsed,
+            position: pose.position,
+            rotation: pose.rotation,
+        };
+        if self.path.keyframes.is_empty() || self.since_keyframe >= KEYFRAME_INTERVAL {
+            self.path.keyframes.push(keyframe);
+            self.since_keyframe = 0.;
+        }
+        self.latest = Some(keyframe);
+
+        self.elapsed += dt;
+        self.since_keyframe += dt;
+    }
+
+    pub fn finish(mut self) -> CameraPath {
+        if let Some(latest) = self
+            .latest
+            .filter(|latest| latest.time > self.path.duration())
+        {
+            self.path.keyframes.push(latest);
+        }
+        self.path.align_rotations();
+        self.path
+    }
+}
+
+/// Plays back a [`CameraPath`], then holds the final pose.
+#[derive(Debug)]
+pub(crate) struct PathController {
+    path: CameraPath,
+    time: f32,
+}
+
+impl PathController {
+    pub fn new(path: CameraPath) -> Self {
+        Self { path, time: 0. }
+    }
+}
+
+impl CameraController for PathController {
+    fn kind(&self) -> Option<ControllerKind> {
+        None
+    }
+
+    fn update(
+        &mut self,
+        dt: f32,
+        _input: &Input,
+        _settings: &ControllerSettings,
+        target: Pose,
+    ) -> Pose {
+        let pose = self.path.sample(self.time).unwrap_or(target);
+        self.time += dt;
+        pose
+    }
+}
+
+#[derive(Debug)]
+pub enum CameraPathError {
+    Io(std::io::Error),
+    Parse(Box<ron::error::SpannedError>),
+    Serialise(ron::Error),
+    /// The path has fewer than two keyframes, so there's nowhere to go.
+    TooFewKeyframes(usize),
+}
+
+impl fmt::Display for CameraPathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CameraPathError::Io(e) => write!(f, "{e}"),
+            CameraPathError::Parse(e) => write!(f, "{e}"),
+            CameraPathError::Serialise(e) => write!(f, "{e}"),
+            CameraPathError::TooFewKeyframes(count) => write!(
+                f,
+                "a path needs at least {MIN_KEYFRAMES} keyframes, but this has {count}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CameraPathError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn keyframe(time: f32, position: Vec3, yaw_degrees: f32) -> Keyframe {
+        Keyframe {
+            time,
+            position,
+            rotation: Quat::from_rotation_y(yaw_degrees.to_radians()),
+        }
+    }
+
+    /// An uneven path that turns as it goes, so neither the spline nor squad is trivial.
+    fn path() -> CameraPath {
+        let mut path = CameraPath {
+            keyframes: vec![
+                keyframe(0., Vec3::ZERO, 0.),
+                keyframe(1., Vec3::new(4., 1., 0.), 30.),
+                keyframe(1.5, Vec3::new(6., 0., -3.), 100.),
+                keyframe(3., Vec3::new(2., 2., -8.), 170.),
+            ],
+        };
+        path.align_rotations();
+        path
+    }
+
+    fn assert_pose_eq(pose: Pose, keyframe: &Keyframe) {
+        assert!(
+            pose.position.abs_diff_eq(keyframe.position, EPSILON),
+            "{pose:?} != {keyframe:?}"
+        );
+        assert!(
+            pose.rotation.abs_diff_eq(keyframe.rotation, EPSILON),
+            "{pose:?} != {keyframe:?}"
+        );
+    }
+
+    #[test]
+    fn passes_through_each_keyframe() {
+        let path = path();
+        for keyframe in &path.keyframes {
+            assert_pose_eq(path.sample(keyframe.time).unwrap(), keyframe);
+        }
+    }
+
+    #[test]
+    fn holds_the_end_poses_outside_the_path() {
+        let path = path();
+        let keyframes = &path.keyframes;
+        assert_pose_eq(path.sample(-1.).unwrap(), &keyframes[0]);
+        assert_pose_eq(path.sample(10.).unwrap(), keyframes.last().unwrap());
+    }
+
+    #[test]
+    fn is_smooth_through_each_keyframe() {
+        let path = path();
+        let h = 1e-3;
+
+        // The velocity either side of an inner keyframe should match
+        for keyframe in &path.keyframes[1..path.keyframes.len() - 1] {
+            let at = |time: f32| path.sample(time).unwrap();
+            let t = keyframe.time;
+
+            let velocity_before = (at(t).position - at(t - h).position) / h;
+            let velocity_after = (at(t + h).position - at(t).position) / h;
+            assert!(
+                velocity_before.abs_diff_eq(velocity_after, 0.05),
+                "{velocity_before} != {velocity_after} at {t}"
+            );
+
+            // `angle_between` loses too much precision over such small angles
+            let angular_velocity = |from: Quat, to: Quat| 2. * quat_log(from.inverse() * to) / h;
+            let angular_before = angular_velocity(at(t - h).rotation, at(t).rotation);
+            let angular_after = angular_velocity(at(t).rotation, at(t + h).rotation);
+            assert!(
+                angular_before.abs_diff_eq(angular_after, 0.05),
+                "{angular_before} != {angular_after} at {t}"
+            );
+        }
+    }
+
+    #[test]
+    fn squad_stays_between_the_end_keyframes() {
+        let path = path();
+        let keyframes = &path.keyframes;
+
+        // At the ends, the missing neighbour is the end keyframe itself, so squad shouldn't
+        // overshoot the rotation either side of the first and last segments
+        let first = path.sample(0.5).unwrap().rotation;
+        let (q0, q1) = (keyframes[0].rotation, keyframes[1].rotation);
+        assert!(first.angle_between(q0) < q0.angle_between(q1));
+        assert!(first.angle_between(q1) < q0.angle_between(q1));
+
+        let last = path.sample(2.25).unwrap().rotation;
+        let (q2, q3) = (keyframes[2].rotation, keyframes[3].rotation);
+        assert!(last.angle_between(q2) < q2.angle_between(q3));
+        assert!(last.angle_between(q3) < q2.angle_between(q3));
+    }
+
+    #[test]
+    fn rotations_take_the_short_way_round() {
+        let mut path = CameraPath {
+            keyframes: vec![keyframe(0., Vec3::ZERO, 0.), keyframe(1., Vec3::ZERO, 10.)],
+        };
+        path.keyframes[1].rotation = -path.keyframes[1].rotation;
+        path.align_rotations();
+
+        let halfway = path.sample(0.5).unwrap().rotation;
+        let expected = Quat::from_rotation_y(5f32.to_radians());
+        assert!(halfway.angle_between(expected) < EPSILON);
+    }
+
+    #[test]
+    fn from_ron_sorts_keyframes() {
+        let path = CameraPath::from_ron(
+            "(keyframes: [
+                (time: 2.0, position: (2.0, 0.0, 0.0), rotation: (0.0, 0.0, 0.0, 1.0)),
+                (time: 0.0, position: (0.0, 0.0, 0.0), rotation: (0.0, 0.0, 0.0, 1.0)),
+            ])",
+        )
+        .unwrap();
+
+        assert_eq!(path.keyframes[0].time, 0.);
+        assert_eq!(path.duration(), 2.);
+    }
+
+    #[test]
+    fn from_ron_rejects_paths_with_fewer_than_two_keyframes() {
+        let error = CameraPath::from_ron("(keyframes: [])").unwrap_err();
+        assert!(matches!(error, CameraPathError::TooFewKeyframes(0)));
+
+        let error = CameraPath::from_ron(
+            "(keyframes: [(time: 0.0, position: (0.0, 0.0, 0.0), rotation: (0.0, 0.0, 0.0, 1.0))])",
+        )
+        .unwrap_err();
+        assert!(matches!(error, CameraPathError::TooFewKeyframes(1)));
+        assert_eq!(
+            error.to_string(),
+            "a path needs at least 2 keyframes, but this has 1"
+        );
+    }
+
+    #[test]
+    fn recording_takes_keyframes_at_intervals_and_ends_on_the_latest_pose() {
+        let mut recorder = PathRecorder::default();
+        let dt = 0.1;
+        for i in 0..11 {
+            recorder.record(
+                dt,
+                Pose {
+                    position: Vec3::X * i as f32,
+                    rotation: Quat::IDENTITY,
+                },
+            );
+        }
+        let path = recorder.finish();
+
+        let times: Vec<f32> = path.keyframes.iter().map(|k| k.time).collect();
+        assert_eq!(times.len(), 5, "{times:?}");
+        assert!((path.duration() - 1.).abs() < EPSILON);
+        assert_eq!(path.keyframes.last().unwrap().position, Vec3::X * 10.);
+    }
+}
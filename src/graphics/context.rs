@@ -1,32 +1,68 @@
 use ash::vk::{self, MemoryRequirements};
+use tracing::info;
 
 use super::core::Core;
 
+/// Everything is drawn with the first queue family, which is where every driver puts graphics.
+const GRAPHICS_QUEUE_FAMILY: u32 = 0;
+
 pub struct Context {
-    pub device: ash::Device,
+    pub(crate) device: ash::Device,
     #[allow(unused)]
-    pub command_pool: vk::CommandPool,
-    pub draw_command_buffer: vk::CommandBuffer,
-    pub graphics_queue: vk::Queue,
-    pub memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub(crate) command_pool: vk::CommandPool,
+    pub(crate) draw_command_buffer: vk::CommandBuffer,
+    pub(crate) graphics_queue: vk::Queue,
+    pub(crate) graphics_queue_family: u32,
+    /// Uploads go through this queue. It's from a family of its own where the device has one,
+    /// so copies can run alongside rendering, and is the graphics queue otherwise.
+    pub(crate) transfer_queue: vk::Queue,
+    pub(crate) transfer_queue_family: u32,
+    pub(crate) memory_properties: vk::PhysicalDeviceMemoryProperties,
+    pub(crate) supported_sample_counts: vk::SampleCountFlags,
+    pub(crate) timestamp_period: Option<f32>,
 }
 
 impl Context {
-    pub(crate) fn new(core: &Core) -> Self {
+    pub fn new(core: &Core) -> Self {
         let instance = &core.instance;
         let physical_device = core.physical_device;
 
+        let queue_families =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+        let transfer_queue_family = find_transfer_queue_family(&queue_families);
+
+        let priorities = [1.0];
+        let mut queue_create_infos = vec![vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(GRAPHICS_QUEUE_FAMILY)
+            .queue_priorities(&priorities)];
+        if let Some(family) = transfer_queue_family {
+            queue_create_infos.push(
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(family)
+                    .queue_priorities(&priorities),
+            );
+        }
+
+        // Swapchains need the surface extensions, which a headless core doesn't have
+        let swapchain_extension = [ash::khr::swapchain::NAME.as_ptr()];
+        let extensions: &[_] = if core.can_present {
+            &swapchain_extension
+        } else {
+            &[]
+        };
+
         let device = unsafe {
             instance.create_device(
                 physical_device,
                 &vk::DeviceCreateInfo::default()
-                    .enabled_extension_names(&[ash::khr::swapchain::NAME.as_ptr()])
-                    .queue_create_infos(&[vk::DeviceQueueCreateInfo::default()
-                        .queue_family_index(0)
-                        .queue_priorities(&[1.0])])
+                    .enabled_extension_names(extensions)
+                    .queue_create_infos(&queue_create_infos)
                     .enabled_features(
                         &vk::PhysicalDeviceFeatures::default().fill_mode_non_solid(true),
                     )
+                    .push_next(
+                        &mut vk::PhysicalDeviceVulkan12Features::default().timeline_semaphore(true),
+                    )
                     .push_next(
                         &mut vk::PhysicalDeviceVulkan13Features::default()
                             .dynamic_rendering(true)
@@ -40,7 +76,7 @@ impl Context {
         let command_pool = unsafe {
             device.create_command_pool(
                 &vk::CommandPoolCreateInfo::default()
-                    .queue_family_index(0)
+                    .queue_family_index(GRAPHICS_QUEUE_FAMILY)
                     .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
                 None,
             )
@@ -56,35 +92,167 @@ impl Context {
         }
         .unwrap()[0];
 
-        let graphics_queue = unsafe { device.get_device_queue(0, 0) };
+        let graphics_queue = unsafe { device.get_device_queue(GRAPHICS_QUEUE_FAMILY, 0) };
+        let transfer_queue = match transfer_queue_family {
+            Some(family) => unsafe { device.get_device_queue(family, 0) },
+            None => graphics_queue,
+        };
+        let transfer_queue_family = transfer_queue_family.unwrap_or(GRAPHICS_QUEUE_FAMILY);
 
         let memory_properties =
             unsafe { instance.get_physical_device_memory_properties(physical_device) };
 
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        let supported_sample_counts =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+
+        let queue_family = queue_families[GRAPHICS_QUEUE_FAMILY as usize];
+        let timestamp_period =
+            (queue_family.timestamp_valid_bits != 0).then_some(limits.timestamp_period);
+
+        info!(
+            queue_family = GRAPHICS_QUEUE_FAMILY,
+            queue_flags = ?queue_family.queue_flags,
+            transfer_queue_family,
+            sample_counts = ?supported_sample_counts,
+            timestamps = timestamp_period.is_some(),
+            "Created device"
+        );
+
         Self {
             device,
             command_pool,
             draw_command_buffer,
             graphics_queue,
+            graphics_queue_family: GRAPHICS_QUEUE_FAMILY,
+            transfer_queue,
+            transfer_queue_family,
             memory_properties,
+            supported_sample_counts,
+            timestamp_period,
         }
     }
 
+    pub fn device(&self) -> &ash::Device {
+        &self.device
+    }
+
+    pub fn graphics_queue(&self) -> vk::Queue {
+        self.graphics_queue
+    }
+
+    pub fn graphics_queue_family(&self) -> u32 {
+        self.graphics_queue_family
+    }
+
+    /// The queue uploads go through, which is the graphics queue unless
+    /// [`Context::has_transfer_queue`].
+    pub fn transfer_queue(&self) -> vk::Queue {
+        self.transfer_queue
+    }
+
+    pub fn transfer_queue_family(&self) -> u32 {
+        self.transfer_queue_family
+    }
+
+    /// The MSAA sample counts usable for both colour and depth attachments.
+    pub fn supported_sample_counts(&self) -> vk::SampleCountFlags {
+        self.supported_sample_counts
+    }
+
+    /// How many nanoseconds each tick of a timestamp query takes, or `None` if the graphics queue
+    /// doesn't support timestamps.
+    pub fn timestamp_period(&self) -> Option<f32> {
+        self.timestamp_period
+    }
+
+    /// Whether uploads go through a queue of their own, so images must be handed over to the
+    /// graphics queue once they're written.
+    pub fn has_transfer_queue(&self) -> bool {
+        self.transfer_queue_family != self.graphics_queue_family
+    }
+
     pub fn find_memory_type_index(
         &self,
         requirements: &MemoryRequirements,
         required_properties: vk::MemoryPropertyFlags,
     ) -> Option<u32> {
         let mem_props = self.memory_properties;
-        for i in 0..mem_props.memory_type_count {
-            if (requirements.memory_type_bits & (1 << i)) != 0
+        (0..mem_props.memory_type_count).find(|&i| {
+            (requirements.memory_type_bits & (1 << i)) != 0
                 && mem_props.memory_types[i as usize]
                     .property_flags
                     .contains(required_properties)
-            {
-                return Some(i);
-            }
-        }
-        None
+        })
+    }
+}
+
+/// Finds a queue family for uploads other than the graphics family, preferring ones that can only
+/// transfer, as they're usually backed by dedicated copy engines. Returns `None` on devices with
+/// just the one family, such as lavapipe.
+fn find_transfer_queue_family(families: &[vk::QueueFamilyProperties]) -> Option<u32> {
+    let candidates = || {
+        (0..families.len() as u32).filter(|&family| {
+            let flags = families[family as usize].queue_flags;
+            family != GRAPHICS_QUEUE_FAMILY
+                && flags.contains(vk::QueueFlags::TRANSFER)
+                && !flags.contains(vk::QueueFlags::GRAPHICS)
+        })
+    };
+
+    candidates()
+        .find(|&family| {
+            !families[family as usize]
+                .queue_flags
+                .contains(vk::QueueFlags::COMPUTE)
+        })
+        .or_else(|| candidates().next())
+}
+
+#[cfg(test)]
+mod tests {
+    use vk::QueueFlags as Flags;
+
+    use super::*;
+
+    fn families(flags: &[vk::QueueFlags]) -> Vec<vk::QueueFamilyProperties> {
+        flags
+            .iter()
+            .map(|&queue_flags| vk::QueueFamilyProperties {
+                queue_flags,
+                queue_count: 1,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn graphics_only_devices_have_no_transfer_family() {
+        let everything = Flags::GRAPHICS | Flags::COMPUTE | Flags::TRANSFER;
+        assert_eq!(find_transfer_queue_family(&families(&[everything])), None);
+        assert_eq!(
+            find_transfer_queue_family(&families(&[everything, everything])),
+            None
+        );
+    }
+
+    #[test]
+    fn dedicated_transfer_families_are_preferred() {
+        let families = families(&[
+            Flags::GRAPHICS | Flags::COMPUTE | Flags::TRANSFER,
+            Flags::COMPUTE | Flags::TRANSFER,
+            Flags::TRANSFER | Flags::SPARSE_BINDING,
+        ]);
+        assert_eq!(find_transfer_queue_family(&families), Some(2));
+    }
+
+    #[test]
+    fn compute_families_are_used_without_a_dedicated_one() {
+        let families = families(&[
+            Flags::GRAPHICS | Flags::COMPUTE | Flags::TRANSFER,
+            Flags::COMPUTE,
+            Flags::COMPUTE | Flags::TRANSFER,
+        ]);
+        assert_eq!(find_transfer_queue_family(&families), Some(2));
     }
 }
@@ -1,25 +1,64 @@
+use std::ffi::c_char;
+
 use ash::vk;
+use tracing::{debug, info, warn};
 use winit::raw_window_handle::HasDisplayHandle;
 
 pub struct Core {
-    pub entry: ash::Entry,
-    pub instance: ash::Instance,
-    pub physical_device: vk::PhysicalDevice,
+    pub(crate) entry: ash::Entry,
+    pub(crate) instance: ash::Instance,
+    pub(crate) physical_device: vk::PhysicalDevice,
+    /// Whether the instance can make window surfaces, so devices can present to them.
+    pub(crate) can_present: bool,
 }
 impl Core {
-    pub(crate) fn new(window: &winit::window::Window) -> Self {
-        let entry = unsafe { ash::Entry::load().unwrap() };
-
+    /// Renders with the GPU matching `gpu`, as described in [`GraphicsSettings::gpu`].
+    ///
+    /// [`GraphicsSettings::gpu`]: super::GraphicsSettings::gpu
+    pub fn new(window: &winit::window::Window, gpu: Option<&str>) -> Self {
         let display_handle = window.display_handle().unwrap().as_raw();
 
         let instance_extensions =
             ash_window::enumerate_required_extensions(display_handle).unwrap();
 
+        Self::with_extensions(instance_extensions, true, gpu)
+    }
+
+    /// Creates a core that can only render offscreen, for when there's no window.
+    pub fn headless(gpu: Option<&str>) -> Self {
+        Self::with_extensions(&[], false, gpu)
+    }
+
+    pub fn entry(&self) -> &ash::Entry {
+        &self.entry
+    }
+
+    pub fn instance(&self) -> &ash::Instance {
+        &self.instance
+    }
+
+    /// The GPU that was picked to render with.
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
+    /// Whether this core was made for a window, rather than being [`Core::headless`].
+    pub fn can_present(&self) -> bool {
+        self.can_present
+    }
+
+    fn with_extensions(
+        instance_extensions: &[*const c_char],
+        can_present: bool,
+        gpu: Option<&str>,
+    ) -> Self {
+        let entry = unsafe { ash::Entry::load().unwrap() };
+
         let instance = unsafe {
             entry
                 .create_instance(
                     &vk::InstanceCreateInfo::default()
-                        .enabled_extension_names(&instance_extensions)
+                        .enabled_extension_names(instance_extensions)
                         .application_info(
                             &vk::ApplicationInfo::default()
                                 .api_version(vk::API_VERSION_1_3)
@@ -31,16 +70,71 @@ impl Core {
                 .unwrap()
         };
 
-        let physical_device = unsafe { instance.enumerate_physical_devices() }
-            .unwrap()
-            .first()
-            .copied()
+        debug!(
+            extensions = instance_extensions.len(),
+            "Created Vulkan 1.3 instance"
+        );
+
+        let physical_devices = unsafe { instance.enumerate_physical_devices() }.unwrap();
+        for (index, &physical_device) in physical_devices.iter().enumerate() {
+            let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+            debug!(
+                index,
+                name = ?properties.device_name_as_c_str().unwrap_or_default(),
+                device_type = ?properties.device_type,
+                "Found GPU"
+            );
+        }
+
+        let physical_device = gpu
+            .and_then(|gpu| {
+                let found = find_physical_device(&instance, &physical_devices, gpu);
+                if found.is_none() {
+                    warn!(gpu, "No GPU matches, using the first one");
+                }
+                found
+            })
+            .or(physical_devices.first().copied())
             .unwrap();
 
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        info!(
+            name = ?properties.device_name_as_c_str().unwrap_or_default(),
+            driver_version = properties.driver_version,
+            api_version = format!(
+                "{}.{}.{}",
+                vk::api_version_major(properties.api_version),
+                vk::api_version_minor(properties.api_version),
+                vk::api_version_patch(properties.api_version)
+            ),
+            "Using GPU"
+        );
+
         Self {
             entry,
             instance,
             physical_device,
+            can_present,
         }
     }
 }
+
+/// Finds the GPU at index `gpu` in `physical_devices`, or else the first whose name contains
+/// `gpu`, ignoring case.
+fn find_physical_device(
+    instance: &ash::Instance,
+    physical_devices: &[vk::PhysicalDevice],
+    gpu: &str,
+) -> Option<vk::PhysicalDevice> {
+    if let Ok(index) = gpu.parse::<usize>() {
+        return physical_devices.get(index).copied();
+    }
+
+    let gpu = gpu.to_lowercase();
+    physical_devices.iter().copied().find(|&physical_device| {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        properties
+            .device_name_as_c_str()
+            .is_ok_and(|name| name.to_string_lossy().to_lowercase().contains(&gpu))
+    })
+}
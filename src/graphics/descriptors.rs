@@ -0,0 +1,121 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{buffer::Buffer, context::Context, lighting::Globals};
+
+/// The descriptor set shared by every pipeline: the per-frame globals at binding 0 and the
+/// shadow map at binding 1.
+pub struct Descriptors {
+    pub layout: vk::DescriptorSetLayout,
+    pub set: vk::DescriptorSet,
+    pub globals: Buffer<Globals>,
+    #[allow(unused)]
+    pool: vk::DescriptorPool,
+}
+
+impl Descriptors {
+    pub(crate) fn new(
+        context: Arc<Context>,
+        shadow_map_view: vk::ImageView,
+        shadow_sampler: vk::Sampler,
+    ) -> Self {
+        let device = &context.device;
+        let stages = vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT;
+
+        let layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                        .descriptor_count(1)
+                        .stage_flags(stages),
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(1)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let pool = unsafe {
+            device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&[
+                        vk::DescriptorPoolSize::default()
+                            .ty(vk::DescriptorType::UNIFORM_BUFFER)
+                            .descriptor_count(1),
+                        vk::DescriptorPoolSize::default()
+                            .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                            .descriptor_count(1),
+                    ]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let set = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(&[layout]),
+            )
+        }
+        .unwrap()[0];
+
+        let globals = Buffer::<Globals>::new(context.clone(), vk::BufferUsageFlags::UNIFORM_BUFFER);
+
+        unsafe {
+            device.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(set)
+                        .dst_binding(0)
+                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                        .buffer_info(&[vk::DescriptorBufferInfo::default()
+                            .buffer(globals.handle)
+                            .range(globals.size())]),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(set)
+                        .dst_binding(1)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .image_info(&[vk::DescriptorImageInfo::default()
+                            .image_view(shadow_map_view)
+                            .sampler(shadow_sampler)
+                            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)]),
+                ],
+                &[],
+            )
+        };
+
+        Self {
+            layout,
+            set,
+            globals,
+            pool,
+        }
+    }
+
+    pub fn bind(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        pipeline_layout: vk::PipelineLayout,
+    ) {
+        unsafe {
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                0,
+                &[self.set],
+                &[],
+            )
+        };
+    }
+}
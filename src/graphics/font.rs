@@ -0,0 +1,355 @@
+use std::{collections::HashMap, ops::RangeInclusive};
+
+use ab_glyph::{Font, FontVec, GlyphId, InvalidFont, PxScale, ScaleFont};
+use glam::Vec2;
+
+use crate::assets::Asset;
+
+/// The size glyphs are rasterised at, in pixels from the highest ascender to the lowest
+/// descender. The atlas stores distances rather than coverage, so text stays sharp when it's
+/// drawn bigger than this.
+const ATLAS_FONT_SIZE: f32 = 48.;
+/// How far the distance field reaches either side of a glyph's outline, in atlas pixels. Each
+/// glyph is padded by this much.
+const SPREAD: usize = 6;
+const ATLAS_WIDTH: usize = 1024;
+/// The characters in the atlas: printable ASCII and Latin-1. Anything else is drawn as
+/// [`FALLBACK`].
+const CHARACTERS: [RangeInclusive<char>; 2] = [' '..='~', '\u{a0}'..='\u{ff}'];
+/// Drawn in place of any character that isn't in the atlas.
+const FALLBACK: char = '?';
+
+/// Where a glyph is in the atlas, and how to place it.
+#[derive(Debug, Clone, Copy)]
+struct AtlasGlyph {
+    id: GlyphId,
+    /// The top left of the glyph's padded bitmap relative to the pen on the baseline, in atlas
+    /// pixels with Y down.
+    offset: Vec2,
+    /// The size of the padded bitmap, in atlas pixels. Zero for glyphs with nothing to draw.
+    size: Vec2,
+    uv_min: Vec2,
+    uv_max: Vec2,
+    advance: f32,
+}
+
+/// One glyph of laid-out text: a rectangle relative to the top left of the text with Y down, and
+/// the part of the atlas to fill it with.
+#[derive(Debug, Clone, Copy)]
+pub struct GlyphQuad {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+}
+
+/// A signed distance field of every glyph in [`CHARACTERS`], packed into a single-channel image.
+/// Each texel is 0.5 on a glyph's outline, rising to 1 inside it and falling to 0 outside.
+pub struct FontAtlas {
+    font: FontVec,
+    glyphs: HashMap<char, AtlasGlyph>,
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    ascent: f32,
+    /// The distance from one baseline to the next, in atlas pixels.
+    line_height: f32,
+}
+
+impl Asset for FontAtlas {
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        FontAtlas::new(bytes).map_err(|e| e.to_string())
+    }
+}
+
+impl FontAtlas {
+    /// Rasterises every glyph of the TrueType or OpenType font in `data`.
+    pub fn new(data: Vec<u8>) -> Result<Self, InvalidFont> {
+        let font = FontVec::try_from_vec(data)?;
+        let scale = PxScale::from(ATLAS_FONT_SIZE);
+        let scaled = font.as_scaled(scale);
+
+        let mut glyphs = HashMap::new();
+        let mut bitmaps = Vec::new();
+
+        // Pack the glyphs into rows, left to right and then top to bottom
+        let (mut x, mut y, mut row_height) = (0, 0, 0);
+        for c in CHARACTERS.into_iter().flatten() {
+            let id = font.glyph_id(c);
+            let mut glyph = AtlasGlyph {
+                id,
+                offset: Vec2::ZERO,
+                size: Vec2::ZERO,
+                uv_min: Vec2::ZERO,
+                uv_max: Vec2::ZERO,
+                advance: scaled.h_advance(id),
+            };
+
+            if let Some(outline) =
+                font.outline_glyph(id.with_scale_and_position(scale, ab_glyph::point(0., 0.)))
+            {
+                let bounds = outline.px_bounds();
+                let width = bounds.width() as usize + 2 * SPREAD;
+                let height = bounds.height() as usize + 2 * SPREAD;
+
+                let mut coverage = vec![0.; width * height];
+                outline.draw(|gx, gy, c| {
+                    coverage[(gy as usize + SPREAD) * width + gx as usize + SPREAD] = c;
+                });
+
+                if x + width > ATLAS_WIDTH {
+                    (x, y, row_height) = (0, y + row_height, 0);
+                }
+                glyph.offset = Vec2::new(bounds.min.x, bounds.min.y) - SPREAD as f32;
+                glyph.size = Vec2::new(width as f32, height as f32);
+                glyph.uv_min = Vec2::new(x as f32, y as f32);
+                bitmaps.push((x, y, width, signed_distance_field(&coverage, width, height)));
+
+                x += width;
+                row_height = row_height.max(height);
+            }
+
+            glyphs.insert(c, glyph);
+        }
+
+        let atlas_height = (y + row_height).next_power_of_two();
+        let mut pixels = vec![0; ATLAS_WIDTH * atlas_height];
+        for (x, y, width, bitmap) in bitmaps {
+            for (row, texels) in bitmap.chunks_exact(width).enumerate() {
+                let start = (y + row) * ATLAS_WIDTH + x;
+                pixels[start..start + width].copy_from_slice(texels);
+            }
+        }
+
+        // Convert from pixels to texture coordinates, now the atlas's size is known
+        let atlas_size = Vec2::new(ATLAS_WIDTH as f32, atlas_height as f32);
+        for glyph in glyphs.values_mut() {
+            glyph.uv_max = (glyph.uv_min + glyph.size) / atlas_size;
+            glyph.uv_min /= atlas_size;
+        }
+
+        Ok(Self {
+            ascent: scaled.ascent(),
+            line_height: scaled.height() + scaled.line_gap(),
+            font,
+            glyphs,
+            pixels,
+            width: ATLAS_WIDTH as u32,
+            height: atlas_height as u32,
+        })
+    }
+
+    /// Lays out `text` with `size` units from the highest ascender to the lowest descender,
+    /// returning a quad for each visible glyph and the size of the whole block. Lines are broken
+    /// at each `\n`, and pairs of glyphs are kerned.
+    pub fn layout(&self, text: &str, size: f32) -> (Vec<GlyphQuad>, Vec2) {
+        let scaled = self.font.as_scaled(PxScale::from(ATLAS_FONT_SIZE));
+        let scale = size / ATLAS_FONT_SIZE;
+
+        let mut quads = Vec::with_capacity(text.len());
+        let mut pen = Vec2::new(0., self.ascent);
+        let mut width: f32 = 0.;
+        let mut previous = None;
+
+        for c in text.chars() {
+            if c == '\n' {
+                width = width.max(pen.x);
+                pen = Vec2::new(0., pen.y + self.line_height);
+                previous = None;
+                continue;
+            }
+
+            let Some(glyph) = self.glyphs.get(&c).or_else(|| self.glyphs.get(&FALLBACK)) else {
+                continue;
+            };
+            if let Some(previous) = previous {
+                pen.x += scaled.kern(previous, glyph.id);
+            }
+
+            if glyph.size != Vec2::ZERO {
+                let min = pen + glyph.offset;
+                quads.push(GlyphQuad {
+                    min: min * scale,
+                    max: (min + glyph.size) * scale,
+                    uv_min: glyph.uv_min,
+                    uv_max: glyph.uv_max,
+                });
+            }
+
+            pen.x += glyph.advance;
+            previous = Some(glyph.id);
+        }
+
+        let height = pen.y - self.ascent + self.line_height;
+        (quads, Vec2::new(width.max(pen.x), height) * scale)
+    }
+}
+
+/// Converts a glyph's coverage into a signed distance field, as stored in the atlas.
+fn signed_distance_field(coverage: &[f32], width: usize, height: usize) -> Vec<u8> {
+    let inside: Vec<bool> = coverage.iter().map(|&c| c >= 0.5).collect();
+    let outside: Vec<bool> = inside.iter().map(|&inside| !inside).collect();
+    let to_inside = squared_distance_transform(&inside, width, height);
+    let to_outside = squared_distance_transform(&outside, width, height);
+
+    (0..inside.len())
+        .map(|i| {
+            // The outline lies halfway between the nearest inside and outside pixel centres
+            let distance = if inside[i] {
+                to_outside[i].sqrt() - 0.5
+            } else {
+                0.5 - to_inside[i].sqrt()
+            };
+            let value = 0.5 + distance / (2 * SPREAD) as f64;
+            (value.clamp(0., 1.) * 255.).round() as u8
+        })
+        .collect()
+}
+
+/// Returns the squared distance from each pixel to the nearest one set in `features`, using
+/// Felzenszwalb and Huttenlocher's exact Euclidean distance transform: first down each column,
+/// then along each row.
+fn squared_distance_transform(features: &[bool], width: usize, height: usize) -> Vec<f64> {
+    const FAR: f64 = 1e20;
+
+    let mut distances: Vec<f64> = features
+        .iter()
+        .map(|&feature| if feature { 0. } else { FAR })
+        .collect();
+
+    let mut line = vec![0.; width.max(height)];
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = distances[y * width + x];
+        }
+        distance_transform_1d(&mut line[..height]);
+        for y in 0..height {
+            distances[y * width + x] = line[y];
+        }
+    }
+    for row in distances.chunks_exact_mut(width) {
+        distance_transform_1d(row);
+    }
+
+    distances
+}
+
+/// Replaces each value `f[q]` with the minimum over `p` of `(q - p)² + f[p]`, by finding the
+/// lower envelope of the parabolas rooted at each `p`.
+fn distance_transform_1d(f: &mut [f64]) {
+    let n = f.len();
+    if n == 0 {
+        return;
+    }
+
+    // The roots of the parabolas in the envelope, and the boundaries between them
+    let mut roots = vec![0; n];
+    let mut boundaries = vec![0.; n + 1];
+    let mut k = 0;
+    boundaries[0] = f64::NEG_INFINITY;
+    boundaries[1] = f64::INFINITY;
+
+    let intersection = |f: &[f64], q: usize, p: usize| {
+        let (fq, fp) = (f[q], f[p]);
+        let (q, p) = (q as f64, p as f64);
+        ((fq + q * q) - (fp + p * p)) / (2. * q - 2. * p)
+    };
+
+    for q in 1..n {
+        let mut s = intersection(f, q, roots[k]);
+        while s <= boundaries[k] {
+            k -= 1;
+            s = intersection(f, q, roots[k]);
+        }
+        k += 1;
+        roots[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = f64::INFINITY;
+    }
+
+    let envelope = f.to_vec();
+    k = 0;
+    for (q, value) in f.iter_mut().enumerate() {
+        while boundaries[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - roots[k] as f64;
+        *value = offset * offset + envelope[roots[k]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use super::*;
+
+    fn atlas() -> &'static FontAtlas {
+        static ATLAS: OnceLock<FontAtlas> = OnceLock::new();
+        ATLAS.get_or_init(|| {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/fonts/DejaVuSans.ttf");
+            FontAtlas::new(std::fs::read(path).unwrap()).unwrap()
+        })
+    }
+
+    #[test]
+    fn every_character_in_the_atlas_has_a_glyph() {
+        let atlas = atlas();
+        for c in CHARACTERS.into_iter().flatten() {
+            assert!(atlas.glyphs.contains_key(&c), "{c:?}");
+        }
+        assert!(atlas.height.is_power_of_two());
+    }
+
+    #[test]
+    fn characters_outside_the_atlas_are_drawn_as_the_fallback() {
+        let atlas = atlas();
+        for text in ["€", "Ω", "漢", "🚂"] {
+            assert!(!atlas.glyphs.contains_key(&text.chars().next().unwrap()));
+
+            let (quads, size) = atlas.layout(text, 48.);
+            let (expected_quads, expected_size) = atlas.layout("?", 48.);
+            assert_eq!(quads.len(), 1, "{text}");
+            assert_eq!(quads[0].uv_min, expected_quads[0].uv_min, "{text}");
+            assert_eq!(quads[0].uv_max, expected_quads[0].uv_max, "{text}");
+            assert_eq!(size, expected_size, "{text}");
+        }
+    }
+
+    #[test]
+    fn latin_1_characters_are_drawn_as_themselves() {
+        let atlas = atlas();
+        let (fallback, _) = atlas.layout("?", 48.);
+        for text in ["é", "ß", "£"] {
+            let (quads, _) = atlas.layout(text, 48.);
+            assert_eq!(quads.len(), 1, "{text}");
+            assert_ne!(quads[0].uv_min, fallback[0].uv_min, "{text}");
+        }
+    }
+
+    #[test]
+    fn spaces_advance_without_a_quad() {
+        let atlas = atlas();
+        let (quads, size) = atlas.layout("a b", 48.);
+        let (_, without_space) = atlas.layout("ab", 48.);
+        assert_eq!(quads.len(), 2);
+        assert!(size.x > without_space.x);
+    }
+
+    #[test]
+    fn lines_break_at_newlines_and_scale_with_size() {
+        let atlas = atlas();
+        let (_, one_line) = atlas.layout("abc", 24.);
+        let (quads, two_lines) = atlas.layout("abc\nab", 24.);
+        assert_eq!(quads.len(), 5);
+        assert_eq!(two_lines.x, one_line.x);
+        assert!((two_lines.y - 2. * one_line.y).abs() < 1e-3);
+
+        // The second line starts back at the left
+        assert!(quads[3].min.x < quads[1].min.x);
+        assert!(quads[3].min.y > quads[0].max.y);
+
+        let (_, doubled) = atlas.layout("abc", 48.);
+        assert!((doubled - 2. * one_line).abs().max_element() < 1e-3);
+    }
+}
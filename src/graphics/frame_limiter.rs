@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+
+/// Sleeps the CPU so frames are submitted no faster than a target rate.
+#[derive(Debug)]
+pub struct FrameLimiter {
+    next_frame: Instant,
+}
+
+impl Default for FrameLimiter {
+    fn default() -> Self {
+        Self {
+            next_frame: Instant::now(),
+        }
+    }
+}
+
+impl FrameLimiter {
+    /// Blocks until it's time to start the next frame. Passing `None` disables the limit.
+    pub fn wait(&mut self, frames_per_second: Option<f32>) {
+        let now = Instant::now();
+        let Some(frames_per_second) = frames_per_second.filter(|fps| *fps > 0.) else {
+            self.next_frame = now;
+            return;
+        };
+
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        }
+
+        // If we've fallen behind, don't try to catch up by rushing the following frames
+        let frame_time = Duration::from_secs_f32(1. / frames_per_second);
+        self.next_frame = (self.next_frame + frame_time).max(Instant::now());
+    }
+}
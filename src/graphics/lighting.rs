@@ -0,0 +1,76 @@
+use serde::Deserialize;
+
+use super::{
+    camera::Camera,
+    shadows::{Cascade, SHADOW_CASCADE_COUNT},
+};
+
+/// Parameters for the scene's sun, ambient light and sky. These can be changed at any time;
+/// they're uploaded to the GPU at the start of each frame. Scene files can leave any of them out
+/// to use the default.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lighting {
+    /// The direction the sunlight travels in, in world space.
+    pub sun_direction: glam::Vec3,
+    pub sun_colour: glam::Vec3,
+    pub sun_intensity: f32,
+    pub ambient_colour: glam::Vec3,
+    /// How far from the camera, in metres, shadows are drawn.
+    pub shadow_distance: f32,
+    /// Blends the cascade splits between uniform (0.0) and logarithmic (1.0).
+    pub cascade_split_lambda: f32,
+    /// The colour behind everything, where nothing's drawn. Linear.
+    pub clear_colour: glam::Vec4,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            sun_direction: glam::Vec3::new(-0.4, -1.0, -0.6).normalize(),
+            sun_colour: glam::Vec3::new(1.0, 0.95, 0.85),
+            sun_intensity: 1.0,
+            ambient_colour: glam::Vec3::splat(0.15),
+            shadow_distance: 150.0,
+            cascade_split_lambda: 0.75,
+            clear_colour: glam::Vec4::new(0.1, 0.2, 1.0, 1.0),
+        }
+    }
+}
+
+/// Per-frame data shared by every draw, laid out to match `Globals` in the shaders.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Globals {
+    pub ndc_from_world: glam::Mat4,
+    pub camera_position: glam::Vec4,
+    pub camera_forward: glam::Vec4,
+    pub sun_direction: glam::Vec4,
+    pub sun_colour: glam::Vec4,
+    pub ambient_colour: glam::Vec4,
+    pub cascade_ndc_from_world: [glam::Mat4; SHADOW_CASCADE_COUNT],
+    /// The view-space depth at which each cascade ends.
+    pub cascade_splits: [f32; SHADOW_CASCADE_COUNT],
+    /// The world-space size of a shadow map texel in each cascade.
+    pub cascade_texel_sizes: [f32; SHADOW_CASCADE_COUNT],
+}
+
+impl Globals {
+    pub fn new(
+        camera: &Camera,
+        lighting: &Lighting,
+        cascades: &[Cascade; SHADOW_CASCADE_COUNT],
+    ) -> Self {
+        Self {
+            ndc_from_world: camera.ndc_from_world(),
+            camera_position: camera.position().extend(1.),
+            camera_forward: camera.forward().extend(0.),
+            sun_direction: lighting.sun_direction.normalize_or_zero().extend(0.),
+            sun_colour: lighting.sun_colour.extend(lighting.sun_intensity),
+            ambient_colour: lighting.ambient_colour.extend(1.),
+            cascade_ndc_from_world: cascades.map(|c| c.ndc_from_world),
+            cascade_splits: cascades.map(|c| c.split_depth),
+            cascade_texel_sizes: cascades.map(|c| c.texel_size),
+        }
+    }
+}
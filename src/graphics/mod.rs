@@ -1,21 +1,81 @@
-use core::Core;
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use ash::vk;
-use camera::Camera;
-use context::Context;
-use renderer::Renderer;
+pub use camera::{Camera, Pose, Projection};
+pub use camera_controller::{CameraController, ControllerKind, ControllerSettings};
+pub use camera_path::CameraPath;
+pub use context::Context;
+pub use core::Core;
+use frame_limiter::FrameLimiter;
+pub use lighting::Lighting;
+use offscreen::Offscreen;
+use overlay::{Overlay, OverlayFrame};
+pub use picking::{pick, Hit, Ray};
+pub use pipeline::Cube;
+pub use post_process::{PostProcessSettings, Tonemapper};
+pub use profiler::{Profiler, Trace};
+pub use render_target::{RenderTarget, RenderTargets};
+use renderer::{Output, Renderer};
+pub use swapchain::DisplaySettings;
 use swapchain::Swapchain;
+pub use text::Placement;
+use text::Text;
+use tracing::warn;
 
-use crate::input::Input;
+use crate::{
+    assets::{AssetServer, DEFAULT_ASSET_ROOT},
+    input::Input,
+};
 
+mod buffer;
 mod camera;
+mod camera_controller;
+mod camera_path;
 mod context;
 mod core;
-mod depth_buffer;
+mod descriptors;
+mod font;
+mod frame_limiter;
+mod lighting;
+mod offscreen;
+mod overlay;
+mod picking;
 mod pipeline;
+mod post_process;
+mod profiler;
+mod render_target;
 mod renderer;
+mod shadows;
 mod swapchain;
+mod text;
+mod uploader;
+
+/// How [`Graphics`] is set up when it's created. Everything but the GPU can be changed later.
+#[derive(Debug, Clone)]
+pub struct GraphicsSettings {
+    /// The GPU to render with: its index in the list Vulkan gives, or part of its name. The first
+    /// GPU is used if this isn't set, or if nothing matches.
+    pub gpu: Option<String>,
+    /// Clamped to what the GPU supports.
+    pub msaa_samples: u32,
+    pub display: DisplaySettings,
+    /// The directory shaders and fonts are loaded from.
+    pub asset_root: PathBuf,
+    /// Load shaders and fonts again when their files change.
+    pub hot_reload: bool,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            gpu: None,
+            msaa_samples: DEFAULT_MSAA_SAMPLES,
+            display: DisplaySettings::default(),
+            asset_root: PathBuf::from(DEFAULT_ASSET_ROOT),
+            hot_reload: true,
+        }
+    }
+}
 
 pub struct Graphics {
     #[allow(unused)]
@@ -23,35 +83,298 @@ pub struct Graphics {
     #[allow(unused)]
     context: Arc<Context>,
     renderer: Renderer,
-    #[allow(unused)]
-    window: winit::window::Window,
+    /// `None` when rendering headlessly.
+    window: Option<winit::window::Window>,
+    /// The developer overlay. `None` when rendering headlessly.
+    overlay: Option<Overlay>,
+    /// What the overlay last drew, until it's rendered.
+    overlay_frame: Option<OverlayFrame>,
+    /// Text to draw this frame.
+    texts: Vec<Text>,
+    display_settings: DisplaySettings,
+    frame_limiter: FrameLimiter,
     pub camera: Camera,
+    pub lighting: Lighting,
+    pub post_process: PostProcessSettings,
 }
 
 impl Graphics {
-    pub fn new(window: winit::window::Window) -> Self {
-        let core = Core::new(&window);
+    pub fn new(window: winit::window::Window, settings: &GraphicsSettings) -> Self {
+        let core = Core::new(&window, settings.gpu.as_deref());
         let context = Context::new(&core);
         let context = Arc::new(context);
-        let swapchain = Swapchain::new(&context.device, &core, &window, vk::SwapchainKHR::null());
-        let camera = Camera::new(swapchain.extent);
-        let renderer = Renderer::new(context.clone(), swapchain);
+        let swapchain = Swapchain::new(&context.device, &core, &window, &settings.display);
+
+        Self::with_output(
+            core,
+            context,
+            Output::Window(swapchain),
+            Some(window),
+            settings,
+        )
+    }
+
+    /// Renders into an offscreen image of the given size instead of a window. Nothing is ever
+    /// shown, but frames cost the same to draw, which is all benchmarks need.
+    pub fn headless(extent: vk::Extent2D, settings: &GraphicsSettings) -> Self {
+        let core = Core::headless(settings.gpu.as_deref());
+        let context = Arc::new(Context::new(&core));
+        let offscreen = Offscreen::new(context.clone(), extent);
+
+        Self::with_output(core, context, Output::Offscreen(offscreen), None, settings)
+    }
+
+    fn with_output(
+        core: Core,
+        context: Arc<Context>,
+        output: Output,
+        window: Option<winit::window::Window>,
+        settings: &GraphicsSettings,
+    ) -> Self {
+        let camera = Camera::new(output.extent());
+        let assets = AssetServer::new(&settings.asset_root, settings.hot_reload);
+        let renderer = Renderer::new(context.clone(), output, settings.msaa_samples, assets);
+        let overlay = window.as_ref().map(Overlay::new);
 
         Graphics {
             core,
             context,
             renderer,
             window,
+            overlay,
+            overlay_frame: None,
+            texts: Vec::new(),
+            display_settings: settings.display,
+            frame_limiter: FrameLimiter::default(),
             camera,
+            lighting: Lighting::default(),
+            post_process: PostProcessSettings::default(),
         }
     }
 
-    pub(crate) fn draw(&mut self, input: &Input) {
-        self.camera.update(1.0 / 60.0, input);
-        self.renderer.draw(&self.camera);
+    pub fn msaa_samples(&self) -> u32 {
+        self.renderer.targets.samples.as_raw()
+    }
+
+    /// Sets the number of MSAA samples per pixel: 1, 2, 4 or 8, clamped to what the GPU supports.
+    pub fn set_msaa_samples(&mut self, samples: u32) {
+        self.renderer.set_msaa_samples(samples);
+    }
+
+    /// Steps through each supported MSAA sample count, wrapping back around to 1.
+    pub fn cycle_msaa_samples(&mut self) {
+        let current = self.msaa_samples();
+        self.set_msaa_samples(current * 2);
+        if self.msaa_samples() == current {
+            self.set_msaa_samples(1);
+        }
+    }
+
+    /// Switches between drawing the scene's faces and just their edges.
+    pub fn toggle_wireframe(&mut self) {
+        self.renderer.pipeline.wireframe = !self.renderer.pipeline.wireframe;
+    }
+
+    pub fn display_settings(&self) -> &DisplaySettings {
+        &self.display_settings
+    }
+
+    /// Applies new display settings, recreating the swapchain if they affect it.
+    pub fn set_display_settings(&mut self, settings: DisplaySettings) {
+        let recreate = settings.vsync != self.display_settings.vsync
+            || settings.prefer_mailbox != self.display_settings.prefer_mailbox
+            || settings.image_count != self.display_settings.image_count;
+
+        self.display_settings = settings;
+        if recreate {
+            self.renderer.swapchain_out_of_date = true;
+        }
+    }
+
+    /// Grabs and hides the cursor so the mouse can be used to look around, or releases it again.
+    /// The cursor is locked in place where the platform supports it, and confined to the window
+    /// otherwise.
+    pub fn set_cursor_grabbed(&mut self, grabbed: bool) {
+        use winit::window::CursorGrabMode;
+
+        let Some(window) = &self.window else {
+            return;
+        };
+
+        let result = if grabbed {
+            window
+                .set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined))
+        } else {
+            window.set_cursor_grab(CursorGrabMode::None)
+        };
+        if let Err(e) = result {
+            warn!("Couldn't change the cursor grab: {e}");
+        }
+
+        window.set_cursor_visible(!grabbed);
+    }
+
+    /// Asks for the object at `pixel` to be picked using the ID buffer. The result is returned by
+    /// [`Graphics::take_gpu_pick`] a frame or so later.
+    pub fn request_gpu_pick(&mut self, pixel: glam::UVec2) {
+        self.renderer.pick_request = Some(pixel);
+    }
+
+    /// Returns the index of the cube under the pixel requested with [`Graphics::request_gpu_pick`]
+    /// (or `None` if there wasn't one), once it's ready.
+    pub fn take_gpu_pick(&mut self) -> Option<Option<usize>> {
+        self.renderer.picked.take()
+    }
+
+    /// The number of draw calls recorded for the latest frame.
+    pub fn draw_calls(&self) -> u32 {
+        self.renderer.draw_calls
+    }
+
+    /// The device everything is rendered with, for creating resources of your own.
+    pub fn context(&self) -> &Arc<Context> {
+        &self.context
+    }
+
+    /// The images the scene is rendered into before post-processing.
+    pub fn render_targets(&self) -> &RenderTargets {
+        &self.renderer.targets
+    }
+
+    /// Where shaders and fonts are loaded from. Anything else can be loaded with it too.
+    pub fn assets(&self) -> &AssetServer {
+        &self.renderer.assets
+    }
+
+    pub fn assets_mut(&mut self) -> &mut AssetServer {
+        &mut self.renderer.assets
+    }
+
+    pub fn profiler(&self) -> &Profiler {
+        &self.renderer.profiler
+    }
+
+    pub fn profiler_mut(&mut self) -> &mut Profiler {
+        &mut self.renderer.profiler
+    }
+
+    /// Blocks until the GPU has finished every frame drawn so far, and reads back their results.
+    pub fn wait_idle(&mut self) {
+        self.renderer.wait_idle();
+    }
+
+    /// Shows or hides the developer overlay.
+    pub fn toggle_overlay(&mut self) {
+        if let Some(overlay) = &mut self.overlay {
+            overlay.visible = !overlay.visible;
+        }
+    }
+
+    /// Passes a window event to the overlay, returning `true` if the overlay used it, in which case
+    /// the game should ignore it. Events always go to the overlay first.
+    pub fn handle_overlay_event(&mut self, event: &winit::event::WindowEvent) -> bool {
+        match (&mut self.overlay, &self.window) {
+            (Some(overlay), Some(window)) => overlay.handle_window_event(window, event),
+            _ => false,
+        }
+    }
+
+    /// Starts building this frame's overlay UI, returning the context to build it with, or `None`
+    /// if the overlay is hidden. Call [`Graphics::end_overlay`] once it's built.
+    pub fn begin_overlay(&mut self) -> Option<egui::Context> {
+        match (&mut self.overlay, &self.window) {
+            (Some(overlay), Some(window)) if overlay.visible => Some(overlay.begin(window)),
+            _ => None,
+        }
+    }
+
+    /// Finishes the overlay UI started by [`Graphics::begin_overlay`], ready to be drawn.
+    pub fn end_overlay(&mut self) {
+        let (Some(overlay), Some(window)) = (&mut self.overlay, &self.window) else {
+            return;
+        };
+
+        let frame = overlay.end(window);
+        match &mut self.overlay_frame {
+            // The last one was never drawn, so keep its texture changes
+            Some(unrendered) => unrendered.merge(frame),
+            None => self.overlay_frame = Some(frame),
+        }
+    }
+
+    /// Draws `text` this frame, with lines `size` pixels or metres tall depending on where it's
+    /// placed. `colour` is linear. Text only lasts a frame, so call this every frame it should be
+    /// seen.
+    ///
+    /// Only printable ASCII and Latin-1 (U+0020 to U+007E and U+00A0 to U+00FF) are in the font
+    /// atlas; any other character is drawn as `?`.
+    pub fn draw_text(
+        &mut self,
+        text: impl Into<String>,
+        placement: Placement,
+        size: f32,
+        colour: glam::Vec4,
+    ) {
+        self.texts.push(Text {
+            text: text.into(),
+            placement,
+            size,
+            colour,
+        });
+    }
+
+    /// Call when the window has been resized so the swapchain is recreated before the next frame.
+    pub fn resized(&mut self) {
+        self.renderer.swapchain_out_of_date = true;
+    }
+
+    /// Advances everything graphics owns by one simulation tick. `camera_target` is what the
+    /// camera follows.
+    pub fn update(&mut self, dt: f32, input: &Input, camera_target: Pose) {
+        self.camera.update(dt, input, camera_target);
+    }
+
+    /// Draws `cubes`, with the camera `alpha` of the way between the previous and current
+    /// simulation ticks.
+    pub fn draw(&mut self, alpha: f32, cubes: &[Cube]) {
+        self.camera.interpolate(alpha);
+        let texts = std::mem::take(&mut self.texts);
+
+        if let Some(window) = &self.window {
+            // Nothing can be drawn while the window is minimised
+            let size = window.inner_size();
+            if size.width == 0 || size.height == 0 {
+                return;
+            }
+
+            if self.renderer.swapchain_out_of_date {
+                let extent = vk::Extent2D {
+                    width: size.width,
+                    height: size.height,
+                };
+                self.renderer
+                    .recreate_swapchain(extent, &self.display_settings);
+                self.camera.extent = extent;
+            }
+        }
+
+        let scope = self.renderer.profiler.begin_cpu("draw");
+        self.renderer.draw(
+            &self.camera,
+            &self.lighting,
+            &self.post_process,
+            cubes,
+            &texts,
+            &mut self.overlay_frame,
+        );
+        self.renderer.profiler.end_cpu(scope);
+        self.frame_limiter.wait(self.display_settings.frame_limit);
     }
 }
 
+const DEFAULT_MSAA_SAMPLES: u32 = 4;
+
 const FULL_IMAGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
     aspect_mask: vk::ImageAspectFlags::COLOR,
     base_mip_level: 0,
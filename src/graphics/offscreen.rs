@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{context::Context, render_target::RenderTarget, swapchain::Drawable};
+
+/// The format of the offscreen image, matching what the swapchain prefers.
+pub const OFFSCREEN_FORMAT: vk::Format = vk::Format::B8G8R8A8_SRGB;
+
+/// An image that stands in for the swapchain when there's no window, so frames can be rendered
+/// headlessly.
+pub struct Offscreen {
+    pub target: RenderTarget,
+    pub extent: vk::Extent2D,
+}
+
+impl Offscreen {
+    pub(crate) fn new(context: Arc<Context>, extent: vk::Extent2D) -> Self {
+        let target = RenderTarget::new(
+            context,
+            extent,
+            OFFSCREEN_FORMAT,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        );
+
+        Self { target, extent }
+    }
+
+    pub fn get_drawable(&self) -> Drawable {
+        Drawable {
+            image: self.target.image,
+            view: self.target.view,
+            ready: None,
+            index: 0,
+            extent: self.extent,
+        }
+    }
+}
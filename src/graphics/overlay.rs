@@ -0,0 +1,593 @@
+use std::{collections::HashMap, sync::Arc};
+
+use ash::vk;
+use egui::{
+    epaint::{Primitive, Vertex},
+    ClippedPrimitive, Color32, ImageData, TextureId, TexturesDelta,
+};
+use winit::{event::WindowEvent, window::Window};
+
+use super::{
+    buffer::ArrayBuffer,
+    context::Context,
+    pipeline::{load_module, ShaderError},
+    render_target::RenderTarget,
+    uploader::Uploader,
+};
+
+use crate::assets::AssetServer;
+
+/// The most textures egui can have at once. It normally only uses one, for the font atlas.
+const MAX_TEXTURES: u32 = 16;
+const TEXTURE_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// The developer overlay's UI state. It sees window events before the game does, and is drawn on
+/// top of everything else.
+pub struct Overlay {
+    context: egui::Context,
+    state: egui_winit::State,
+    pub visible: bool,
+}
+
+/// What the overlay drew during a frame, ready to be rendered.
+pub struct OverlayFrame {
+    primitives: Vec<ClippedPrimitive>,
+    textures_delta: TexturesDelta,
+    pixels_per_point: f32,
+}
+
+impl Overlay {
+    pub(crate) fn new(window: &Window) -> Self {
+        let context = egui::Context::default();
+        let state = egui_winit::State::new(
+            context.clone(),
+            egui::ViewportId::ROOT,
+            window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+
+        Self {
+            context,
+            state,
+            visible: false,
+        }
+    }
+
+    /// Passes `event` to the UI, returning `true` if the UI used it and the game shouldn't.
+    pub(crate) fn handle_window_event(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.visible && self.state.on_window_event(window, event).consumed
+    }
+
+    /// Starts building the UI for this frame, returning the context to build it with.
+    pub(crate) fn begin(&mut self, window: &Window) -> egui::Context {
+        let input = self.state.take_egui_input(window);
+        self.context.begin_pass(input);
+        self.context.clone()
+    }
+
+    /// Finishes the UI started by [`Overlay::begin`] and returns what should be rendered.
+    pub(crate) fn end(&mut self, window: &Window) -> OverlayFrame {
+        let output = self.context.end_pass();
+        self.state
+            .handle_platform_output(window, output.platform_output);
+
+        OverlayFrame {
+            primitives: self
+                .context
+                .tessellate(output.shapes, output.pixels_per_point),
+            textures_delta: output.textures_delta,
+            pixels_per_point: output.pixels_per_point,
+        }
+    }
+}
+
+impl OverlayFrame {
+    /// Replaces this frame with a newer one, keeping any texture changes that haven't been
+    /// uploaded yet.
+    pub fn merge(&mut self, newer: OverlayFrame) {
+        let mut textures_delta = std::mem::take(&mut self.textures_delta);
+        textures_delta.append(newer.textures_delta);
+
+        *self = OverlayFrame {
+            textures_delta,
+            ..newer
+        };
+    }
+}
+
+/// A texture egui has asked for, such as the font atlas.
+struct OverlayTexture {
+    image: RenderTarget,
+    set: vk::DescriptorSet,
+    extent: vk::Extent2D,
+    /// What's been uploaded, so partial updates can be applied here and uploaded whole.
+    pixels: Vec<Color32>,
+}
+
+/// Renders egui's meshes on top of the frame.
+pub struct OverlayPass {
+    handle: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    format: vk::Format,
+    descriptor_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: vk::Sampler,
+    textures: HashMap<TextureId, OverlayTexture>,
+    /// Textures egui has finished with. They're freed next frame, once the GPU is done with them.
+    pending_free: Vec<TextureId>,
+    vertices: ArrayBuffer<Vertex>,
+    indices: ArrayBuffer<u32>,
+    context: Arc<Context>,
+}
+
+impl OverlayPass {
+    pub fn new(context: Arc<Context>, format: vk::Format, assets: &mut AssetServer) -> Self {
+        let device = &context.device;
+
+        let descriptor_layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET)
+                    .max_sets(MAX_TEXTURES)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(MAX_TEXTURES)]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )
+        }
+        .unwrap();
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .size(std::mem::size_of::<OverlayRegisters>() as u32)
+                        .stage_flags(vk::ShaderStageFlags::VERTEX)]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let handle = create_pipeline(&context, layout, format, assets)
+            .unwrap_or_else(|e| panic!("Couldn't create the overlay pipeline: {e}"));
+
+        let vertices = ArrayBuffer::new(context.clone(), vk::BufferUsageFlags::VERTEX_BUFFER, 0);
+        let indices = ArrayBuffer::new(context.clone(), vk::BufferUsageFlags::INDEX_BUFFER, 0);
+
+        Self {
+            handle,
+            layout,
+            format,
+            descriptor_layout,
+            descriptor_pool,
+            sampler,
+            textures: HashMap::new(),
+            pending_free: Vec::new(),
+            vertices,
+            indices,
+            context,
+        }
+    }
+
+    /// Recreates the pipeline with the latest shaders, keeping the old one if they're unusable.
+    /// The GPU must not be using the pipeline.
+    pub fn reload_shaders(&mut self, assets: &mut AssetServer) -> Result<(), ShaderError> {
+        let handle = create_pipeline(&self.context, self.layout, self.format, assets)?;
+        unsafe { self.context.device.destroy_pipeline(self.handle, None) };
+        self.handle = handle;
+        Ok(())
+    }
+
+    /// Queues the frame's texture changes to be uploaded, and writes its geometry. This must be
+    /// called once the previous frame has finished.
+    pub(crate) fn prepare(&mut self, frame: &OverlayFrame, uploader: &mut Uploader) {
+        // The previous frame has finished, so the textures it freed are no longer in use
+        for id in std::mem::take(&mut self.pending_free) {
+            if let Some(texture) = self.textures.remove(&id) {
+                unsafe {
+                    self.context
+                        .device
+                        .free_descriptor_sets(self.descriptor_pool, &[texture.set])
+                }
+                .unwrap();
+            }
+        }
+        self.pending_free
+            .extend_from_slice(&frame.textures_delta.free);
+
+        self.upload_textures(&frame.textures_delta, uploader);
+
+        let meshes = || {
+            frame
+                .primitives
+                .iter()
+                .filter_map(|clipped| match &clipped.primitive {
+                    Primitive::Mesh(mesh) => Some(mesh),
+                    Primitive::Callback(_) => None,
+                })
+        };
+        let vertex_count = meshes().map(|mesh| mesh.vertices.len()).sum();
+        let index_count = meshes().map(|mesh| mesh.indices.len()).sum();
+        grow(
+            &mut self.vertices,
+            &self.context,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vertex_count,
+        );
+        grow(
+            &mut self.indices,
+            &self.context,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            index_count,
+        );
+
+        let (mut vertex_offset, mut index_offset) = (0, 0);
+        for mesh in meshes() {
+            self.vertices.write(vertex_offset, &mesh.vertices);
+            self.indices.write(index_offset, &mesh.indices);
+            vertex_offset += mesh.vertices.len();
+            index_offset += mesh.indices.len();
+        }
+    }
+
+    /// Queues each changed texture to be uploaded whole on the transfer queue. Partial updates are
+    /// applied to the texture's copy of its pixels first, so nothing needs to be read back.
+    fn upload_textures(&mut self, textures_delta: &TexturesDelta, uploader: &mut Uploader) {
+        let mut changed = Vec::new();
+        for (id, delta) in &textures_delta.set {
+            let pixels: Vec<Color32> = match &delta.image {
+                ImageData::Color(image) => image.pixels.clone(),
+                ImageData::Font(image) => image.srgba_pixels(None).collect(),
+            };
+            let [width, height] = delta.image.size();
+
+            let texture = match delta.pos {
+                None => self.create_texture(*id, width as u32, height as u32),
+                Some(_) => match self.textures.get_mut(id) {
+                    Some(texture) => texture,
+                    None => continue,
+                },
+            };
+            let [x, y] = delta.pos.unwrap_or([0, 0]);
+            let texture_width = texture.extent.width as usize;
+            for (row, row_pixels) in pixels.chunks_exact(width).enumerate() {
+                let start = (y + row) * texture_width + x;
+                texture.pixels[start..start + width].copy_from_slice(row_pixels);
+            }
+
+            if !changed.contains(id) {
+                changed.push(*id);
+            }
+        }
+
+        // Each texture is only uploaded once, however many times it changed
+        for id in changed {
+            let texture = &self.textures[&id];
+            let bytes: Vec<u8> = texture.pixels.iter().flat_map(|p| p.to_array()).collect();
+            let staging = ArrayBuffer::new(
+                self.context.clone(),
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                bytes.len(),
+            );
+            staging.write(0, &bytes);
+            uploader.upload_image(staging, &texture.image, texture.extent);
+        }
+    }
+
+    /// Creates a texture for `id`, replacing any it already has.
+    fn create_texture(&mut self, id: TextureId, width: u32, height: u32) -> &mut OverlayTexture {
+        let device = &self.context.device;
+
+        let set = match self.textures.remove(&id) {
+            Some(texture) => texture.set,
+            None => unsafe {
+                device.allocate_descriptor_sets(
+                    &vk::DescriptorSetAllocateInfo::default()
+                        .descriptor_pool(self.descriptor_pool)
+                        .set_layouts(&[self.descriptor_layout]),
+                )
+            }
+            .unwrap()[0],
+        };
+
+        let image = RenderTarget::new(
+            self.context.clone(),
+            vk::Extent2D { width, height },
+            TEXTURE_FORMAT,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+
+        unsafe {
+            device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .image_view(image.view)
+                        .sampler(self.sampler)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            )
+        };
+
+        let extent = vk::Extent2D { width, height };
+        let texture = OverlayTexture {
+            image,
+            set,
+            extent,
+            pixels: vec![Color32::TRANSPARENT; (width * height) as usize],
+        };
+        self.textures.entry(id).insert_entry(texture).into_mut()
+    }
+
+    /// Records the frame's meshes into the current rendering pass, returning the number of draw
+    /// calls.
+    pub(crate) fn draw(&self, frame: &OverlayFrame, extent: vk::Extent2D) -> u32 {
+        let device = &self.context.device;
+        let command_buffer = self.context.draw_command_buffer;
+        let pixels_per_point = frame.pixels_per_point;
+        let registers = OverlayRegisters {
+            screen_size: glam::Vec2::new(extent.width as f32, extent.height as f32)
+                / pixels_per_point,
+        };
+        let mut draw_calls = 0;
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.handle);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertices.handle], &[0]);
+            device.cmd_bind_index_buffer(
+                command_buffer,
+                self.indices.handle,
+                0,
+                vk::IndexType::UINT32,
+            );
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport::default()
+                    .width(extent.width as _)
+                    .height(extent.height as _)
+                    .max_depth(1.)],
+            );
+            device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    &registers as *const _ as *const u8,
+                    std::mem::size_of::<OverlayRegisters>(),
+                ),
+            );
+        }
+
+        let (mut vertex_offset, mut index_offset) = (0, 0);
+        for ClippedPrimitive {
+            clip_rect,
+            primitive,
+        } in &frame.primitives
+        {
+            let Primitive::Mesh(mesh) = primitive else {
+                continue;
+            };
+            let first_index = index_offset;
+            let first_vertex = vertex_offset;
+            vertex_offset += mesh.vertices.len();
+            index_offset += mesh.indices.len();
+
+            let Some(texture) = self.textures.get(&mesh.texture_id) else {
+                continue;
+            };
+
+            // The clip rectangle is in points, and may go off screen
+            let min_x = ((clip_rect.min.x * pixels_per_point).round() as u32).min(extent.width);
+            let min_y = ((clip_rect.min.y * pixels_per_point).round() as u32).min(extent.height);
+            let max_x = ((clip_rect.max.x * pixels_per_point).round() as u32).min(extent.width);
+            let max_y = ((clip_rect.max.y * pixels_per_point).round() as u32).min(extent.height);
+            if max_x <= min_x || max_y <= min_y {
+                continue;
+            }
+
+            unsafe {
+                device.cmd_set_scissor(
+                    command_buffer,
+                    0,
+                    &[vk::Rect2D {
+                        offset: vk::Offset2D {
+                            x: min_x as i32,
+                            y: min_y as i32,
+                        },
+                        extent: vk::Extent2D {
+                            width: max_x - min_x,
+                            height: max_y - min_y,
+                        },
+                    }],
+                );
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    self.layout,
+                    0,
+                    &[texture.set],
+                    &[],
+                );
+                device.cmd_draw_indexed(
+                    command_buffer,
+                    mesh.indices.len() as u32,
+                    1,
+                    first_index as u32,
+                    first_vertex as i32,
+                    0,
+                );
+            }
+            draw_calls += 1;
+        }
+
+        draw_calls
+    }
+}
+
+impl Drop for OverlayPass {
+    fn drop(&mut self) {
+        // Dropping the textures' images is enough, as their sets are freed with the pool
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_pipeline(self.handle, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_layout, None);
+        }
+    }
+}
+
+/// Creates the pipeline that draws egui's meshes into a `format` attachment.
+fn create_pipeline(
+    context: &Context,
+    layout: vk::PipelineLayout,
+    format: vk::Format,
+    assets: &mut AssetServer,
+) -> Result<vk::Pipeline, ShaderError> {
+    let vertex = load_module(assets, "overlay.vertex.spv", context)?;
+    let fragment = load_module(assets, "overlay.fragment.spv", context)?;
+
+    let pipelines = unsafe {
+        context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::GraphicsPipelineCreateInfo::default()
+                .stages(&[
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(vertex.handle)
+                        .stage(vk::ShaderStageFlags::VERTEX),
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(fragment.handle)
+                        .stage(vk::ShaderStageFlags::FRAGMENT),
+                ])
+                .vertex_input_state(
+                    &vk::PipelineVertexInputStateCreateInfo::default()
+                        .vertex_binding_descriptions(&[vk::VertexInputBindingDescription::default(
+                        )
+                        .binding(0)
+                        .stride(std::mem::size_of::<Vertex>() as u32)
+                        .input_rate(vk::VertexInputRate::VERTEX)])
+                        .vertex_attribute_descriptions(&[
+                            // Position
+                            vk::VertexInputAttributeDescription::default()
+                                .location(0)
+                                .format(vk::Format::R32G32_SFLOAT)
+                                .offset(0),
+                            // UV
+                            vk::VertexInputAttributeDescription::default()
+                                .location(1)
+                                .format(vk::Format::R32G32_SFLOAT)
+                                .offset(8),
+                            // Colour
+                            vk::VertexInputAttributeDescription::default()
+                                .location(2)
+                                .format(vk::Format::R8G8B8A8_UNORM)
+                                .offset(16),
+                        ]),
+                )
+                .input_assembly_state(
+                    &vk::PipelineInputAssemblyStateCreateInfo::default()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                )
+                .viewport_state(
+                    &vk::PipelineViewportStateCreateInfo::default()
+                        .scissor_count(1)
+                        .viewport_count(1),
+                )
+                .dynamic_state(
+                    &vk::PipelineDynamicStateCreateInfo::default()
+                        .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
+                )
+                .rasterization_state(
+                    &vk::PipelineRasterizationStateCreateInfo::default()
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .line_width(1.0),
+                )
+                .depth_stencil_state(&vk::PipelineDepthStencilStateCreateInfo::default())
+                .color_blend_state(
+                    &vk::PipelineColorBlendStateCreateInfo::default().attachments(&[
+                        // egui's colours are premultiplied by alpha
+                        vk::PipelineColorBlendAttachmentState::default()
+                            .blend_enable(true)
+                            .src_color_blend_factor(vk::BlendFactor::ONE)
+                            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                            .color_blend_op(vk::BlendOp::ADD)
+                            .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_DST_ALPHA)
+                            .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                            .alpha_blend_op(vk::BlendOp::ADD)
+                            .color_write_mask(vk::ColorComponentFlags::RGBA),
+                    ]),
+                )
+                .multisample_state(
+                    &vk::PipelineMultisampleStateCreateInfo::default()
+                        .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                )
+                .layout(layout)
+                .push_next(
+                    &mut vk::PipelineRenderingCreateInfo::default()
+                        .color_attachment_formats(&[format]),
+                )],
+            None,
+        )
+    }
+    .map_err(|(_, e)| ShaderError::Vulkan(e))?;
+
+    Ok(pipelines[0])
+}
+
+/// Replaces `buffer` with a bigger one if it can't hold `len` values. The GPU must not be using it.
+fn grow<T: Copy>(
+    buffer: &mut ArrayBuffer<T>,
+    context: &Arc<Context>,
+    usage: vk::BufferUsageFlags,
+    len: usize,
+) {
+    if len > buffer.capacity() {
+        // Leave some room, so it doesn't have to grow again as soon as the UI changes
+        *buffer = ArrayBuffer::new(context.clone(), usage, len.next_power_of_two());
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+struct OverlayRegisters {
+    screen_size: glam::Vec2,
+}
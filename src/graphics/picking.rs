@@ -0,0 +1,629 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{
+    buffer::Buffer,
+    context::Context,
+    descriptors::Descriptors,
+    pipeline::{cube_faces, load_module, Cube, ShaderError},
+    render_target::{RenderTarget, DEPTH_FORMAT},
+};
+
+use crate::assets::AssetServer;
+
+/// The height of the ground plane.
+const GROUND_HEIGHT: f32 = 0.;
+
+/// A half-line in world space, such as the one under the cursor.
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: glam::Vec3,
+    /// Always normalised.
+    pub direction: glam::Vec3,
+}
+
+impl Ray {
+    pub fn at(&self, distance: f32) -> glam::Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// Returns the distance along the ray to `cube`, if it hits it.
+    pub fn intersect_cube(&self, cube: &Cube) -> Option<f32> {
+        // Work in the cube's local space, where it's a unit cube around the origin. The direction
+        // isn't renormalised, so distances along the ray are the same in both spaces.
+        let local_from_world = cube.transform.inverse();
+        let origin = local_from_world.transform_point3(self.origin);
+        let direction = local_from_world.transform_vector3(self.direction);
+
+        let t0 = (glam::Vec3::splat(-0.5) - origin) / direction;
+        let t1 = (glam::Vec3::splat(0.5) - origin) / direction;
+        let near = t0.min(t1).max_element();
+        let far = t0.max(t1).min_element();
+
+        // A ray starting inside the cube hits it straight away
+        (near <= far && far >= 0.).then_some(near.max(0.))
+    }
+
+    /// Returns the distance along the ray to the horizontal plane at `height`, if it hits it.
+    pub fn intersect_plane(&self, height: f32) -> Option<f32> {
+        let distance = (height - self.origin.y) / self.direction.y;
+        (distance.is_finite() && distance >= 0.).then_some(distance)
+    }
+}
+
+/// What a [`Ray`] hit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hit {
+    /// The cube at `index` in the list that was picked from.
+    Object {
+        index: usize,
+        point: glam::Vec3,
+    },
+    Ground {
+        point: glam::Vec3,
+    },
+}
+
+/// Finds the nearest of `cubes` along `ray`, or failing that the point where it meets the ground.
+pub fn pick(ray: &Ray, cubes: &[Cube]) -> Option<Hit> {
+    let nearest = cubes
+        .iter()
+        .enumerate()
+        .filter_map(|(index, cube)| Some((index, ray.intersect_cube(cube)?)))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    if let Some((index, distance)) = nearest {
+        return Some(Hit::Object {
+            index,
+            point: ray.at(distance),
+        });
+    }
+
+    ray.intersect_plane(GROUND_HEIGHT)
+        .map(|distance| Hit::Ground {
+            point: ray.at(distance),
+        })
+}
+
+/// The format of the ID buffer. 0 means nothing was drawn; otherwise it's the cube's index + 1.
+const ID_FORMAT: vk::Format = vk::Format::R32_UINT;
+
+/// Picks objects exactly as they were drawn, by rendering each one's ID into an integer target and
+/// reading back the pixel under the cursor. This catches thin objects that are hard to hit with a
+/// ray, at the cost of a frame of latency.
+pub struct IdPicker {
+    handle: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    ids: RenderTarget,
+    depth: RenderTarget,
+    extent: vk::Extent2D,
+    readback: Buffer<u32>,
+    /// Set when a pick has been submitted but not read back yet.
+    in_flight: bool,
+    context: Arc<Context>,
+}
+
+impl IdPicker {
+    pub fn new(
+        context: Arc<Context>,
+        extent: vk::Extent2D,
+        descriptors: &Descriptors,
+        assets: &mut AssetServer,
+    ) -> Self {
+        let device = &context.device;
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptors.layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .size(std::mem::size_of::<IdRegisters>() as u32)
+                        .stage_flags(
+                            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        )]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let handle = create_pipeline(&context, layout, assets)
+            .unwrap_or_else(|e| panic!("Couldn't create the ID buffer pipeline: {e}"));
+
+        let (ids, depth) = create_targets(&context, extent);
+        let readback = Buffer::new(context.clone(), vk::BufferUsageFlags::TRANSFER_DST);
+
+        Self {
+            handle,
+            layout,
+            ids,
+            depth,
+            extent,
+            readback,
+            in_flight: false,
+            context,
+        }
+    }
+
+    /// Recreates the pipeline with the latest shaders, keeping the old one if they're unusable.
+    /// The GPU must not be using the pipeline.
+    pub fn reload_shaders(&mut self, assets: &mut AssetServer) -> Result<(), ShaderError> {
+        let handle = create_pipeline(&self.context, self.layout, assets)?;
+        unsafe { self.context.device.destroy_pipeline(self.handle, None) };
+        self.handle = handle;
+        Ok(())
+    }
+
+    /// Recreates the targets if `extent` has changed. The GPU must be idle.
+    pub fn resize(&mut self, extent: vk::Extent2D) {
+        if extent != self.extent {
+            (self.ids, self.depth) = create_targets(&self.context, extent);
+            self.extent = extent;
+        }
+    }
+
+    /// Returns the result of the last pick, if one was submitted. Only call this once the frame
+    /// it was submitted in has finished.
+    pub fn take_result(&mut self) -> Option<Option<usize>> {
+        if !std::mem::take(&mut self.in_flight) {
+            return None;
+        }
+
+        Some(self.readback.read().checked_sub(1).map(|id| id as usize))
+    }
+
+    /// Records commands to draw the IDs of `cubes` and copy the one at `pixel` into the readback
+    /// buffer, returning the number of draw calls.
+    pub(crate) fn draw(
+        &mut self,
+        descriptors: &Descriptors,
+        cubes: &[Cube],
+        pixel: glam::UVec2,
+    ) -> u32 {
+        let device = &self.context.device;
+        let command_buffer = self.context.draw_command_buffer;
+        let render_area = self.extent;
+        let pixel = pixel.min(glam::UVec2::new(render_area.width, render_area.height) - 1);
+        let mut draw_calls = 0;
+
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[
+                    vk::ImageMemoryBarrier2::default()
+                        .subresource_range(self.ids.subresource_range())
+                        .image(self.ids.image)
+                        .src_access_mask(vk::AccessFlags2::NONE)
+                        .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                        .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                    vk::ImageMemoryBarrier2::default()
+                        .subresource_range(self.depth.subresource_range())
+                        .image(self.depth.image)
+                        .src_access_mask(vk::AccessFlags2::empty())
+                        .src_stage_mask(vk::PipelineStageFlags2::empty())
+                        .dst_access_mask(
+                            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                        )
+                        .dst_stage_mask(vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL),
+                ]),
+            );
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.handle);
+            descriptors.bind(device, command_buffer, self.layout);
+            device.cmd_set_scissor(command_buffer, 0, &[render_area.into()]);
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport::default()
+                    .width(render_area.width as _)
+                    .height(render_area.height as _)
+                    .max_depth(1.)],
+            );
+
+            device.cmd_begin_rendering(
+                command_buffer,
+                &vk::RenderingInfo::default()
+                    .render_area(render_area.into())
+                    .layer_count(1)
+                    .depth_attachment(
+                        &vk::RenderingAttachmentInfo::default()
+                            .image_view(self.depth.view)
+                            .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                            .load_op(vk::AttachmentLoadOp::CLEAR)
+                            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                            .clear_value(vk::ClearValue {
+                                depth_stencil: vk::ClearDepthStencilValue {
+                                    depth: 0.0,
+                                    stencil: 0,
+                                },
+                            }),
+                    )
+                    .color_attachments(&[vk::RenderingAttachmentInfo::default()
+                        .image_view(self.ids.view)
+                        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .store_op(vk::AttachmentStoreOp::STORE)
+                        .clear_value(vk::ClearValue {
+                            color: vk::ClearColorValue { uint32: [0; 4] },
+                        })]),
+            );
+
+            for (index, cube) in cubes.iter().enumerate() {
+                for world_from_local in cube_faces(cube.transform) {
+                    let registers = IdRegisters {
+                        world_from_local: world_from_local.into(),
+                        id: index as u32 + 1,
+                        _pad: [0; 3],
+                    };
+
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.layout,
+                        vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        0,
+                        std::slice::from_raw_parts(
+                            &registers as *const _ as *const u8,
+                            std::mem::size_of::<IdRegisters>(),
+                        ),
+                    );
+                    device.cmd_draw(command_buffer, 6, 1, 0, 0);
+                    draw_calls += 1;
+                }
+            }
+
+            device.cmd_end_rendering(command_buffer);
+
+            // Copy the pixel under the cursor back to the CPU
+            device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[
+                    vk::ImageMemoryBarrier2::default()
+                        .subresource_range(self.ids.subresource_range())
+                        .image(self.ids.image)
+                        .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                        .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                        .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+                        .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+                ]),
+            );
+
+            device.cmd_copy_image_to_buffer(
+                command_buffer,
+                self.ids.image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                self.readback.handle,
+                &[vk::BufferImageCopy::default()
+                    .image_subresource(vk::ImageSubresourceLayers {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        mip_level: 0,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    })
+                    .image_offset(vk::Offset3D {
+                        x: pixel.x as i32,
+                        y: pixel.y as i32,
+                        z: 0,
+                    })
+                    .image_extent(vk::Extent3D {
+                        width: 1,
+                        height: 1,
+                        depth: 1,
+                    })],
+            );
+
+            device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&[
+                    vk::BufferMemoryBarrier2::default()
+                        .buffer(self.readback.handle)
+                        .size(self.readback.size())
+                        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                        .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                        .dst_access_mask(vk::AccessFlags2::HOST_READ)
+                        .dst_stage_mask(vk::PipelineStageFlags2::HOST),
+                ]),
+            );
+        }
+
+        self.in_flight = true;
+
+        draw_calls
+    }
+}
+
+impl Drop for IdPicker {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_pipeline(self.handle, None);
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Creates the pipeline that draws the index of each cube into the ID buffer.
+fn create_pipeline(
+    context: &Context,
+    layout: vk::PipelineLayout,
+    assets: &mut AssetServer,
+) -> Result<vk::Pipeline, ShaderError> {
+    let vertex = load_module(assets, "id.vertex.spv", context)?;
+    let fragment = load_module(assets, "id.fragment.spv", context)?;
+
+    let pipelines = unsafe {
+        context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::GraphicsPipelineCreateInfo::default()
+                .stages(&[
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(vertex.handle)
+                        .stage(vk::ShaderStageFlags::VERTEX),
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(fragment.handle)
+                        .stage(vk::ShaderStageFlags::FRAGMENT),
+                ])
+                .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                .input_assembly_state(
+                    &vk::PipelineInputAssemblyStateCreateInfo::default()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                )
+                .viewport_state(
+                    &vk::PipelineViewportStateCreateInfo::default()
+                        .scissor_count(1)
+                        .viewport_count(1),
+                )
+                .dynamic_state(
+                    &vk::PipelineDynamicStateCreateInfo::default()
+                        .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
+                )
+                .rasterization_state(
+                    &vk::PipelineRasterizationStateCreateInfo::default()
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .cull_mode(vk::CullModeFlags::BACK)
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .line_width(1.0),
+                )
+                .depth_stencil_state(
+                    &vk::PipelineDepthStencilStateCreateInfo::default()
+                        .depth_write_enable(true)
+                        .depth_test_enable(true)
+                        .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL)
+                        .max_depth_bounds(1.),
+                )
+                .color_blend_state(
+                    &vk::PipelineColorBlendStateCreateInfo::default().attachments(&[
+                        vk::PipelineColorBlendAttachmentState::default()
+                            .blend_enable(false)
+                            .color_write_mask(vk::ColorComponentFlags::R),
+                    ]),
+                )
+                .multisample_state(
+                    &vk::PipelineMultisampleStateCreateInfo::default()
+                        .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                )
+                .layout(layout)
+                .push_next(
+                    &mut vk::PipelineRenderingCreateInfo::default()
+                        .depth_attachment_format(DEPTH_FORMAT)
+                        .color_attachment_formats(&[ID_FORMAT]),
+                )],
+            None,
+        )
+    }
+    .map_err(|(_, e)| ShaderError::Vulkan(e))?;
+
+    Ok(pipelines[0])
+}
+
+fn create_targets(context: &Arc<Context>, extent: vk::Extent2D) -> (RenderTarget, RenderTarget) {
+    let ids = RenderTarget::new(
+        context.clone(),
+        extent,
+        ID_FORMAT,
+        vk::SampleCountFlags::TYPE_1,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+    );
+    let depth = RenderTarget::new(
+        context.clone(),
+        extent,
+        DEPTH_FORMAT,
+        vk::SampleCountFlags::TYPE_1,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+    );
+
+    (ids, depth)
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+struct IdRegisters {
+    world_from_local: glam::Mat4,
+    id: u32,
+    _pad: [u32; 3],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::{Camera, Pose, Projection};
+
+    const EPSILON: f32 = 1e-4;
+
+    fn cube_at(position: glam::Vec3) -> Cube {
+        Cube {
+            transform: glam::Affine3A::from_translation(position),
+            colour: glam::Vec4::ONE,
+        }
+    }
+
+    /// A ray from 10m along +Z, looking back down -Z towards the origin.
+    fn ray_towards_origin() -> Ray {
+        Ray {
+            origin: glam::Vec3::new(0., 0., 10.),
+            direction: glam::Vec3::NEG_Z,
+        }
+    }
+
+    #[test]
+    fn ray_hits_the_near_face_of_a_cube() {
+        let distance = ray_towards_origin().intersect_cube(&cube_at(glam::Vec3::ZERO));
+        assert_eq!(distance, Some(9.5));
+    }
+
+    #[test]
+    fn ray_hits_a_scaled_and_rotated_cube() {
+        let cube = Cube {
+            transform: glam::Affine3A::from_scale_rotation_translation(
+                glam::Vec3::new(1., 1., 4.),
+                glam::Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+                glam::Vec3::ZERO,
+            ),
+            colour: glam::Vec4::ONE,
+        };
+
+        // Turned a quarter, the cube's long side lies along X, leaving it only 1m deep in Z
+        let distance = ray_towards_origin().intersect_cube(&cube).unwrap();
+        assert!((distance - 9.5).abs() < EPSILON, "{distance}");
+
+        let ray = Ray {
+            origin: glam::Vec3::new(10., 0., 0.),
+            direction: glam::Vec3::NEG_X,
+        };
+        let distance = ray.intersect_cube(&cube).unwrap();
+        assert!((distance - 8.).abs() < EPSILON, "{distance}");
+    }
+
+    #[test]
+    fn ray_misses_cubes_beside_and_behind_it() {
+        let ray = ray_towards_origin();
+        assert_eq!(
+            ray.intersect_cube(&cube_at(glam::Vec3::new(2., 0., 0.))),
+            None
+        );
+        assert_eq!(
+            ray.intersect_cube(&cube_at(glam::Vec3::new(0., 0., 20.))),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_starting_inside_a_cube_hits_it_straight_away() {
+        let ray = Ray {
+            origin: glam::Vec3::new(0.1, 0.2, 0.),
+            direction: glam::Vec3::X,
+        };
+        assert_eq!(ray.intersect_cube(&cube_at(glam::Vec3::ZERO)), Some(0.));
+    }
+
+    #[test]
+    fn pick_finds_the_nearest_cube() {
+        let cubes = [
+            cube_at(glam::Vec3::new(0., 0., -5.)),
+            cube_at(glam::Vec3::new(3., 0., 0.)),
+            cube_at(glam::Vec3::new(0., 0., 2.)),
+            cube_at(glam::Vec3::ZERO),
+        ];
+
+        let hit = pick(&ray_towards_origin(), &cubes);
+        assert_eq!(
+            hit,
+            Some(Hit::Object {
+                index: 2,
+                point: glam::Vec3::new(0., 0., 2.5),
+            })
+        );
+    }
+
+    #[test]
+    fn pick_falls_back_to_the_ground() {
+        let ray = Ray {
+            origin: glam::Vec3::new(0., 10., 0.),
+            direction: glam::Vec3::new(1., -1., 0.).normalize(),
+        };
+        let cubes = [cube_at(glam::Vec3::new(-5., 0., 0.))];
+
+        let Some(Hit::Ground { point }) = pick(&ray, &cubes) else {
+            panic!("expected to hit the ground");
+        };
+        assert!(
+            point.abs_diff_eq(glam::Vec3::new(10., 0., 0.), EPSILON),
+            "{point}"
+        );
+    }
+
+    #[test]
+    fn pick_misses_when_looking_at_the_sky() {
+        let ray = Ray {
+            origin: glam::Vec3::new(0., 10., 0.),
+            direction: glam::Vec3::Y,
+        };
+        assert_eq!(pick(&ray, &[cube_at(glam::Vec3::ZERO)]), None);
+    }
+
+    fn camera(projection: Projection) -> Camera {
+        let mut camera = Camera::new(vk::Extent2D {
+            width: 1280,
+            height: 720,
+        });
+        camera.projection = projection;
+        camera.set_pose(Pose {
+            position: glam::Vec3::new(3., 10., 10.),
+            rotation: glam::Quat::from_euler(glam::EulerRot::YXZ, 0.4, -0.6, 0.),
+        });
+        camera
+    }
+
+    #[test]
+    fn centre_ray_looks_forward() {
+        for projection in [Projection::default(), Projection::orthographic()] {
+            let camera = camera(projection);
+            let ray = camera.ray_through_pixel(glam::Vec2::new(640., 360.));
+
+            assert!(
+                ray.direction.abs_diff_eq(camera.forward(), EPSILON),
+                "{projection:?}: {} != {}",
+                ray.direction,
+                camera.forward()
+            );
+            // The ray starts on the near plane, straight in front of the camera
+            let offset = ray.origin - camera.position();
+            assert!(
+                offset.cross(camera.forward()).length() < 1e-3,
+                "{projection:?}: {offset}"
+            );
+        }
+    }
+
+    #[test]
+    fn corner_rays_spread_out_only_in_perspective() {
+        let top_left = glam::Vec2::ZERO;
+        let bottom_right = glam::Vec2::new(1280., 720.);
+
+        let perspective = camera(Projection::default());
+        let (a, b) = (
+            perspective.ray_through_pixel(top_left),
+            perspective.ray_through_pixel(bottom_right),
+        );
+        assert!(a.direction.dot(b.direction) < 0.9);
+        // Up the screen is up the world, for a camera that isn't rolled
+        assert!(a.direction.y > b.direction.y);
+
+        let orthographic = camera(Projection::orthographic());
+        let (a, b) = (
+            orthographic.ray_through_pixel(top_left),
+            orthographic.ray_through_pixel(bottom_right),
+        );
+        assert!(a.direction.abs_diff_eq(b.direction, EPSILON));
+        assert!(a.origin.y > b.origin.y);
+    }
+}
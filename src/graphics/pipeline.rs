@@ -1,119 +1,143 @@
-use std::{f32::consts::TAU, path::Path, sync::Arc};
+use std::{f32::consts::TAU, fmt, io::Cursor, path::Path, sync::Arc};
 
 use ash::vk;
 
 use super::{
-    camera::Camera,
     context::Context,
-    depth_buffer::{DepthBuffer, DEPTH_FORMAT},
-    swapchain::Drawable,
+    descriptors::Descriptors,
+    render_target::{RenderTargets, DEPTH_FORMAT, HDR_FORMAT},
 };
 
+use crate::assets::{Asset, AssetError, AssetServer};
+
+/// Shaders are loaded from here, relative to the asset root.
+pub(crate) const SHADER_DIR: &str = "shaders";
+
+/// A cube to be drawn this frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Cube {
+    pub transform: glam::Affine3A,
+    pub colour: glam::Vec4,
+}
+
 pub struct Pipeline {
     handle: vk::Pipeline,
+    /// The same as `handle`, but drawing only the edges of each triangle.
+    wireframe_handle: vk::Pipeline,
+    /// Draw with `wireframe_handle` instead of `handle`.
+    pub(crate) wireframe: bool,
     layout: vk::PipelineLayout,
+    samples: vk::SampleCountFlags,
     context: Arc<Context>,
 }
 
 impl Pipeline {
-    pub fn new(context: Arc<Context>, format: vk::Format) -> Self {
+    pub fn new(
+        context: Arc<Context>,
+        samples: vk::SampleCountFlags,
+        descriptors: &Descriptors,
+        assets: &mut AssetServer,
+    ) -> Self {
         let device = &context.device;
 
         let layout = unsafe {
             device.create_pipeline_layout(
-                &vk::PipelineLayoutCreateInfo::default().push_constant_ranges(&[
-                    vk::PushConstantRange::default()
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptors.layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
                         .size(std::mem::size_of::<Registers>() as u32)
-                        .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT),
-                ]),
+                        .stage_flags(
+                            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                        )]),
                 None,
             )
         }
         .unwrap();
 
-        let handle = unsafe {
-            device.create_graphics_pipelines(
-                vk::PipelineCache::null(),
-                &[vk::GraphicsPipelineCreateInfo::default()
-                    .stages(&[
-                        vk::PipelineShaderStageCreateInfo::default()
-                            .name(c"main")
-                            .module(load_module("triangle.vertex.spv", &context))
-                            .stage(vk::ShaderStageFlags::VERTEX),
-                        vk::PipelineShaderStageCreateInfo::default()
-                            .name(c"main")
-                            .module(load_module("triangle.fragment.spv", &context))
-                            .stage(vk::ShaderStageFlags::FRAGMENT),
-                    ])
-                    .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
-                    .input_assembly_state(
-                        &vk::PipelineInputAssemblyStateCreateInfo::default()
-                            .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
-                    )
-                    .viewport_state(
-                        &vk::PipelineViewportStateCreateInfo::default()
-                            .scissor_count(1)
-                            .viewport_count(1),
-                    )
-                    .dynamic_state(
-                        &vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&[
-                            vk::DynamicState::SCISSOR,
-                            vk::DynamicState::VIEWPORT,
-                        ]),
-                    )
-                    .rasterization_state(
-                        &vk::PipelineRasterizationStateCreateInfo::default()
-                            .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
-                            .cull_mode(vk::CullModeFlags::BACK)
-                            .polygon_mode(vk::PolygonMode::FILL)
-                            .line_width(1.0),
-                    )
-                    .depth_stencil_state(
-                        &vk::PipelineDepthStencilStateCreateInfo::default()
-                            .depth_write_enable(true)
-                            .depth_test_enable(true)
-                            .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL)
-                            .stencil_test_enable(false)
-                            .depth_bounds_test_enable(false)
-                            .max_depth_bounds(1.),
-                    )
-                    .color_blend_state(
-                        &vk::PipelineColorBlendStateCreateInfo::default().attachments(&[
-                            vk::PipelineColorBlendAttachmentState::default()
-                                .blend_enable(false)
-                                .color_write_mask(vk::ColorComponentFlags::RGBA),
-                        ]),
-                    )
-                    .multisample_state(
-                        &vk::PipelineMultisampleStateCreateInfo::default()
-                            .rasterization_samples(vk::SampleCountFlags::TYPE_1),
-                    )
-                    .layout(layout)
-                    .push_next(
-                        &mut vk::PipelineRenderingCreateInfo::default()
-                            .depth_attachment_format(DEPTH_FORMAT)
-                            .color_attachment_formats(&[format]),
-                    )],
-                None,
-            )
-        }
-        .unwrap()[0];
+        let [handle, wireframe_handle] =
+            [vk::PolygonMode::FILL, vk::PolygonMode::LINE].map(|mode| {
+                create_pipeline(&context, layout, samples, mode, assets)
+                    .unwrap_or_else(|e| panic!("Couldn't create the scene pipeline: {e}"))
+            });
 
         Self {
             context,
             layout,
+            samples,
             handle,
+            wireframe_handle,
+            wireframe: false,
+        }
+    }
+
+    /// Recreates the pipeline with the latest shaders, keeping the old one if they're unusable.
+    /// The GPU must not be using the pipeline.
+    pub fn reload_shaders(&mut self, assets: &mut AssetServer) -> Result<(), ShaderError> {
+        let device = &self.context.device;
+        let handle = create_pipeline(
+            &self.context,
+            self.layout,
+            self.samples,
+            vk::PolygonMode::FILL,
+            assets,
+        )?;
+        let wireframe_handle = create_pipeline(
+            &self.context,
+            self.layout,
+            self.samples,
+            vk::PolygonMode::LINE,
+            assets,
+        )
+        .inspect_err(|_| unsafe { device.destroy_pipeline(handle, None) })?;
+
+        unsafe {
+            device.destroy_pipeline(self.handle, None);
+            device.destroy_pipeline(self.wireframe_handle, None);
         }
+        self.handle = handle;
+        self.wireframe_handle = wireframe_handle;
+        Ok(())
     }
 
-    pub(crate) fn draw(&self, drawable: Drawable, depth_buffer: DepthBuffer, camera: &Camera) {
+    /// Draws `cubes` into `targets` over `clear_colour`, returning the number of draw calls.
+    /// `draw_over` is called
+    /// before the pass ends, to draw anything else that belongs in the scene, such as labels;
+    /// it returns its own number of draw calls.
+    pub(crate) fn draw(
+        &self,
+        targets: &RenderTargets,
+        descriptors: &Descriptors,
+        clear_colour: glam::Vec4,
+        cubes: &[Cube],
+        draw_over: impl FnOnce() -> u32,
+    ) -> u32 {
         let device = &self.context.device;
         let command_buffer = self.context.draw_command_buffer;
-        let render_area = drawable.extent;
+        let render_area = targets.extent;
+        let mut draw_calls = 0;
+
+        // When multisampling, render into the MSAA target and resolve into the HDR target
+        let colour_attachment = match &targets.msaa_colour {
+            Some(msaa_colour) => vk::RenderingAttachmentInfo::default()
+                .image_view(msaa_colour.view)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(targets.hdr.view)
+                .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            None => vk::RenderingAttachmentInfo::default()
+                .image_view(targets.hdr.view)
+                .store_op(vk::AttachmentStoreOp::STORE),
+        };
 
         unsafe {
             // Next, bind the pipeline and set the dynamic state
-            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.handle);
+            let handle = if self.wireframe {
+                self.wireframe_handle
+            } else {
+                self.handle
+            };
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, handle);
+            descriptors.bind(device, command_buffer, self.layout);
             device.cmd_set_scissor(command_buffer, 0, &[render_area.into()]);
             device.cmd_set_viewport(
                 command_buffer,
@@ -132,7 +156,7 @@ impl Pipeline {
                     .layer_count(1)
                     .depth_attachment(
                         &vk::RenderingAttachmentInfo::default()
-                            .image_view(depth_buffer.view)
+                            .image_view(targets.depth.view)
                             .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
                             .load_op(vk::AttachmentLoadOp::CLEAR)
                             .store_op(vk::AttachmentStoreOp::DONT_CARE)
@@ -143,83 +167,47 @@ impl Pipeline {
                                 },
                             }),
                     )
-                    .color_attachments(&[vk::RenderingAttachmentInfo::default()
-                        .image_view(drawable.view)
+                    .color_attachments(&[colour_attachment
                         .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
                         .load_op(vk::AttachmentLoadOp::CLEAR)
-                        .store_op(vk::AttachmentStoreOp::STORE)
                         .clear_value(vk::ClearValue {
                             color: vk::ClearColorValue {
-                                float32: [0.1, 0.2, 1.0, 1.0],
+                                float32: clear_colour.to_array(),
                             },
                         })]),
             );
 
-            self.draw_cube(device, command_buffer, camera, glam::Affine3A::from_scale_rotation_translation(
-                glam::Vec3::splat(10.),
-                glam::Quat::IDENTITY,
-                Default::default(),
-            ), [0.1, 1.0, 0.1, 1.0].into());
+            for cube in cubes {
+                draw_calls += self.draw_cube(device, command_buffer, cube);
+            }
+        }
+
+        draw_calls += draw_over();
 
-            self.draw_cube(device, command_buffer, camera, glam::Affine3A::from_scale_rotation_translation(
-                glam::Vec3::splat(3.),
-                glam::Quat::IDENTITY,
-                [15.0, 0., 0.].into(),
-            ), [1.0, 0.1, 0.1, 1.0].into());
+        // End rendering
+        unsafe { device.cmd_end_rendering(command_buffer) };
 
-            // End rendering
-            device.cmd_end_rendering(command_buffer);
-        }
+        draw_calls
     }
 
     fn draw_cube(
         &self,
         device: &ash::Device,
         command_buffer: vk::CommandBuffer,
-        camera: &Camera,
-        transform: glam::Affine3A,
-        colour: glam::Vec4,
-    ) {
-        #[rustfmt::skip]
-        let transforms = [
-            // TOP
-            (
-                [0., 0.5, 0.], 
-                glam::Quat::from_rotation_x(-TAU / 4.),
-            ),
-            // BOTTOM
-            (
-                [0., -0.5, 0.],
-                glam::Quat::from_rotation_x(TAU / 4.),
-            ),
-            // LEFT
-            (
-                [-0.5, 0.0, 0.], 
-                glam::Quat::from_rotation_y(-TAU / 4.),
-            ),
-            // RIGHT
-            (
-                [0.5, 0.0, 0.], 
-                glam::Quat::from_rotation_y(TAU / 4.),
-            ),
-            // FRONT
-            (
-                [0.0, 0.0, 0.5], 
-                glam::Quat::IDENTITY
-            ),
-            // BACK
-            (
-                [0.0, 0.0, -0.5], 
-                glam::Quat::from_rotation_y(TAU / 2.),
-            ),
-        ];
-
-        for (translation, rotation) in transforms {
+        cube: &Cube,
+    ) -> u32 {
+        let mut draw_calls = 0;
+        for world_from_local in cube_faces(cube.transform) {
+            // Each quad faces +Z in its local space; use the inverse transpose so non-uniform
+            // scales don't skew the normal.
+            let normal = (world_from_local.matrix3.inverse().transpose() * glam::Vec3A::Z)
+                .normalize()
+                .extend(0.);
+
             let registers = Registers {
-                ndc_from_local: camera.ndc_from_world()
-                    * transform
-                    * glam::Affine3A::from_rotation_translation(rotation, translation.into()),
-                colour,
+                world_from_local: world_from_local.into(),
+                colour: cube.colour,
+                normal,
             };
 
             unsafe {
@@ -228,33 +216,224 @@ impl Pipeline {
                     self.layout,
                     vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
                     0,
-                    &std::slice::from_raw_parts(
+                    std::slice::from_raw_parts(
                         &registers as *const _ as *const u8,
                         std::mem::size_of::<Registers>(),
                     ),
                 );
                 device.cmd_draw(command_buffer, 6, 1, 0, 0);
             }
+            draw_calls += 1;
         }
+
+        draw_calls
     }
 }
 
-fn load_module(path: &str, context: &Context) -> vk::ShaderModule {
-    let path = Path::new("assets/shaders/").join(path);
-    let mut file = std::fs::File::open(path).unwrap();
-    let words = ash::util::read_spv(&mut file).unwrap();
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_pipeline(self.handle, None);
+            device.destroy_pipeline(self.wireframe_handle, None);
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Creates the pipeline that draws lit, shadowed cubes into the scene's `samples` targets, filling
+/// their faces or just outlining them according to `polygon_mode`.
+fn create_pipeline(
+    context: &Context,
+    layout: vk::PipelineLayout,
+    samples: vk::SampleCountFlags,
+    polygon_mode: vk::PolygonMode,
+    assets: &mut AssetServer,
+) -> Result<vk::Pipeline, ShaderError> {
+    let vertex = load_module(assets, "triangle.vertex.spv", context)?;
+    let fragment = load_module(assets, "triangle.fragment.spv", context)?;
+
+    let pipelines = unsafe {
+        context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::GraphicsPipelineCreateInfo::default()
+                .stages(&[
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(vertex.handle)
+                        .stage(vk::ShaderStageFlags::VERTEX),
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(fragment.handle)
+                        .stage(vk::ShaderStageFlags::FRAGMENT),
+                ])
+                .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                .input_assembly_state(
+                    &vk::PipelineInputAssemblyStateCreateInfo::default()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                )
+                .viewport_state(
+                    &vk::PipelineViewportStateCreateInfo::default()
+                        .scissor_count(1)
+                        .viewport_count(1),
+                )
+                .dynamic_state(
+                    &vk::PipelineDynamicStateCreateInfo::default()
+                        .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
+                )
+                .rasterization_state(
+                    &vk::PipelineRasterizationStateCreateInfo::default()
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .cull_mode(vk::CullModeFlags::BACK)
+                        .polygon_mode(polygon_mode)
+                        .line_width(1.0),
+                )
+                .depth_stencil_state(
+                    &vk::PipelineDepthStencilStateCreateInfo::default()
+                        .depth_write_enable(true)
+                        .depth_test_enable(true)
+                        .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL)
+                        .stencil_test_enable(false)
+                        .depth_bounds_test_enable(false)
+                        .max_depth_bounds(1.),
+                )
+                .color_blend_state(
+                    &vk::PipelineColorBlendStateCreateInfo::default().attachments(&[
+                        vk::PipelineColorBlendAttachmentState::default()
+                            .blend_enable(false)
+                            .color_write_mask(vk::ColorComponentFlags::RGBA),
+                    ]),
+                )
+                .multisample_state(
+                    &vk::PipelineMultisampleStateCreateInfo::default()
+                        .rasterization_samples(samples),
+                )
+                .layout(layout)
+                .push_next(
+                    &mut vk::PipelineRenderingCreateInfo::default()
+                        .depth_attachment_format(DEPTH_FORMAT)
+                        .color_attachment_formats(&[HDR_FORMAT]),
+                )],
+            None,
+        )
+    }
+    .map_err(|(_, e)| ShaderError::Vulkan(e))?;
+
+    Ok(pipelines[0])
+}
 
-    unsafe {
-        context
-            .device
-            .create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&words), None)
+/// Returns the `world_from_local` transform of each of a cube's six quads.
+pub(crate) fn cube_faces(transform: glam::Affine3A) -> impl Iterator<Item = glam::Affine3A> {
+    #[rustfmt::skip]
+    let transforms = [
+        // TOP
+        (
+            [0., 0.5, 0.],
+            glam::Quat::from_rotation_x(-TAU / 4.),
+        ),
+        // BOTTOM
+        (
+            [0., -0.5, 0.],
+            glam::Quat::from_rotation_x(TAU / 4.),
+        ),
+        // LEFT
+        (
+            [-0.5, 0.0, 0.],
+            glam::Quat::from_rotation_y(-TAU / 4.),
+        ),
+        // RIGHT
+        (
+            [0.5, 0.0, 0.],
+            glam::Quat::from_rotation_y(TAU / 4.),
+        ),
+        // FRONT
+        (
+            [0.0, 0.0, 0.5],
+            glam::Quat::IDENTITY
+        ),
+        // BACK
+        (
+            [0.0, 0.0, -0.5],
+            glam::Quat::from_rotation_y(TAU / 2.),
+        ),
+    ];
+
+    transforms.into_iter().map(move |(translation, rotation)| {
+        transform * glam::Affine3A::from_rotation_translation(rotation, translation.into())
+    })
+}
+
+/// Compiled SPIR-V, ready to be made into a shader module.
+pub(crate) struct ShaderCode(Vec<u32>);
+
+/// The first word of every SPIR-V module.
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+impl Asset for ShaderCode {
+    /// Only checks that the file looks like SPIR-V. The driver finds anything else wrong with it
+    /// when it's made into a pipeline.
+    fn decode(bytes: Vec<u8>) -> Result<Self, String> {
+        let code = ash::util::read_spv(&mut Cursor::new(bytes)).map_err(|e| e.to_string())?;
+        if code.first() != Some(&SPIRV_MAGIC) {
+            return Err("not a SPIR-V module".into());
+        }
+        Ok(ShaderCode(code))
     }
-    .unwrap()
 }
 
+/// A shader module that's destroyed when it's dropped, which is safe as soon as the pipelines
+/// that use it have been created.
+pub(crate) struct ShaderModule<'a> {
+    pub handle: vk::ShaderModule,
+    device: &'a ash::Device,
+}
+
+impl Drop for ShaderModule<'_> {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_shader_module(self.handle, None) };
+    }
+}
+
+/// Creates a module from the shader called `name`, waiting for it to load if it hasn't already.
+pub(crate) fn load_module<'a>(
+    assets: &mut AssetServer,
+    name: &str,
+    context: &'a Context,
+) -> Result<ShaderModule<'a>, ShaderError> {
+    let handle = assets.load::<ShaderCode>(Path::new(SHADER_DIR).join(name));
+    let code = assets.wait(handle).map_err(ShaderError::Load)?;
+
+    let device = &context.device;
+    let handle = unsafe {
+        device.create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&code.0), None)
+    }
+    .map_err(ShaderError::Vulkan)?;
+
+    Ok(ShaderModule { handle, device })
+}
+
+/// Why a pipeline couldn't be made from its shaders.
+#[derive(Debug)]
+pub enum ShaderError {
+    Load(AssetError),
+    Vulkan(vk::Result),
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::Load(e) => write!(f, "{e}"),
+            ShaderError::Vulkan(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
 #[repr(C)]
 #[derive(Debug, Clone)]
 struct Registers {
-    ndc_from_local: glam::Mat4,
+    world_from_local: glam::Mat4,
     colour: glam::Vec4,
+    normal: glam::Vec4,
 }
@@ -0,0 +1,354 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{
+    context::Context,
+    pipeline::{load_module, ShaderError},
+    swapchain::Drawable,
+};
+
+use crate::assets::AssetServer;
+
+/// The curve used to map HDR scene colours into the displayable range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Tonemapper {
+    #[default]
+    Aces,
+    AgX,
+    Reinhard,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 3] = [Tonemapper::Aces, Tonemapper::AgX, Tonemapper::Reinhard];
+
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::Aces => Tonemapper::AgX,
+            Tonemapper::AgX => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Aces,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PostProcessSettings {
+    /// A linear multiplier applied to the scene before tonemapping.
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0,
+            tonemapper: Tonemapper::default(),
+        }
+    }
+}
+
+/// A fullscreen pass that reads the HDR render target and writes the tonemapped result into the
+/// drawable.
+pub struct PostProcess {
+    handle: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    format: vk::Format,
+    descriptor_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    context: Arc<Context>,
+}
+
+impl PostProcess {
+    pub fn new(
+        context: Arc<Context>,
+        format: vk::Format,
+        hdr_view: vk::ImageView,
+        assets: &mut AssetServer,
+    ) -> Self {
+        let device = &context.device;
+
+        let descriptor_layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let descriptor_set = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&[descriptor_layout]),
+            )
+        }
+        .unwrap()[0];
+
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::NEAREST)
+                    .min_filter(vk::Filter::NEAREST)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )
+        }
+        .unwrap();
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .size(std::mem::size_of::<PostProcessRegisters>() as u32)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT)]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let handle = create_pipeline(&context, layout, format, assets)
+            .unwrap_or_else(|e| panic!("Couldn't create the post-process pipeline: {e}"));
+
+        let post_process = Self {
+            handle,
+            layout,
+            format,
+            descriptor_layout,
+            descriptor_pool,
+            descriptor_set,
+            sampler,
+            context,
+        };
+        post_process.set_input(hdr_view);
+        post_process
+    }
+
+    /// Recreates the pipeline with the latest shaders, keeping the old one if they're unusable.
+    /// The GPU must not be using the pipeline.
+    pub fn reload_shaders(&mut self, assets: &mut AssetServer) -> Result<(), ShaderError> {
+        let handle = create_pipeline(&self.context, self.layout, self.format, assets)?;
+        unsafe { self.context.device.destroy_pipeline(self.handle, None) };
+        self.handle = handle;
+        Ok(())
+    }
+
+    /// Points the pass at a new HDR image, eg. after the render targets have been recreated.
+    /// The GPU must not be using the descriptor set.
+    pub fn set_input(&self, hdr_view: vk::ImageView) {
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .image_view(hdr_view)
+                        .sampler(self.sampler)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            )
+        };
+    }
+
+    /// Records the pass, returning the number of draw calls. `draw_over` is called before the pass
+    /// ends, to draw anything that goes on top of the scene, such as the overlay; it returns its
+    /// own number of draw calls.
+    pub(crate) fn draw(
+        &self,
+        drawable: Drawable,
+        settings: &PostProcessSettings,
+        draw_over: impl FnOnce() -> u32,
+    ) -> u32 {
+        let device = &self.context.device;
+        let command_buffer = self.context.draw_command_buffer;
+        let render_area = drawable.extent;
+
+        let registers = PostProcessRegisters {
+            exposure: settings.exposure,
+            tonemapper: settings.tonemapper as u32,
+        };
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.handle);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_set_scissor(command_buffer, 0, &[render_area.into()]);
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport::default()
+                    .width(render_area.width as _)
+                    .height(render_area.height as _)
+                    .max_depth(1.)],
+            );
+
+            // Every pixel is overwritten, so there's no need to clear
+            device.cmd_begin_rendering(
+                command_buffer,
+                &vk::RenderingInfo::default()
+                    .render_area(render_area.into())
+                    .layer_count(1)
+                    .color_attachments(&[vk::RenderingAttachmentInfo::default()
+                        .image_view(drawable.view)
+                        .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                        .store_op(vk::AttachmentStoreOp::STORE)]),
+            );
+
+            device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::slice::from_raw_parts(
+                    &registers as *const _ as *const u8,
+                    std::mem::size_of::<PostProcessRegisters>(),
+                ),
+            );
+
+            // A single triangle that covers the whole screen
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+
+        let draw_calls = 1 + draw_over();
+
+        unsafe { device.cmd_end_rendering(command_buffer) };
+
+        draw_calls
+    }
+}
+
+impl Drop for PostProcess {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_pipeline(self.handle, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_layout, None);
+        }
+    }
+}
+
+/// Creates the pipeline that tonemaps the HDR target into a `format` attachment.
+fn create_pipeline(
+    context: &Context,
+    layout: vk::PipelineLayout,
+    format: vk::Format,
+    assets: &mut AssetServer,
+) -> Result<vk::Pipeline, ShaderError> {
+    let vertex = load_module(assets, "post_process.vertex.spv", context)?;
+    let fragment = load_module(assets, "post_process.fragment.spv", context)?;
+
+    let pipelines = unsafe {
+        context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::GraphicsPipelineCreateInfo::default()
+                .stages(&[
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(vertex.handle)
+                        .stage(vk::ShaderStageFlags::VERTEX),
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(fragment.handle)
+                        .stage(vk::ShaderStageFlags::FRAGMENT),
+                ])
+                .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                .input_assembly_state(
+                    &vk::PipelineInputAssemblyStateCreateInfo::default()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                )
+                .viewport_state(
+                    &vk::PipelineViewportStateCreateInfo::default()
+                        .scissor_count(1)
+                        .viewport_count(1),
+                )
+                .dynamic_state(
+                    &vk::PipelineDynamicStateCreateInfo::default()
+                        .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
+                )
+                .rasterization_state(
+                    &vk::PipelineRasterizationStateCreateInfo::default()
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .line_width(1.0),
+                )
+                .depth_stencil_state(&vk::PipelineDepthStencilStateCreateInfo::default())
+                .color_blend_state(
+                    &vk::PipelineColorBlendStateCreateInfo::default().attachments(&[
+                        vk::PipelineColorBlendAttachmentState::default()
+                            .blend_enable(false)
+                            .color_write_mask(vk::ColorComponentFlags::RGBA),
+                    ]),
+                )
+                .multisample_state(
+                    &vk::PipelineMultisampleStateCreateInfo::default()
+                        .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                )
+                .layout(layout)
+                .push_next(
+                    &mut vk::PipelineRenderingCreateInfo::default()
+                        .color_attachment_formats(&[format]),
+                )],
+            None,
+        )
+    }
+    .map_err(|(_, e)| ShaderError::Vulkan(e))?;
+
+    Ok(pipelines[0])
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+struct PostProcessRegisters {
+    exposure: f32,
+    tonemapper: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_cycles_through_every_tonemapper_in_order() {
+        let mut tonemapper = Tonemapper::default();
+        for expected in Tonemapper::ALL {
+            assert_eq!(tonemapper, expected);
+            tonemapper = tonemapper.next();
+        }
+
+        // And back round to the start
+        assert_eq!(tonemapper, Tonemapper::ALL[0]);
+    }
+}
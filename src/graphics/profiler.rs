@@ -0,0 +1,506 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ash::vk;
+use serde::Serialize;
+
+use super::context::Context;
+
+/// How many frames of queries can be waiting to be read back. A frame's results are only read
+/// once the GPU has finished with them, so they're never waited on.
+const QUERY_FRAMES: usize = 3;
+/// The most GPU scopes that can be measured in a single frame. Any more are ignored.
+const MAX_GPU_SCOPES: usize = 32;
+/// How many frames of history are kept for each scope.
+pub const HISTORY_LENGTH: usize = 120;
+
+/// Measures named regions of each frame, both on the CPU and on the GPU, where GPU regions are
+/// measured with timestamp queries.
+///
+/// Keeps a short history of each scope's duration, and can capture a trace of every scope to be
+/// viewed in `chrome://tracing` or Perfetto.
+pub struct Profiler {
+    query_frames: Vec<QueryFrame>,
+    /// The index into `query_frames` of the frame being recorded.
+    current: usize,
+    /// How many frames have been recorded so far.
+    frame_count: u64,
+    /// Nanoseconds per timestamp tick. `None` if the device can't write timestamps, in which case
+    /// GPU scopes do nothing.
+    timestamp_period: Option<f32>,
+    /// How deeply the next scope will be nested, on the GPU and the CPU.
+    gpu_depth: u32,
+    cpu_depth: u32,
+    /// All times in traces are measured from here.
+    epoch: Instant,
+    gpu_history: Vec<ScopeHistory>,
+    cpu_history: Vec<ScopeHistory>,
+    /// The total GPU time of each frame that's been read back since the last
+    /// [`Profiler::take_frame_times`], with the frame's index.
+    frame_times: Vec<(u64, Duration)>,
+    /// Set while capturing a trace.
+    trace: Option<Trace>,
+    context: Arc<Context>,
+}
+
+/// The timestamp queries for one frame.
+struct QueryFrame {
+    pool: vk::QueryPool,
+    /// The index of the frame that wrote these queries.
+    frame: u64,
+    /// When recording of the frame started, relative to the profiler's epoch.
+    started: Duration,
+    scopes: Vec<PendingScope>,
+    /// Set when the queries have been submitted but not read back yet.
+    in_flight: bool,
+}
+
+/// A GPU scope whose timestamps haven't been read back yet. Its queries are at `2 * index` and
+/// `2 * index + 1` in the pool.
+struct PendingScope {
+    name: &'static str,
+    depth: u32,
+}
+
+/// Marks a GPU scope between [`Profiler::begin_gpu`] and [`Profiler::end_gpu`].
+#[must_use]
+pub struct GpuScope(Option<usize>);
+
+/// Marks a CPU scope between [`Profiler::begin_cpu`] and [`Profiler::end_cpu`].
+#[must_use]
+pub struct CpuScope {
+    name: &'static str,
+    depth: u32,
+    start: Instant,
+}
+
+/// The recent durations of a scope.
+#[derive(Debug, Clone)]
+pub struct ScopeHistory {
+    pub name: &'static str,
+    /// How deeply the scope was nested inside others.
+    pub depth: u32,
+    samples: VecDeque<Duration>,
+}
+
+impl ScopeHistory {
+    fn new(name: &'static str, depth: u32) -> Self {
+        Self {
+            name,
+            depth,
+            samples: VecDeque::with_capacity(HISTORY_LENGTH),
+        }
+    }
+
+    fn push(&mut self, duration: Duration) {
+        if self.samples.len() == HISTORY_LENGTH {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(duration);
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+}
+
+impl Profiler {
+    pub fn new(context: Arc<Context>) -> Self {
+        let query_frames = (0..QUERY_FRAMES)
+            .map(|_| QueryFrame {
+                pool: unsafe {
+                    context.device.create_query_pool(
+                        &vk::QueryPoolCreateInfo::default()
+                            .query_type(vk::QueryType::TIMESTAMP)
+                            .query_count(2 * MAX_GPU_SCOPES as u32),
+                        None,
+                    )
+                }
+                .unwrap(),
+                frame: 0,
+                started: Duration::ZERO,
+                scopes: Vec::new(),
+                in_flight: false,
+            })
+            .collect();
+
+        Self {
+            query_frames,
+            current: 0,
+            frame_count: 0,
+            timestamp_period: context.timestamp_period,
+            gpu_depth: 0,
+            cpu_depth: 0,
+            epoch: Instant::now(),
+            gpu_history: Vec::new(),
+            cpu_history: Vec::new(),
+            frame_times: Vec::new(),
+            trace: None,
+            context,
+        }
+    }
+
+    /// Reads back any finished frames, then starts measuring a new one. Call this once the command
+    /// buffer has begun, before any GPU scopes.
+    pub fn begin_frame(&mut self, command_buffer: vk::CommandBuffer) {
+        self.resolve();
+
+        self.current = (self.frame_count % QUERY_FRAMES as u64) as usize;
+        let started = self.epoch.elapsed();
+        let query_frame = &mut self.query_frames[self.current];
+
+        // Rather than wait for results that are still missing after this many frames, drop them
+        query_frame.in_flight = false;
+        query_frame.frame = self.frame_count;
+        query_frame.started = started;
+        query_frame.scopes.clear();
+
+        if self.timestamp_period.is_some() {
+            unsafe {
+                self.context.device.cmd_reset_query_pool(
+                    command_buffer,
+                    query_frame.pool,
+                    0,
+                    2 * MAX_GPU_SCOPES as u32,
+                );
+            }
+        }
+    }
+
+    /// Finishes measuring the frame. Call this after the last GPU scope, before submitting.
+    pub fn end_frame(&mut self) {
+        self.query_frames[self.current].in_flight = true;
+        self.frame_count += 1;
+    }
+
+    /// Starts measuring the GPU work recorded from here until the matching
+    /// [`Profiler::end_gpu`].
+    pub fn begin_gpu(&mut self, command_buffer: vk::CommandBuffer, name: &'static str) -> GpuScope {
+        let query_frame = &mut self.query_frames[self.current];
+        if self.timestamp_period.is_none() || query_frame.scopes.len() == MAX_GPU_SCOPES {
+            return GpuScope(None);
+        }
+
+        let index = query_frame.scopes.len();
+        query_frame.scopes.push(PendingScope {
+            name,
+            depth: self.gpu_depth,
+        });
+        self.gpu_depth += 1;
+
+        unsafe {
+            self.context.device.cmd_write_timestamp2(
+                command_buffer,
+                vk::PipelineStageFlags2::TOP_OF_PIPE,
+                query_frame.pool,
+                2 * index as u32,
+            );
+        }
+
+        GpuScope(Some(index))
+    }
+
+    pub fn end_gpu(&mut self, command_buffer: vk::CommandBuffer, scope: GpuScope) {
+        let Some(index) = scope.0 else {
+            return;
+        };
+        self.gpu_depth -= 1;
+
+        unsafe {
+            self.context.device.cmd_write_timestamp2(
+                command_buffer,
+                vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+                self.query_frames[self.current].pool,
+                2 * index as u32 + 1,
+            );
+        }
+    }
+
+    /// Starts measuring CPU time until the matching [`Profiler::end_cpu`].
+    pub fn begin_cpu(&mut self, name: &'static str) -> CpuScope {
+        let depth = self.cpu_depth;
+        self.cpu_depth += 1;
+
+        CpuScope {
+            name,
+            depth,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn end_cpu(&mut self, scope: CpuScope) {
+        let duration = scope.start.elapsed();
+        self.cpu_depth -= 1;
+
+        history_for(&mut self.cpu_history, scope.name, scope.depth).push(duration);
+        if let Some(trace) = &mut self.trace {
+            trace.push(
+                scope.name,
+                TraceThread::Cpu,
+                scope.start - self.epoch,
+                duration,
+            );
+        }
+    }
+
+    /// Reads back the results of every frame the GPU has finished with, without waiting for any
+    /// that it hasn't.
+    pub fn resolve(&mut self) {
+        let Some(timestamp_period) = self.timestamp_period else {
+            return;
+        };
+        let to_duration =
+            |ticks: u64| Duration::from_nanos((ticks as f64 * timestamp_period as f64) as u64);
+
+        let mut query_frames: Vec<&mut QueryFrame> = self
+            .query_frames
+            .iter_mut()
+            .filter(|query_frame| query_frame.in_flight)
+            .collect();
+        query_frames.sort_by_key(|query_frame| query_frame.frame);
+
+        for query_frame in query_frames {
+            let scope_count = query_frame.scopes.len();
+            if scope_count == 0 {
+                query_frame.in_flight = false;
+                continue;
+            }
+
+            let mut timestamps = [0u64; 2 * MAX_GPU_SCOPES];
+            let timestamps = &mut timestamps[..2 * scope_count];
+            let result = unsafe {
+                self.context.device.get_query_pool_results(
+                    query_frame.pool,
+                    0,
+                    timestamps,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            match result {
+                Ok(()) => query_frame.in_flight = false,
+                Err(vk::Result::NOT_READY) => continue,
+                Err(e) => panic!("Failed to read timestamps: {e}"),
+            }
+
+            // GPU timestamps have their own clock, so line the frame up with when it was recorded
+            let first = timestamps.iter().step_by(2).copied().min().unwrap_or(0);
+            let last = timestamps
+                .iter()
+                .skip(1)
+                .step_by(2)
+                .copied()
+                .max()
+                .unwrap_or(0);
+            self.frame_times
+                .push((query_frame.frame, to_duration(last.saturating_sub(first))));
+
+            for (scope, times) in query_frame.scopes.iter().zip(timestamps.chunks_exact(2)) {
+                let duration = to_duration(times[1].saturating_sub(times[0]));
+                history_for(&mut self.gpu_history, scope.name, scope.depth).push(duration);
+
+                if let Some(trace) = &mut self.trace {
+                    let start = query_frame.started + to_duration(times[0].saturating_sub(first));
+                    trace.push(scope.name, TraceThread::Gpu, start, duration);
+                }
+            }
+        }
+    }
+
+    /// The recent history of each GPU scope, in the order they were first seen.
+    pub fn gpu_history(&self) -> &[ScopeHistory] {
+        &self.gpu_history
+    }
+
+    /// The recent history of each CPU scope, in the order they first finished.
+    pub fn cpu_history(&self) -> &[ScopeHistory] {
+        &self.cpu_history
+    }
+
+    /// Returns the total GPU time of each frame that's been read back since the last call, along
+    /// with the frame's index.
+    pub fn take_frame_times(&mut self) -> Vec<(u64, Duration)> {
+        std::mem::take(&mut self.frame_times)
+    }
+
+    /// Starts recording every scope into a trace.
+    pub fn start_trace(&mut self) {
+        self.trace = Some(Trace {
+            epoch: self.epoch,
+            events: Vec::new(),
+        });
+    }
+
+    /// Stops recording, and returns the trace if one was being recorded. Frames still on the GPU
+    /// are left out.
+    pub fn stop_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+}
+
+/// Returns the history for the scope called `name`, adding it if it's new.
+fn history_for<'a>(
+    histories: &'a mut Vec<ScopeHistory>,
+    name: &'static str,
+    depth: u32,
+) -> &'a mut ScopeHistory {
+    let index = match histories.iter().position(|history| history.name == name) {
+        Some(index) => index,
+        None => {
+            histories.push(ScopeHistory::new(name, depth));
+            histories.len() - 1
+        }
+    };
+    &mut histories[index]
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TraceThread {
+    Cpu = 1,
+    Gpu = 2,
+    Span = 3,
+}
+
+/// Every scope measured while tracing, in the Chrome trace event format.
+#[derive(Debug, Serialize)]
+pub struct Trace {
+    /// The profiler's epoch, which every event's timestamp is measured from.
+    #[serde(skip)]
+    epoch: Instant,
+    #[serde(rename = "traceEvents")]
+    events: Vec<TraceEvent>,
+}
+
+/// A complete event: one that has a start and a duration.
+#[derive(Debug, Serialize)]
+struct TraceEvent {
+    name: &'static str,
+    #[serde(rename = "cat")]
+    category: &'static str,
+    #[serde(rename = "ph")]
+    phase: &'static str,
+    /// In microseconds.
+    #[serde(rename = "ts")]
+    timestamp: f64,
+    /// In microseconds.
+    #[serde(rename = "dur")]
+    duration: f64,
+    pid: u32,
+    tid: u32,
+}
+
+impl Trace {
+    fn push(
+        &mut self,
+        name: &'static str,
+        thread: TraceThread,
+        start: Duration,
+        duration: Duration,
+    ) {
+        self.events.push(TraceEvent {
+            name,
+            category: match thread {
+                TraceThread::Cpu => "cpu",
+                TraceThread::Gpu => "gpu",
+                TraceThread::Span => "span",
+            },
+            phase: "X",
+            timestamp: start.as_secs_f64() * 1e6,
+            duration: duration.as_secs_f64() * 1e6,
+            pid: 1,
+            tid: thread as u32,
+        });
+    }
+
+    /// Adds a `tracing` span, on a track of its own below the CPU and GPU scopes.
+    pub fn push_span(&mut self, name: &'static str, start: Instant, duration: Duration) {
+        let start = start.saturating_duration_since(self.epoch);
+        self.push(name, TraceThread::Span, start, duration);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), TraceError> {
+        let contents = serde_json::to_string(self).map_err(TraceError::Json)?;
+        std::fs::write(path, contents).map_err(TraceError::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "{e}"),
+            TraceError::Json(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn trace_is_chrome_complete_events_on_a_track_each() {
+        let epoch = Instant::now();
+        let mut trace = Trace {
+            epoch,
+            events: Vec::new(),
+        };
+        trace.push(
+            "draw",
+            TraceThread::Cpu,
+            Duration::from_micros(1500),
+            Duration::from_micros(250),
+        );
+        trace.push(
+            "shadows",
+            TraceThread::Gpu,
+            Duration::from_millis(2),
+            Duration::from_micros(40),
+        );
+        trace.push_span(
+            "frame",
+            epoch + Duration::from_millis(1),
+            Duration::from_millis(16),
+        );
+
+        let event = |name, cat, ts, dur, tid| json!({ "name": name, "cat": cat, "ph": "X", "ts": ts, "dur": dur, "pid": 1, "tid": tid });
+        assert_eq!(
+            serde_json::to_value(&trace).unwrap(),
+            json!({
+                "traceEvents": [
+                    event("draw", "cpu", 1500., 250., 1),
+                    event("shadows", "gpu", 2000., 40., 2),
+                    event("frame", "span", 1000., 16000., 3),
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn spans_from_before_the_epoch_start_at_zero() {
+        let before = Instant::now();
+        let mut trace = Trace {
+            epoch: before + Duration::from_secs(1),
+            events: Vec::new(),
+        };
+        trace.push_span("startup", before, Duration::from_millis(5));
+
+        assert_eq!(trace.events[0].timestamp, 0.);
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::context::Context;
+
+/// An image that can be rendered into, such as a depth buffer or a multisampled colour buffer.
+pub struct RenderTarget {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub memory: vk::DeviceMemory,
+    pub format: vk::Format,
+    context: Arc<Context>,
+}
+
+impl RenderTarget {
+    pub fn new(
+        context: Arc<Context>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        usage: vk::ImageUsageFlags,
+    ) -> Self {
+        let device = &context.device;
+        let image = unsafe {
+            device.create_image(
+                &vk::ImageCreateInfo::default()
+                    .array_layers(1)
+                    .mip_levels(1)
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .samples(samples)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(usage)
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .extent(extent.into())
+                    .format(format),
+                None,
+            )
+        }
+        .unwrap();
+
+        let memory_requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let memory_type_index = context
+            .find_memory_type_index(&memory_requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            .expect("No memory type index for render target - impossible");
+
+        let memory = unsafe {
+            device.allocate_memory(
+                &vk::MemoryAllocateInfo::default()
+                    .allocation_size(memory_requirements.size)
+                    .memory_type_index(memory_type_index),
+                None,
+            )
+        }
+        .expect("Failed to allocate memory - impossible");
+
+        unsafe {
+            device.bind_image_memory2(&[vk::BindImageMemoryInfo::default()
+                .image(image)
+                .memory(memory)])
+        }
+        .unwrap();
+
+        let view = unsafe {
+            device.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .image(image)
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .format(format)
+                    .components(vk::ComponentMapping::default())
+                    .subresource_range(subresource_range(format)),
+                None,
+            )
+        }
+        .unwrap();
+
+        Self {
+            image,
+            view,
+            memory,
+            format,
+            context,
+        }
+    }
+
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        subresource_range(self.format)
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+fn subresource_range(format: vk::Format) -> vk::ImageSubresourceRange {
+    if format == DEPTH_FORMAT {
+        DEPTH_RANGE
+    } else {
+        super::FULL_IMAGE
+    }
+}
+
+/// The images the scene is rendered into before it's post-processed into the drawable.
+pub struct RenderTargets {
+    pub depth: RenderTarget,
+    /// The multisampled colour target that's resolved into `hdr`, if MSAA is enabled.
+    pub msaa_colour: Option<RenderTarget>,
+    /// The single-sampled, floating point colour target read by post-processing.
+    pub hdr: RenderTarget,
+    pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl RenderTargets {
+    pub fn new(
+        context: &Arc<Context>,
+        extent: vk::Extent2D,
+        samples: vk::SampleCountFlags,
+    ) -> Self {
+        let depth = RenderTarget::new(
+            context.clone(),
+            extent,
+            DEPTH_FORMAT,
+            samples,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+        );
+
+        let msaa_colour = (samples != vk::SampleCountFlags::TYPE_1).then(|| {
+            RenderTarget::new(
+                context.clone(),
+                extent,
+                HDR_FORMAT,
+                samples,
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            )
+        });
+
+        let hdr = RenderTarget::new(
+            context.clone(),
+            extent,
+            HDR_FORMAT,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+        );
+
+        Self {
+            depth,
+            msaa_colour,
+            hdr,
+            extent,
+            samples,
+        }
+    }
+}
+
+/// Returns the highest sample count supported by the device that doesn't exceed `requested`.
+pub fn clamp_sample_count(requested: u32, supported: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    [
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|&count| count.as_raw() <= requested && supported.contains(count))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
+
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+pub const DEPTH_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::DEPTH,
+    layer_count: 1,
+    level_count: 1,
+    base_mip_level: 0,
+    base_array_layer: 0,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UP_TO_4: vk::SampleCountFlags = vk::SampleCountFlags::from_raw(0b111);
+
+    #[test]
+    fn supported_counts_are_used_as_they_are() {
+        for count in [1, 2, 4] {
+            assert_eq!(clamp_sample_count(count, UP_TO_4).as_raw(), count);
+        }
+    }
+
+    #[test]
+    fn counts_above_the_device_limit_use_its_highest() {
+        assert_eq!(clamp_sample_count(8, UP_TO_4), vk::SampleCountFlags::TYPE_4);
+        assert_eq!(
+            clamp_sample_count(64, vk::SampleCountFlags::TYPE_1),
+            vk::SampleCountFlags::TYPE_1
+        );
+    }
+
+    #[test]
+    fn other_counts_round_down_to_a_power_of_two() {
+        assert_eq!(clamp_sample_count(3, UP_TO_4), vk::SampleCountFlags::TYPE_2);
+        assert_eq!(clamp_sample_count(7, UP_TO_4), vk::SampleCountFlags::TYPE_4);
+        assert_eq!(clamp_sample_count(0, UP_TO_4), vk::SampleCountFlags::TYPE_1);
+    }
+
+    #[test]
+    fn unsupported_counts_in_between_are_skipped() {
+        let supported = vk::SampleCountFlags::TYPE_1 | vk::SampleCountFlags::TYPE_8;
+        assert_eq!(
+            clamp_sample_count(4, supported),
+            vk::SampleCountFlags::TYPE_1
+        );
+        assert_eq!(
+            clamp_sample_count(8, supported),
+            vk::SampleCountFlags::TYPE_8
+        );
+    }
+}
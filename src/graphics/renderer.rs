@@ -1,28 +1,115 @@
-use std::{sync::Arc, u64};
+use std::sync::Arc;
 
 use ash::vk::{self};
+use tracing::{info, warn};
 
 use super::{
     camera::Camera,
     context::Context,
-    depth_buffer::{DepthBuffer, DEPTH_RANGE},
-    pipeline::Pipeline,
-    swapchain::{Drawable, Swapchain},
+    descriptors::Descriptors,
+    lighting::{Globals, Lighting},
+    offscreen::Offscreen,
+    overlay::{OverlayFrame, OverlayPass},
+    picking::IdPicker,
+    pipeline::{Cube, Pipeline, SHADER_DIR},
+    post_process::{PostProcess, PostProcessSettings},
+    profiler::Profiler,
+    render_target::{clamp_sample_count, RenderTargets},
+    shadows::{compute_cascades, ShadowMap, ShadowPipeline},
+    swapchain::{DisplaySettings, Drawable, Swapchain},
+    text::{Text, TextPass},
+    uploader::Uploader,
     FULL_IMAGE,
 };
 
+use crate::assets::AssetServer;
+
+/// Where finished frames end up.
+pub enum Output {
+    /// Presented to a window.
+    Window(Swapchain),
+    /// Left in an image, for rendering without a window.
+    Offscreen(Offscreen),
+}
+
+impl Output {
+    pub fn extent(&self) -> vk::Extent2D {
+        match self {
+            Output::Window(swapchain) => swapchain.extent,
+            Output::Offscreen(offscreen) => offscreen.extent,
+        }
+    }
+
+    pub fn format(&self) -> vk::Format {
+        match self {
+            Output::Window(swapchain) => swapchain.format,
+            Output::Offscreen(offscreen) => offscreen.target.format,
+        }
+    }
+
+    /// Returns the next image to render into, or `None` if the swapchain must be recreated.
+    fn get_drawable(&self) -> Option<Drawable> {
+        match self {
+            Output::Window(swapchain) => swapchain.get_drawable(),
+            Output::Offscreen(offscreen) => Some(offscreen.get_drawable()),
+        }
+    }
+
+    /// Presents the drawable if there's a window, returning `false` if the swapchain should be
+    /// recreated.
+    fn present(
+        &self,
+        drawable: Drawable,
+        queue: vk::Queue,
+        rendering_complete: vk::Semaphore,
+    ) -> bool {
+        match self {
+            Output::Window(swapchain) => swapchain.present(drawable, queue, rendering_complete),
+            Output::Offscreen(_) => true,
+        }
+    }
+}
+
 pub struct Renderer {
     pub pipeline: Pipeline,
     pub context: Arc<Context>,
     pub fence: vk::Fence,
     pub rendering_complete: vk::Semaphore,
-    pub swapchain: Swapchain,
-    pub depth_buffer: DepthBuffer,
+    pub output: Output,
+    pub targets: RenderTargets,
+    pub post_process: PostProcess,
+    pub overlay: OverlayPass,
+    pub text: TextPass,
+    pub descriptors: Descriptors,
+    pub shadow_map: ShadowMap,
+    pub shadow_pipeline: ShadowPipeline,
+    pub id_picker: IdPicker,
+    /// The pixel to pick with the ID buffer next frame, if any.
+    pub pick_request: Option<glam::UVec2>,
+    /// The result of the last ID buffer pick, once it's been read back.
+    pub picked: Option<Option<usize>>,
+    pub profiler: Profiler,
+    /// Where shaders and the font come from.
+    pub assets: AssetServer,
+    pub uploader: Uploader,
+    /// The number of draw calls recorded for the latest frame.
+    pub draw_calls: u32,
+    /// Set when the swapchain no longer matches the surface and must be recreated.
+    pub swapchain_out_of_date: bool,
 }
 
 impl Renderer {
-    pub(crate) fn new(context: Arc<Context>, swapchain: Swapchain) -> Self {
-        let pipeline = Pipeline::new(context.clone(), swapchain.format);
+    pub(crate) fn new(
+        context: Arc<Context>,
+        output: Output,
+        msaa_samples: u32,
+        mut assets: AssetServer,
+    ) -> Self {
+        let msaa_samples = clamp_sample_count(msaa_samples, context.supported_sample_counts);
+        let shadow_map = ShadowMap::new(&context);
+        let descriptors = Descriptors::new(context.clone(), shadow_map.view, shadow_map.sampler);
+        let shadow_pipeline = ShadowPipeline::new(context.clone(), &descriptors, &mut assets);
+        let pipeline = Pipeline::new(context.clone(), msaa_samples, &descriptors, &mut assets);
         let device = &context.device;
 
         let rendering_complete =
@@ -36,30 +123,203 @@ impl Renderer {
         }
         .unwrap();
 
-        let depth_buffer = DepthBuffer::new(&context, &swapchain);
+        let targets = RenderTargets::new(&context, output.extent(), msaa_samples);
+        let post_process = PostProcess::new(
+            context.clone(),
+            output.format(),
+            targets.hdr.view,
+            &mut assets,
+        );
+        let overlay = OverlayPass::new(context.clone(), output.format(), &mut assets);
+        let text = TextPass::new(context.clone(), msaa_samples, output.format(), &mut assets);
+        let id_picker = IdPicker::new(context.clone(), output.extent(), &descriptors, &mut assets);
+        let profiler = Profiler::new(context.clone());
+        let uploader = Uploader::new(context.clone());
 
         Self {
             pipeline,
             context,
             rendering_complete,
             fence,
-            swapchain,
-            depth_buffer,
+            output,
+            targets,
+            post_process,
+            overlay,
+            text,
+            descriptors,
+            shadow_map,
+            shadow_pipeline,
+            id_picker,
+            pick_request: None,
+            picked: None,
+            profiler,
+            assets,
+            uploader,
+            draw_calls: 0,
+            swapchain_out_of_date: false,
         }
     }
 
-    pub(crate) fn draw(&self, camera: &Camera) {
-        let drawable = self.begin_rendering();
-        self.pipeline.draw(drawable, self.depth_buffer, camera);
-        self.end_rendering(drawable);
-        self.swapchain.present(
+    pub(crate) fn draw(
+        &mut self,
+        camera: &Camera,
+        lighting: &Lighting,
+        post_process_settings: &PostProcessSettings,
+        cubes: &[Cube],
+        texts: &[Text],
+        overlay: &mut Option<OverlayFrame>,
+    ) {
+        self.update_assets();
+
+        let Some(drawable) = self.begin_rendering() else {
+            self.swapchain_out_of_date = true;
+            return;
+        };
+
+        // The previous frame has finished by now, so any pick it made can be read back
+        if let Some(picked) = self.id_picker.take_result() {
+            self.picked = Some(picked);
+        }
+        let command_buffer = self.context.draw_command_buffer;
+        self.profiler.begin_frame(command_buffer);
+        let frame_scope = self.profiler.begin_gpu(command_buffer, "frame");
+
+        // The previous frame has finished by now, so it's safe to update the globals
+        let cascades = compute_cascades(camera, lighting);
+        self.descriptors
+            .globals
+            .write(Globals::new(camera, lighting, &cascades));
+
+        // New textures are copied on the transfer queue, and the frame waits for them before
+        // sampling. Only take the overlay once it's certain to be drawn, so nothing it changed is
+        // lost
+        self.text
+            .prepare(texts, camera, &self.assets, &mut self.uploader);
+        let overlay = overlay.take();
+        if let Some(overlay) = &overlay {
+            self.overlay.prepare(overlay, &mut self.uploader);
+        }
+        let upload_wait = self.uploader.submit(command_buffer);
+
+        let scope = self.profiler.begin_gpu(command_buffer, "shadows");
+        let mut draw_calls = self
+            .shadow_pipeline
+            .draw(&self.shadow_map, &self.descriptors, cubes);
+        self.profiler.end_gpu(command_buffer, scope);
+
+        let scope = self.profiler.begin_gpu(command_buffer, "scene");
+        draw_calls += self.pipeline.draw(
+            &self.targets,
+            &self.descriptors,
+            lighting.clear_colour,
+            cubes,
+            || self.text.draw_world(camera),
+        );
+        self.profiler.end_gpu(command_buffer, scope);
+
+        if let Some(pixel) = self.pick_request.take() {
+            let scope = self.profiler.begin_gpu(command_buffer, "picking");
+            draw_calls += self.id_picker.draw(&self.descriptors, cubes, pixel);
+            self.profiler.end_gpu(command_buffer, scope);
+        }
+
+        let scope = self.profiler.begin_gpu(command_buffer, "post-process");
+        self.hdr_to_shader_read();
+        draw_calls += self.post_process.draw(drawable, post_process_settings, || {
+            let mut draw_calls = self.text.draw_screen(drawable.extent);
+            if let Some(overlay) = &overlay {
+                let scope = self.profiler.begin_gpu(command_buffer, "overlay");
+                draw_calls += self.overlay.draw(overlay, drawable.extent);
+                self.profiler.end_gpu(command_buffer, scope);
+            }
+            draw_calls
+        });
+        self.profiler.end_gpu(command_buffer, scope);
+        self.draw_calls = draw_calls;
+
+        self.profiler.end_gpu(command_buffer, frame_scope);
+        self.profiler.end_frame();
+        self.end_rendering(drawable, upload_wait);
+        if !self.output.present(
             drawable,
             self.context.graphics_queue,
             self.rendering_complete,
+        ) {
+            self.swapchain_out_of_date = true;
+        }
+    }
+
+    /// Recreates the swapchain, and the render targets if its size has changed. Does nothing when
+    /// rendering offscreen.
+    pub fn recreate_swapchain(&mut self, extent: vk::Extent2D, settings: &DisplaySettings) {
+        let Output::Window(swapchain) = &mut self.output else {
+            return;
+        };
+
+        unsafe { self.context.device.device_wait_idle() }.unwrap();
+
+        swapchain.recreate(&self.context.device, extent, settings);
+        self.swapchain_out_of_date = false;
+
+        if self.targets.extent != extent {
+            self.targets = RenderTargets::new(&self.context, extent, self.targets.samples);
+            self.post_process.set_input(self.targets.hdr.view);
+            self.id_picker.resize(extent);
+        }
+    }
+
+    /// Changes the number of MSAA samples per pixel, recreating the render targets and pipeline.
+    /// The count is clamped to what the device supports; 1 disables multisampling.
+    pub fn set_msaa_samples(&mut self, samples: u32) {
+        let samples = clamp_sample_count(samples, self.context.supported_sample_counts);
+        if samples == self.targets.samples {
+            return;
+        }
+
+        unsafe { self.context.device.device_wait_idle() }.unwrap();
+
+        self.targets = RenderTargets::new(&self.context, self.output.extent(), samples);
+        self.post_process.set_input(self.targets.hdr.view);
+        let wireframe = self.pipeline.wireframe;
+        self.pipeline = Pipeline::new(
+            self.context.clone(),
+            samples,
+            &self.descriptors,
+            &mut self.assets,
         );
+        self.pipeline.wireframe = wireframe;
+        self.text.set_samples(samples, &mut self.assets);
+    }
+
+    /// Picks up assets that have finished loading, and recreates every pipeline if any shaders
+    /// have been reloaded.
+    fn update_assets(&mut self) {
+        let reloaded = self.assets.update();
+        if !reloaded.iter().any(|path| path.starts_with(SHADER_DIR)) {
+            return;
+        }
+
+        unsafe { self.context.device.device_wait_idle() }.unwrap();
+
+        // A pass whose new shaders don't work carries on with its old ones
+        let assets = &mut self.assets;
+        let results = [
+            ("shadow", self.shadow_pipeline.reload_shaders(assets)),
+            ("scene", self.pipeline.reload_shaders(assets)),
+            ("ID buffer", self.id_picker.reload_shaders(assets)),
+            ("post-process", self.post_process.reload_shaders(assets)),
+            ("overlay", self.overlay.reload_shaders(assets)),
+            ("text", self.text.reload_shaders(assets)),
+        ];
+        for (pass, result) in results {
+            match result {
+                Ok(()) => info!("Reloaded the {pass} shaders"),
+                Err(e) => warn!("Couldn't reload the {pass} shaders, so keeping the old ones: {e}"),
+            }
+        }
     }
 
-    fn begin_rendering(&self) -> Drawable {
+    fn begin_rendering(&self) -> Option<Drawable> {
         let device = &self.context.device;
 
         // Block the CPU until we're done rendering the previous frame
@@ -67,9 +327,13 @@ impl Renderer {
             device
                 .wait_for_fences(&[self.fence], true, u64::MAX)
                 .unwrap();
-            device.reset_fences(&[self.fence]).unwrap();
         }
 
+        // Get a `Drawable` from the output. The fence is only reset once we know we'll be
+        // submitting work that signals it.
+        let drawable = self.output.get_drawable()?;
+        unsafe { device.reset_fences(&[self.fence]).unwrap() };
+
         // Begin the command buffer
         let command_buffer = self.context.draw_command_buffer;
         unsafe {
@@ -78,50 +342,118 @@ impl Renderer {
                 .unwrap()
         };
 
-        // Get a `Drawable` from the swapchain
-        let drawable = self.swapchain.get_drawable();
-
         // Transition the rendering attachments into their correct state
+        let mut barriers = vec![
+            // Swapchain image
+            vk::ImageMemoryBarrier2::default()
+                .subresource_range(FULL_IMAGE)
+                .image(drawable.image)
+                .src_access_mask(vk::AccessFlags2::NONE)
+                .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            // HDR target
+            vk::ImageMemoryBarrier2::default()
+                .subresource_range(self.targets.hdr.subresource_range())
+                .image(self.targets.hdr.image)
+                .src_access_mask(vk::AccessFlags2::NONE)
+                .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            // Depth buffer
+            vk::ImageMemoryBarrier2::default()
+                .subresource_range(self.targets.depth.subresource_range())
+                .image(self.targets.depth.image)
+                .src_access_mask(vk::AccessFlags2::empty())
+                .src_stage_mask(vk::PipelineStageFlags2::empty())
+                .dst_access_mask(
+                    vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                )
+                .dst_stage_mask(vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS)
+                .old_layout(vk::ImageLayout::UNDEFINED)
+                .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL),
+        ];
+
+        // Multisampled colour target
+        if let Some(msaa_colour) = &self.targets.msaa_colour {
+            barriers.push(
+                vk::ImageMemoryBarrier2::default()
+                    .subresource_range(msaa_colour.subresource_range())
+                    .image(msaa_colour.image)
+                    .src_access_mask(vk::AccessFlags2::NONE)
+                    .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                    .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            );
+        }
+
         unsafe {
             device.cmd_pipeline_barrier2(
                 command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&barriers),
+            );
+        }
+
+        Some(drawable)
+    }
+
+    /// Waits for the scene to finish rendering into the HDR target, then makes it readable by
+    /// post-processing.
+    fn hdr_to_shader_read(&self) {
+        let hdr = &self.targets.hdr;
+        unsafe {
+            self.context.device.cmd_pipeline_barrier2(
+                self.context.draw_command_buffer,
                 &vk::DependencyInfo::default().image_memory_barriers(&[
-                    // Swapchain image
                     vk::ImageMemoryBarrier2::default()
-                        .subresource_range(FULL_IMAGE)
-                        .image(drawable.image)
-                        .src_access_mask(vk::AccessFlags2::NONE)
+                        .subresource_range(hdr.subresource_range())
+                        .image(hdr.image)
+                        .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
                         .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-                        .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
-                        .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
-                        .old_layout(vk::ImageLayout::UNDEFINED)
-                        .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
-                    // Depth buffer
-                    vk::ImageMemoryBarrier2::default()
-                        .subresource_range(DEPTH_RANGE)
-                        .image(self.depth_buffer.image)
-                        .src_access_mask(vk::AccessFlags2::empty())
-                        .src_stage_mask(vk::PipelineStageFlags2::empty())
-                        .dst_access_mask(
-                            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
-                                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
-                        )
-                        .dst_stage_mask(vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS)
-                        .old_layout(vk::ImageLayout::UNDEFINED)
-                        .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL),
+                        .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+                        .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+                        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
                 ]),
             );
         }
-
-        drawable
     }
 
-    fn end_rendering(&self, drawable: Drawable) {
+    /// Submits the frame, once `upload_wait` says any uploads it uses have finished.
+    fn end_rendering(&self, drawable: Drawable, upload_wait: Option<vk::SemaphoreSubmitInfo>) {
         let device = &self.context.device;
         let queue = self.context.graphics_queue;
         let command_buffer = self.context.draw_command_buffer;
         let swapchain_image = drawable.image;
 
+        // Offscreen images are never presented, so leave them ready to be copied out instead
+        let final_layout = match drawable.ready {
+            Some(_) => vk::ImageLayout::PRESENT_SRC_KHR,
+            None => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        };
+        let wait_semaphores: Vec<_> = drawable
+            .ready
+            .map(|ready| {
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(ready)
+                    .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+            })
+            .into_iter()
+            .chain(upload_wait)
+            .collect();
+        let signal_semaphore = drawable.ready.map(|_| {
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(self.rendering_complete)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+        });
+
         unsafe {
             // First, transition the color attachment into the present state
             device.cmd_pipeline_barrier2(
@@ -135,7 +467,7 @@ impl Renderer {
                         .dst_access_mask(vk::AccessFlags2::NONE)
                         .dst_stage_mask(vk::PipelineStageFlags2::BOTTOM_OF_PIPE)
                         .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                        .new_layout(vk::ImageLayout::PRESENT_SRC_KHR),
+                        .new_layout(final_layout),
                 ]),
             );
 
@@ -150,15 +482,29 @@ impl Renderer {
                         .command_buffer_infos(&[
                             vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)
                         ])
-                        .wait_semaphore_infos(&[vk::SemaphoreSubmitInfo::default()
-                            .semaphore(drawable.ready)
-                            .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)])
-                        .signal_semaphore_infos(&[vk::SemaphoreSubmitInfo::default()
-                            .semaphore(self.rendering_complete)
-                            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)])],
+                        .wait_semaphore_infos(&wait_semaphores)
+                        .signal_semaphore_infos(signal_semaphore.as_slice())],
                     self.fence,
                 )
                 .unwrap();
         }
     }
+
+    /// Waits for the GPU to finish everything it's been given, then reads back the results of the
+    /// last frame.
+    pub fn wait_idle(&mut self) {
+        unsafe { self.context.device.device_wait_idle() }.unwrap();
+
+        if let Some(picked) = self.id_picker.take_result() {
+            self.picked = Some(picked);
+        }
+        self.profiler.resolve();
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        // The passes destroy their objects as they're dropped, which the GPU may still be using
+        unsafe { self.context.device.device_wait_idle() }.unwrap();
+    }
 }
@@ -0,0 +1,538 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{
+    camera::Camera,
+    context::Context,
+    descriptors::Descriptors,
+    lighting::Lighting,
+    pipeline::{cube_faces, load_module, Cube, ShaderError},
+    render_target::DEPTH_FORMAT,
+};
+
+use crate::assets::AssetServer;
+
+pub const SHADOW_CASCADE_COUNT: usize = 4;
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+
+/// The closest distance to the camera the first cascade starts at. Using the camera's actual near
+/// plane would squash the logarithmic split scheme into the first few centimetres.
+const CASCADE_NEAR: f32 = 0.1;
+
+/// How far behind each cascade, along the sun's direction, objects can still cast shadows into it.
+const CASTER_DISTANCE: f32 = 100.;
+
+/// One slice of the view frustum and the light-space projection that covers it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Cascade {
+    pub ndc_from_world: glam::Mat4,
+    /// The view-space depth at which this cascade ends.
+    pub split_depth: f32,
+    /// The world-space size of one shadow map texel.
+    pub texel_size: f32,
+}
+
+/// Splits the camera's view frustum into cascades and fits an orthographic projection around each.
+///
+/// Each cascade is fitted to the bounding sphere of its frustum slice rather than the slice itself,
+/// so its size doesn't change as the camera rotates, and its origin is snapped to whole shadow map
+/// texels so shadow edges don't shimmer as the camera moves.
+pub(crate) fn compute_cascades(
+    camera: &Camera,
+    lighting: &Lighting,
+) -> [Cascade; SHADOW_CASCADE_COUNT] {
+    let near = CASCADE_NEAR;
+    let far = lighting.shadow_distance.max(near + 1.);
+    let lambda = lighting.cascade_split_lambda.clamp(0., 1.);
+
+    let direction = lighting
+        .sun_direction
+        .try_normalize()
+        .unwrap_or(glam::Vec3::NEG_Y);
+    let up = if direction.y.abs() > 0.99 {
+        glam::Vec3::Z
+    } else {
+        glam::Vec3::Y
+    };
+    let light_from_world = glam::Mat4::look_to_rh(glam::Vec3::ZERO, direction, up);
+    let world_from_light = light_from_world.inverse();
+
+    let mut split_near = near;
+    std::array::from_fn(|i| {
+        // The "practical split scheme": a blend of logarithmic and uniform splits
+        let p = (i + 1) as f32 / SHADOW_CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(p);
+        let uniform = near + (far - near) * p;
+        let split_far = lambda * logarithmic + (1. - lambda) * uniform;
+
+        let corners = camera.frustum_corners(split_near, split_far);
+        split_near = split_far;
+
+        let centre = corners.iter().sum::<glam::Vec3>() / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|c| c.distance(centre))
+            .fold(0., f32::max);
+
+        // Round the radius up so floating point noise doesn't change the cascade's size
+        let radius = (radius * 16.).ceil() / 16.;
+        let texel_size = 2. * radius / SHADOW_MAP_SIZE as f32;
+
+        // Snap the centre to the texel grid in light space
+        let mut centre = light_from_world.transform_point3(centre);
+        centre.x = (centre.x / texel_size).floor() * texel_size;
+        centre.y = (centre.y / texel_size).floor() * texel_size;
+        let centre = world_from_light.transform_point3(centre);
+
+        let eye = centre - direction * (radius + CASTER_DISTANCE);
+        let view_from_world = glam::Mat4::look_to_rh(eye, direction, up);
+
+        // Reverse-Z, to match the main pass
+        let depth = 2. * radius + CASTER_DISTANCE;
+        let projection = glam::Mat4::orthographic_rh(-radius, radius, -radius, radius, depth, 0.);
+
+        Cascade {
+            ndc_from_world: projection * view_from_world,
+            split_depth: split_far,
+            texel_size,
+        }
+    })
+}
+
+/// A layered depth image with one layer per cascade.
+pub struct ShadowMap {
+    pub image: vk::Image,
+    /// A view of every layer, used for sampling.
+    pub view: vk::ImageView,
+    /// A view of each individual layer, used for rendering.
+    pub layer_views: [vk::ImageView; SHADOW_CASCADE_COUNT],
+    pub sampler: vk::Sampler,
+    #[allow(unused)]
+    pub memory: vk::DeviceMemory,
+}
+
+impl ShadowMap {
+    pub(crate) fn new(context: &Context) -> Self {
+        let device = &context.device;
+        let image = unsafe {
+            device.create_image(
+                &vk::ImageCreateInfo::default()
+                    .array_layers(SHADOW_CASCADE_COUNT as u32)
+                    .mip_levels(1)
+                    .image_type(vk::ImageType::TYPE_2D)
+                    .samples(vk::SampleCountFlags::TYPE_1)
+                    .tiling(vk::ImageTiling::OPTIMAL)
+                    .usage(
+                        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT
+                            | vk::ImageUsageFlags::SAMPLED,
+                    )
+                    .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                    .initial_layout(vk::ImageLayout::UNDEFINED)
+                    .extent(vk::Extent3D {
+                        width: SHADOW_MAP_SIZE,
+                        height: SHADOW_MAP_SIZE,
+                        depth: 1,
+                    })
+                    .format(DEPTH_FORMAT),
+                None,
+            )
+        }
+        .unwrap();
+
+        let memory_requirements = unsafe { device.get_image_memory_requirements(image) };
+
+        let memory_type_index = context
+            .find_memory_type_index(&memory_requirements, vk::MemoryPropertyFlags::DEVICE_LOCAL)
+            .expect("No memory type index for shadow map - impossible");
+
+        let memory = unsafe {
+            device.allocate_memory(
+                &vk::MemoryAllocateInfo::default()
+                    .allocation_size(memory_requirements.size)
+                    .memory_type_index(memory_type_index),
+                None,
+            )
+        }
+        .expect("Failed to allocate memory - impossible");
+
+        unsafe { device.bind_image_memory(image, memory, 0) }.unwrap();
+
+        let create_view = |view_type, base_array_layer, layer_count| unsafe {
+            device
+                .create_image_view(
+                    &vk::ImageViewCreateInfo::default()
+                        .image(image)
+                        .view_type(view_type)
+                        .format(DEPTH_FORMAT)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::DEPTH,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer,
+                            layer_count,
+                        }),
+                    None,
+                )
+                .unwrap()
+        };
+
+        let view = create_view(
+            vk::ImageViewType::TYPE_2D_ARRAY,
+            0,
+            SHADOW_CASCADE_COUNT as u32,
+        );
+        let layer_views =
+            std::array::from_fn(|i| create_view(vk::ImageViewType::TYPE_2D, i as u32, 1));
+
+        // Areas outside the shadow map read as the far plane, so they're always lit
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_BORDER)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK)
+                    .compare_enable(true)
+                    .compare_op(vk::CompareOp::GREATER_OR_EQUAL)
+                    .max_lod(1.),
+                None,
+            )
+        }
+        .unwrap();
+
+        Self {
+            image,
+            view,
+            layer_views,
+            sampler,
+            memory,
+        }
+    }
+}
+
+/// The depth-only pipeline that renders each cascade of the [`ShadowMap`].
+pub struct ShadowPipeline {
+    handle: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    context: Arc<Context>,
+}
+
+impl ShadowPipeline {
+    pub fn new(context: Arc<Context>, descriptors: &Descriptors, assets: &mut AssetServer) -> Self {
+        let device = &context.device;
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptors.layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .size(std::mem::size_of::<ShadowRegisters>() as u32)
+                        .stage_flags(vk::ShaderStageFlags::VERTEX)]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let handle = create_pipeline(&context, layout, assets)
+            .unwrap_or_else(|e| panic!("Couldn't create the shadow pipeline: {e}"));
+
+        Self {
+            handle,
+            layout,
+            context,
+        }
+    }
+
+    /// Renders every cascade, leaving the shadow map ready to be sampled by the fragment shader.
+    /// Returns the number of draw calls.
+    /// Recreates the pipeline with the latest shaders, keeping the old one if they're unusable.
+    /// The GPU must not be using the pipeline.
+    pub fn reload_shaders(&mut self, assets: &mut AssetServer) -> Result<(), ShaderError> {
+        let handle = create_pipeline(&self.context, self.layout, assets)?;
+        unsafe { self.context.device.destroy_pipeline(self.handle, None) };
+        self.handle = handle;
+        Ok(())
+    }
+
+    pub(crate) fn draw(
+        &self,
+        shadow_map: &ShadowMap,
+        descriptors: &Descriptors,
+        cubes: &[Cube],
+    ) -> u32 {
+        let device = &self.context.device;
+        let command_buffer = self.context.draw_command_buffer;
+        let full_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::DEPTH,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: SHADOW_CASCADE_COUNT as u32,
+        };
+        let render_area = vk::Extent2D {
+            width: SHADOW_MAP_SIZE,
+            height: SHADOW_MAP_SIZE,
+        };
+        let mut draw_calls = 0;
+
+        unsafe {
+            // Last frame's contents can be discarded
+            device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[
+                    vk::ImageMemoryBarrier2::default()
+                        .subresource_range(full_range)
+                        .image(shadow_map.image)
+                        .src_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+                        .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+                        .dst_access_mask(
+                            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                        )
+                        .dst_stage_mask(vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS)
+                        .old_layout(vk::ImageLayout::UNDEFINED)
+                        .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL),
+                ]),
+            );
+
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.handle);
+            descriptors.bind(device, command_buffer, self.layout);
+            device.cmd_set_scissor(command_buffer, 0, &[render_area.into()]);
+            device.cmd_set_viewport(
+                command_buffer,
+                0,
+                &[vk::Viewport::default()
+                    .width(render_area.width as _)
+                    .height(render_area.height as _)
+                    .max_depth(1.)],
+            );
+
+            for (cascade, view) in shadow_map.layer_views.iter().enumerate() {
+                device.cmd_begin_rendering(
+                    command_buffer,
+                    &vk::RenderingInfo::default()
+                        .render_area(render_area.into())
+                        .layer_count(1)
+                        .depth_attachment(
+                            &vk::RenderingAttachmentInfo::default()
+                                .image_view(*view)
+                                .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                                .load_op(vk::AttachmentLoadOp::CLEAR)
+                                .store_op(vk::AttachmentStoreOp::STORE)
+                                .clear_value(vk::ClearValue {
+                                    depth_stencil: vk::ClearDepthStencilValue {
+                                        depth: 0.0,
+                                        stencil: 0,
+                                    },
+                                }),
+                        ),
+                );
+
+                for cube in cubes {
+                    for world_from_local in cube_faces(cube.transform) {
+                        let registers = ShadowRegisters {
+                            world_from_local: world_from_local.into(),
+                            cascade: cascade as u32,
+                            _pad: [0; 3],
+                        };
+
+                        device.cmd_push_constants(
+                            command_buffer,
+                            self.layout,
+                            vk::ShaderStageFlags::VERTEX,
+                            0,
+                            std::slice::from_raw_parts(
+                                &registers as *const _ as *const u8,
+                                std::mem::size_of::<ShadowRegisters>(),
+                            ),
+                        );
+                        device.cmd_draw(command_buffer, 6, 1, 0, 0);
+                        draw_calls += 1;
+                    }
+                }
+
+                device.cmd_end_rendering(command_buffer);
+            }
+
+            // Make the shadow map available to the main pass
+            device.cmd_pipeline_barrier2(
+                command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[
+                    vk::ImageMemoryBarrier2::default()
+                        .subresource_range(full_range)
+                        .image(shadow_map.image)
+                        .src_access_mask(vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                        .src_stage_mask(vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS)
+                        .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+                        .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+                        .old_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+                ]),
+            );
+        }
+
+        draw_calls
+    }
+}
+
+impl Drop for ShadowPipeline {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_pipeline(self.handle, None);
+            device.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
+
+/// Creates the depth-only pipeline that draws cubes into one cascade at a time.
+fn create_pipeline(
+    context: &Context,
+    layout: vk::PipelineLayout,
+    assets: &mut AssetServer,
+) -> Result<vk::Pipeline, ShaderError> {
+    let vertex = load_module(assets, "shadow.vertex.spv", context)?;
+
+    let pipelines = unsafe {
+        context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::GraphicsPipelineCreateInfo::default()
+                .stages(&[vk::PipelineShaderStageCreateInfo::default()
+                    .name(c"main")
+                    .module(vertex.handle)
+                    .stage(vk::ShaderStageFlags::VERTEX)])
+                .vertex_input_state(&vk::PipelineVertexInputStateCreateInfo::default())
+                .input_assembly_state(
+                    &vk::PipelineInputAssemblyStateCreateInfo::default()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                )
+                .viewport_state(
+                    &vk::PipelineViewportStateCreateInfo::default()
+                        .scissor_count(1)
+                        .viewport_count(1),
+                )
+                .dynamic_state(
+                    &vk::PipelineDynamicStateCreateInfo::default()
+                        .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
+                )
+                // Both sides of each quad cast shadows. The bias is negative as we're using
+                // reverse-Z: it pushes casters away from the light.
+                .rasterization_state(
+                    &vk::PipelineRasterizationStateCreateInfo::default()
+                        .front_face(vk::FrontFace::COUNTER_CLOCKWISE)
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .depth_bias_enable(true)
+                        .depth_bias_constant_factor(-1.25)
+                        .depth_bias_slope_factor(-1.75)
+                        .line_width(1.0),
+                )
+                .depth_stencil_state(
+                    &vk::PipelineDepthStencilStateCreateInfo::default()
+                        .depth_write_enable(true)
+                        .depth_test_enable(true)
+                        .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL)
+                        .max_depth_bounds(1.),
+                )
+                .color_blend_state(&vk::PipelineColorBlendStateCreateInfo::default())
+                .multisample_state(
+                    &vk::PipelineMultisampleStateCreateInfo::default()
+                        .rasterization_samples(vk::SampleCountFlags::TYPE_1),
+                )
+                .layout(layout)
+                .push_next(
+                    &mut vk::PipelineRenderingCreateInfo::default()
+                        .depth_attachment_format(DEPTH_FORMAT),
+                )],
+            None,
+        )
+    }
+    .map_err(|(_, e)| ShaderError::Vulkan(e))?;
+
+    Ok(pipelines[0])
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+struct ShadowRegisters {
+    world_from_local: glam::Mat4,
+    cascade: u32,
+    _pad: [u32; 3],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::Pose;
+
+    fn camera(position: glam::Vec3) -> Camera {
+        let mut camera = Camera::new(vk::Extent2D {
+            width: 1280,
+            height: 720,
+        });
+        camera.set_pose(Pose {
+            position,
+            rotation: glam::Quat::from_rotation_y(0.3) * glam::Quat::from_rotation_x(-0.2),
+        });
+        camera
+    }
+
+    #[test]
+    fn splits_increase_up_to_the_shadow_distance() {
+        for cascade_split_lambda in [0., 0.5, 0.75, 1.] {
+            let lighting = Lighting {
+                cascade_split_lambda,
+                ..Default::default()
+            };
+            let cascades = compute_cascades(&camera(glam::Vec3::ZERO), &lighting);
+
+            let mut previous = CASCADE_NEAR;
+            for cascade in &cascades {
+                assert!(cascade.split_depth > previous, "{cascades:?}");
+                previous = cascade.split_depth;
+            }
+            let last = cascades[SHADOW_CASCADE_COUNT - 1].split_depth;
+            assert!((last - lighting.shadow_distance).abs() < 1e-3, "{last}");
+        }
+    }
+
+    #[test]
+    fn each_cascade_contains_its_slice_of_the_frustum() {
+        let camera = camera(glam::Vec3::new(3., 10., -7.));
+        let cascades = compute_cascades(&camera, &Lighting::default());
+
+        let mut split_near = CASCADE_NEAR;
+        for cascade in &cascades {
+            for corner in camera.frustum_corners(split_near, cascade.split_depth) {
+                let ndc = cascade.ndc_from_world.transform_point3(corner);
+                assert!(ndc.x.abs() <= 1. && ndc.y.abs() <= 1., "{ndc}");
+                assert!((0. ..=1.).contains(&ndc.z), "{ndc}");
+            }
+            split_near = cascade.split_depth;
+        }
+    }
+
+    #[test]
+    fn cascades_are_snapped_to_whole_texels() {
+        let lighting = Lighting::default();
+        let texel_offsets = |position| {
+            compute_cascades(&camera(position), &lighting).map(|cascade| {
+                // Where the world origin lands on the shadow map, in texels from its centre
+                let ndc = cascade.ndc_from_world.transform_point3(glam::Vec3::ZERO);
+                ndc.truncate() * SHADOW_MAP_SIZE as f32 / 2.
+            })
+        };
+
+        let start = glam::Vec3::new(3., 10., -7.);
+        for offset in [0., 0.01, 0.13, 0.5] {
+            let moved = texel_offsets(start + glam::Vec3::new(offset, 0., offset / 2.));
+            for texels in moved {
+                let error = (texels - texels.round()).abs().max_element();
+                assert!(error < 0.01, "{texels} isn't a whole number of texels");
+            }
+        }
+    }
+}
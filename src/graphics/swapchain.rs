@@ -1,6 +1,34 @@
 use ash::vk;
+use tracing::{debug, info};
 use winit::raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
+/// Controls how frames are presented to the display. Changing these at runtime recreates the
+/// swapchain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplaySettings {
+    /// Wait for vertical blank before presenting. Disabling this prefers immediate presentation,
+    /// which allows tearing.
+    pub vsync: bool,
+    /// Use mailbox presentation when the surface supports it: no tearing, but with the latency of
+    /// vsync off.
+    pub prefer_mailbox: bool,
+    /// The number of swapchain images to request. Defaults to one more than the surface minimum.
+    pub image_count: Option<u32>,
+    /// Limits how many frames per second the CPU will submit.
+    pub frame_limit: Option<f32>,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self {
+            vsync: true,
+            prefer_mailbox: true,
+            image_count: None,
+            frame_limit: None,
+        }
+    }
+}
+
 pub struct Swapchain {
     #[allow(unused)]
     pub surface_handle: vk::SurfaceKHR,
@@ -12,6 +40,8 @@ pub struct Swapchain {
     pub image_views: Vec<vk::ImageView>,
     pub extent: vk::Extent2D,
     pub format: vk::Format,
+    pub present_mode: vk::PresentModeKHR,
+    physical_device: vk::PhysicalDevice,
     image_available: vk::Semaphore,
 }
 
@@ -20,7 +50,7 @@ impl Swapchain {
         device: &ash::Device,
         core: &super::core::Core,
         window: &winit::window::Window,
-        old_swapchain: vk::SwapchainKHR,
+        settings: &DisplaySettings,
     ) -> Self {
         let entry = &core.entry;
         let instance = &core.instance;
@@ -44,25 +74,77 @@ impl Swapchain {
             .iter()
             .find(|&&f| surface_formats.iter().any(|sf| sf.format == f))
             .expect("Desired swapchain format unavailable");
+        info!(
+            ?format,
+            available = surface_formats.len(),
+            "Chose swapchain format"
+        );
 
-        let capabilities = unsafe {
-            surface_fn
-                .get_physical_device_surface_capabilities(core.physical_device, surface_handle)
-        }
-        .unwrap();
+        let image_available =
+            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }.unwrap();
+
+        let mut swapchain = Self {
+            surface_handle,
+            surface_fn,
+            swapchain_handle: vk::SwapchainKHR::null(),
+            swapchain_fn: ash::khr::swapchain::Device::new(instance, device),
+            images: Vec::new(),
+            image_views: Vec::new(),
+            extent: vk::Extent2D::default(),
+            format,
+            present_mode: vk::PresentModeKHR::FIFO,
+            physical_device: core.physical_device,
+            image_available,
+        };
 
         let extent = vk::Extent2D {
             width: window.inner_size().width,
             height: window.inner_size().height,
         };
+        swapchain.recreate(device, extent, settings);
+        swapchain
+    }
 
-        let swapchain_fn = ash::khr::swapchain::Device::new(instance, device);
+    /// Replaces the swapchain with one of the given size and settings. The caller must ensure the
+    /// GPU is no longer using any of the current swapchain's images.
+    pub(crate) fn recreate(
+        &mut self,
+        device: &ash::Device,
+        extent: vk::Extent2D,
+        settings: &DisplaySettings,
+    ) {
+        let capabilities = unsafe {
+            self.surface_fn
+                .get_physical_device_surface_capabilities(self.physical_device, self.surface_handle)
+        }
+        .unwrap();
+
+        let present_modes = unsafe {
+            self.surface_fn.get_physical_device_surface_present_modes(
+                self.physical_device,
+                self.surface_handle,
+            )
+        }
+        .unwrap();
+
+        self.present_mode = choose_present_mode(settings, &present_modes);
+
+        // A max_image_count of zero means there's no limit
+        let mut image_count = settings
+            .image_count
+            .unwrap_or(capabilities.min_image_count + 1)
+            .max(capabilities.min_image_count);
+        if capabilities.max_image_count != 0 {
+            image_count = image_count.min(capabilities.max_image_count);
+        }
+
+        let old_swapchain = self.swapchain_handle;
         let swapchain_handle = unsafe {
-            swapchain_fn.create_swapchain(
+            self.swapchain_fn.create_swapchain(
                 &vk::SwapchainCreateInfoKHR::default()
-                    .surface(surface_handle)
-                    .min_image_count(capabilities.min_image_count + 1)
-                    .image_format(format)
+                    .surface(self.surface_handle)
+                    .min_image_count(image_count)
+                    .image_format(self.format)
                     .image_extent(extent)
                     .image_color_space(vk::ColorSpaceKHR::SRGB_NONLINEAR)
                     .image_array_layers(1)
@@ -70,7 +152,7 @@ impl Swapchain {
                     .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
                     .queue_family_indices(&[0])
                     .clipped(true)
-                    .present_mode(vk::PresentModeKHR::FIFO)
+                    .present_mode(self.present_mode)
                     .pre_transform(capabilities.current_transform)
                     .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
                     .old_swapchain(old_swapchain),
@@ -79,94 +161,214 @@ impl Swapchain {
         }
         .unwrap();
 
-        let (images, image_views) = unsafe { swapchain_fn.get_swapchain_images(swapchain_handle) }
-            .unwrap()
-            .into_iter()
-            .map(|image| {
-                let view = unsafe {
-                    device.create_image_view(
-                        &vk::ImageViewCreateInfo::default()
-                            .view_type(vk::ImageViewType::TYPE_2D)
-                            .image(image)
-                            .format(format)
-                            .subresource_range(
-                                vk::ImageSubresourceRange::default()
-                                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                                    .base_mip_level(0)
-                                    .level_count(1)
-                                    .base_array_layer(0)
-                                    .layer_count(1),
-                            ),
-                        None,
-                    )
-                }
-                .unwrap();
-
-                (image, view)
-            })
-            .unzip();
+        unsafe {
+            for view in self.image_views.drain(..) {
+                device.destroy_image_view(view, None);
+            }
+            self.swapchain_fn.destroy_swapchain(old_swapchain, None);
+        }
 
-        let image_available =
-            unsafe { device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) }.unwrap();
+        let format = self.format;
+        let (images, image_views) =
+            unsafe { self.swapchain_fn.get_swapchain_images(swapchain_handle) }
+                .unwrap()
+                .into_iter()
+                .map(|image| {
+                    let view = unsafe {
+                        device.create_image_view(
+                            &vk::ImageViewCreateInfo::default()
+                                .view_type(vk::ImageViewType::TYPE_2D)
+                                .image(image)
+                                .format(format)
+                                .subresource_range(
+                                    vk::ImageSubresourceRange::default()
+                                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                                        .base_mip_level(0)
+                                        .level_count(1)
+                                        .base_array_layer(0)
+                                        .layer_count(1),
+                                ),
+                            None,
+                        )
+                    }
+                    .unwrap();
 
-        Self {
-            surface_handle,
-            surface_fn,
-            swapchain_handle,
-            swapchain_fn,
-            images,
-            image_views,
-            extent,
-            format,
-            image_available,
-        }
+                    (image, view)
+                })
+                .unzip();
+
+        info!(
+            width = extent.width,
+            height = extent.height,
+            present_mode = ?self.present_mode,
+            image_count,
+            "Created swapchain"
+        );
+
+        self.swapchain_handle = swapchain_handle;
+        self.images = images;
+        self.image_views = image_views;
+        self.extent = extent;
     }
 
-    pub fn get_drawable(&self) -> Drawable {
-        let (index, optimal) = unsafe {
-            self.swapchain_fn
-                .acquire_next_image(
-                    self.swapchain_handle,
-                    u64::MAX,
-                    self.image_available,
-                    vk::Fence::null(),
-                )
-                .unwrap()
+    /// Returns the next image to render into, or `None` if the swapchain is out of date and must be
+    /// recreated.
+    pub fn get_drawable(&self) -> Option<Drawable> {
+        let (index, suboptimal) = match unsafe {
+            self.swapchain_fn.acquire_next_image(
+                self.swapchain_handle,
+                u64::MAX,
+                self.image_available,
+                vk::Fence::null(),
+            )
+        } {
+            Ok(result) => result,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                debug!("Swapchain out of date when acquiring");
+                return None;
+            }
+            Err(e) => panic!("Failed to acquire swapchain image: {e}"),
         };
 
-        if !optimal {
-            // eprintln!("Swapchain is not optimal! This isn't handled yet");
+        if suboptimal {
+            debug!(index, "Acquired a suboptimal swapchain image");
         }
 
-        Drawable {
+        Some(Drawable {
             image: self.images[index as usize],
             view: self.image_views[index as usize],
-            ready: self.image_available,
+            ready: Some(self.image_available),
             index,
             extent: self.extent,
-        }
+        })
     }
 
-    pub fn present(&self, drawable: Drawable, queue: vk::Queue, rendering_complete: vk::Semaphore) {
-        unsafe {
-            self.swapchain_fn
-                .queue_present(
-                    queue,
-                    &vk::PresentInfoKHR::default()
-                        .wait_semaphores(&[rendering_complete])
-                        .image_indices(&[drawable.index])
-                        .swapchains(&[self.swapchain_handle]),
-                )
-                .unwrap();
+    /// Presents the drawable, returning `false` if the swapchain should be recreated.
+    pub fn present(
+        &self,
+        drawable: Drawable,
+        queue: vk::Queue,
+        rendering_complete: vk::Semaphore,
+    ) -> bool {
+        let result = unsafe {
+            self.swapchain_fn.queue_present(
+                queue,
+                &vk::PresentInfoKHR::default()
+                    .wait_semaphores(&[rendering_complete])
+                    .image_indices(&[drawable.index])
+                    .swapchains(&[self.swapchain_handle]),
+            )
+        };
+
+        match result {
+            Ok(false) => true,
+            Ok(true) => {
+                debug!("Swapchain suboptimal when presenting");
+                false
+            }
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                debug!("Swapchain out of date when presenting");
+                false
+            }
+            Err(e) => panic!("Failed to present: {e}"),
         }
     }
 }
 
+/// Picks the best supported present mode for `settings`. FIFO is always supported, so it's the
+/// fallback.
+fn choose_present_mode(
+    settings: &DisplaySettings,
+    available: &[vk::PresentModeKHR],
+) -> vk::PresentModeKHR {
+    let mut preferences = Vec::new();
+    if !settings.vsync {
+        preferences.push(vk::PresentModeKHR::IMMEDIATE);
+    }
+    if settings.prefer_mailbox {
+        preferences.push(vk::PresentModeKHR::MAILBOX);
+    }
+    if !settings.vsync {
+        preferences.push(vk::PresentModeKHR::FIFO_RELAXED);
+    }
+
+    preferences
+        .into_iter()
+        .find(|mode| available.contains(mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Drawable {
     pub image: vk::Image,
     pub view: vk::ImageView,
-    pub ready: vk::Semaphore,
+    /// Signalled once the image can be rendered into. `None` for offscreen images, which are
+    /// always ready and are never presented.
+    pub ready: Option<vk::Semaphore>,
     pub index: u32,
     pub extent: vk::Extent2D,
 }
+
+#[cfg(test)]
+mod tests {
+    use vk::PresentModeKHR as Mode;
+
+    use super::*;
+
+    fn settings(vsync: bool, prefer_mailbox: bool) -> DisplaySettings {
+        DisplaySettings {
+            vsync,
+            prefer_mailbox,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn picks_the_most_preferred_available_mode() {
+        let all = [
+            Mode::FIFO,
+            Mode::FIFO_RELAXED,
+            Mode::MAILBOX,
+            Mode::IMMEDIATE,
+        ];
+        let only_fifo = [Mode::FIFO];
+        let no_immediate = [Mode::FIFO, Mode::FIFO_RELAXED, Mode::MAILBOX];
+        let no_mailbox = [Mode::FIFO, Mode::IMMEDIATE];
+        let relaxed = [Mode::FIFO, Mode::FIFO_RELAXED];
+
+        // (vsync, prefer mailbox, available modes, expected)
+        let cases: [(bool, bool, &[Mode], Mode); 14] = [
+            (true, false, &all, Mode::FIFO),
+            (true, false, &only_fifo, Mode::FIFO),
+            (true, true, &all, Mode::MAILBOX),
+            (true, true, &no_mailbox, Mode::FIFO),
+            (true, true, &relaxed, Mode::FIFO),
+            (true, true, &only_fifo, Mode::FIFO),
+            (false, false, &all, Mode::IMMEDIATE),
+            (false, false, &no_immediate, Mode::FIFO_RELAXED),
+            (false, false, &relaxed, Mode::FIFO_RELAXED),
+            (false, false, &only_fifo, Mode::FIFO),
+            (false, true, &all, Mode::IMMEDIATE),
+            (false, true, &no_immediate, Mode::MAILBOX),
+            (false, true, &relaxed, Mode::FIFO_RELAXED),
+            (false, true, &only_fifo, Mode::FIFO),
+        ];
+
+        for (vsync, prefer_mailbox, available, expected) in cases {
+            assert_eq!(
+                choose_present_mode(&settings(vsync, prefer_mailbox), available),
+                expected,
+                "vsync {vsync}, prefer mailbox {prefer_mailbox}, {available:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn falls_back_to_fifo_when_nothing_is_reported() {
+        assert_eq!(
+            choose_present_mode(&DisplaySettings::default(), &[]),
+            Mode::FIFO
+        );
+        assert_eq!(choose_present_mode(&settings(false, true), &[]), Mode::FIFO);
+    }
+}
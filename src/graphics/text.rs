@@ -0,0 +1,568 @@
+use std::sync::Arc;
+
+use ash::vk;
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use super::{
+    buffer::ArrayBuffer,
+    camera::Camera,
+    context::Context,
+    font::{FontAtlas, GlyphQuad},
+    pipeline::{load_module, ShaderError},
+    render_target::{RenderTarget, DEPTH_FORMAT, HDR_FORMAT},
+    uploader::Uploader,
+};
+
+use crate::assets::{AssetServer, Handle};
+
+/// Relative to the asset root.
+const FONT_PATH: &str = "fonts/DejaVuSans.ttf";
+
+/// Where a piece of text is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// On top of the finished frame, with the top left corner of the text at this pixel.
+    Screen(Vec2),
+    /// In the world, centred on this point and turned to face the camera. Anything in front of it
+    /// hides it.
+    World(Vec3),
+}
+
+/// Text to be drawn this frame.
+#[derive(Debug, Clone)]
+pub struct Text {
+    pub text: String,
+    pub placement: Placement,
+    /// The height of a line of text: in pixels on screen, or in metres in the world.
+    pub size: f32,
+    /// Linear, like the colours of cubes.
+    pub colour: Vec4,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct TextVertex {
+    position: Vec3,
+    uv: Vec2,
+    colour: Vec4,
+}
+
+/// Draws text from a signed distance field font atlas, both as world-space labels in the scene
+/// and over the finished frame.
+pub struct TextPass {
+    /// Depth tested against the scene, and multisampled like it.
+    world_pipeline: vk::Pipeline,
+    screen_pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    samples: vk::SampleCountFlags,
+    format: vk::Format,
+    descriptor_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    sampler: vk::Sampler,
+    font_handle: Handle<FontAtlas>,
+    /// The version of the font that `font` and `atlas` were made from. Nothing's drawn until the
+    /// first version has loaded.
+    font_version: u32,
+    font: Option<Arc<FontAtlas>>,
+    atlas: Option<RenderTarget>,
+    /// World vertices come first, followed by screen vertices.
+    vertices: ArrayBuffer<TextVertex>,
+    world_vertex_count: u32,
+    screen_vertex_count: u32,
+    context: Arc<Context>,
+}
+
+impl TextPass {
+    /// Starts the font loading in the background, so text only appears once it's ready.
+    pub fn new(
+        context: Arc<Context>,
+        samples: vk::SampleCountFlags,
+        format: vk::Format,
+        assets: &mut AssetServer,
+    ) -> Self {
+        let device = &context.device;
+        let font_handle = assets.load(FONT_PATH);
+
+        let descriptor_layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&[
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .stage_flags(vk::ShaderStageFlags::FRAGMENT),
+                ]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .max_sets(1)
+                    .pool_sizes(&[vk::DescriptorPoolSize::default()
+                        .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let descriptor_set = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(descriptor_pool)
+                    .set_layouts(&[descriptor_layout]),
+            )
+        }
+        .unwrap()[0];
+
+        // Distances interpolate well, which is the point of storing them
+        let sampler = unsafe {
+            device.create_sampler(
+                &vk::SamplerCreateInfo::default()
+                    .mag_filter(vk::Filter::LINEAR)
+                    .min_filter(vk::Filter::LINEAR)
+                    .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                    .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE),
+                None,
+            )
+        }
+        .unwrap();
+
+        let layout = unsafe {
+            device.create_pipeline_layout(
+                &vk::PipelineLayoutCreateInfo::default()
+                    .set_layouts(&[descriptor_layout])
+                    .push_constant_ranges(&[vk::PushConstantRange::default()
+                        .size(std::mem::size_of::<TextRegisters>() as u32)
+                        .stage_flags(vk::ShaderStageFlags::VERTEX)]),
+                None,
+            )
+        }
+        .unwrap();
+
+        let world_pipeline = create_pipeline(&context, layout, HDR_FORMAT, samples, true, assets)
+            .unwrap_or_else(|e| panic!("Couldn't create the world text pipeline: {e}"));
+        let screen_pipeline = create_pipeline(
+            &context,
+            layout,
+            format,
+            vk::SampleCountFlags::TYPE_1,
+            false,
+            assets,
+        )
+        .unwrap_or_else(|e| panic!("Couldn't create the screen text pipeline: {e}"));
+        let vertices = ArrayBuffer::new(context.clone(), vk::BufferUsageFlags::VERTEX_BUFFER, 0);
+
+        Self {
+            world_pipeline,
+            screen_pipeline,
+            layout,
+            samples,
+            format,
+            descriptor_layout,
+            descriptor_pool,
+            descriptor_set,
+            sampler,
+            font_handle,
+            font_version: 0,
+            font: None,
+            atlas: None,
+            vertices,
+            world_vertex_count: 0,
+            screen_vertex_count: 0,
+            context,
+        }
+    }
+
+    /// Recreates the world pipeline to match the scene's new MSAA sample count. The GPU must not
+    /// be using the pipeline.
+    pub fn set_samples(&mut self, samples: vk::SampleCountFlags, assets: &mut AssetServer) {
+        let world_pipeline = create_pipeline(
+            &self.context,
+            self.layout,
+            HDR_FORMAT,
+            samples,
+            true,
+            assets,
+        )
+        .unwrap_or_else(|e| panic!("Couldn't create the world text pipeline: {e}"));
+        unsafe {
+            self.context
+                .device
+                .destroy_pipeline(self.world_pipeline, None)
+        };
+        self.world_pipeline = world_pipeline;
+        self.samples = samples;
+    }
+
+    /// Recreates both pipelines with the latest shaders, keeping the old ones if they're unusable.
+    /// The GPU must not be using the pipelines.
+    pub fn reload_shaders(&mut self, assets: &mut AssetServer) -> Result<(), ShaderError> {
+        let device = &self.context.device;
+        let world_pipeline = create_pipeline(
+            &self.context,
+            self.layout,
+            HDR_FORMAT,
+            self.samples,
+            true,
+            assets,
+        )?;
+        let screen_pipeline = create_pipeline(
+            &self.context,
+            self.layout,
+            self.format,
+            vk::SampleCountFlags::TYPE_1,
+            false,
+            assets,
+        )
+        .inspect_err(|_| unsafe { device.destroy_pipeline(world_pipeline, None) })?;
+
+        unsafe {
+            device.destroy_pipeline(self.world_pipeline, None);
+            device.destroy_pipeline(self.screen_pipeline, None);
+        }
+        self.world_pipeline = world_pipeline;
+        self.screen_pipeline = screen_pipeline;
+        Ok(())
+    }
+
+    /// Lays out `texts` and writes their vertices, queueing a new atlas to upload first if the
+    /// font has loaded or changed since the last one. This must be called once the previous frame
+    /// has finished.
+    pub(crate) fn prepare(
+        &mut self,
+        texts: &[Text],
+        camera: &Camera,
+        assets: &AssetServer,
+        uploader: &mut Uploader,
+    ) {
+        let font_version = assets.version(self.font_handle);
+        if font_version != self.font_version {
+            self.font_version = font_version;
+            if let Some(font) = assets.get(self.font_handle) {
+                self.set_font(font, uploader);
+            }
+        }
+
+        let Some(font) = &self.font else {
+            self.world_vertex_count = 0;
+            self.screen_vertex_count = 0;
+            return;
+        };
+
+        let right = camera.rotation() * Vec3::X;
+        let up = camera.rotation() * Vec3::Y;
+
+        let mut world = Vec::new();
+        let mut screen = Vec::new();
+        for text in texts {
+            let (quads, size) = font.layout(&text.text, text.size);
+            let colour = text.colour;
+
+            match text.placement {
+                Placement::Screen(pixel) => {
+                    screen.extend(
+                        quads.iter().flat_map(|quad| {
+                            quad_vertices(quad, colour, |p| (pixel + p).extend(0.))
+                        }),
+                    );
+                }
+                Placement::World(centre) => {
+                    // Layout has Y down from the top left, but labels are centred with Y up
+                    world.extend(quads.iter().flat_map(|quad| {
+                        quad_vertices(quad, colour, |p| {
+                            let p = p - size / 2.;
+                            centre + right * p.x - up * p.y
+                        })
+                    }));
+                }
+            }
+        }
+
+        let vertex_count = world.len() + screen.len();
+        if vertex_count > self.vertices.capacity() {
+            self.vertices = ArrayBuffer::new(
+                self.context.clone(),
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                vertex_count.next_power_of_two(),
+            );
+        }
+        self.vertices.write(0, &world);
+        self.vertices.write(world.len(), &screen);
+        self.world_vertex_count = world.len() as u32;
+        self.screen_vertex_count = screen.len() as u32;
+    }
+
+    /// Makes a new atlas for `font`, queues its pixels to be uploaded, and points the descriptor
+    /// set at it. The previous frame must have finished, as it may have been using the old atlas.
+    fn set_font(&mut self, font: Arc<FontAtlas>, uploader: &mut Uploader) {
+        let extent = vk::Extent2D {
+            width: font.width,
+            height: font.height,
+        };
+        let atlas = RenderTarget::new(
+            self.context.clone(),
+            extent,
+            vk::Format::R8_UNORM,
+            vk::SampleCountFlags::TYPE_1,
+            vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+        );
+        let staging = ArrayBuffer::new(
+            self.context.clone(),
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            font.pixels.len(),
+        );
+        staging.write(0, &font.pixels);
+        uploader.upload_image(staging, &atlas, extent);
+
+        unsafe {
+            self.context.device.update_descriptor_sets(
+                &[vk::WriteDescriptorSet::default()
+                    .dst_set(self.descriptor_set)
+                    .dst_binding(0)
+                    .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                    .image_info(&[vk::DescriptorImageInfo::default()
+                        .image_view(atlas.view)
+                        .sampler(self.sampler)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)])],
+                &[],
+            )
+        };
+
+        self.font = Some(font);
+        self.atlas = Some(atlas);
+    }
+
+    /// Records the world-space labels into the scene's rendering pass, returning the number of
+    /// draw calls.
+    pub(crate) fn draw_world(&self, camera: &Camera) -> u32 {
+        self.draw(
+            self.world_pipeline,
+            camera.ndc_from_world(),
+            0,
+            self.world_vertex_count,
+        )
+    }
+
+    /// Records the screen-space text into the current rendering pass, which covers `extent`,
+    /// returning the number of draw calls.
+    pub(crate) fn draw_screen(&self, extent: vk::Extent2D) -> u32 {
+        // Pixels run from the top left, as does Vulkan's NDC
+        let size = Vec2::new(extent.width as f32, extent.height as f32);
+        let ndc_from_pixel = Mat4::from_translation(Vec3::new(-1., -1., 0.))
+            * Mat4::from_scale((2. / size).extend(1.));
+
+        self.draw(
+            self.screen_pipeline,
+            ndc_from_pixel,
+            self.world_vertex_count,
+            self.screen_vertex_count,
+        )
+    }
+
+    fn draw(
+        &self,
+        pipeline: vk::Pipeline,
+        ndc_from_position: Mat4,
+        first_vertex: u32,
+        vertex_count: u32,
+    ) -> u32 {
+        if vertex_count == 0 {
+            return 0;
+        }
+
+        let device = &self.context.device;
+        let command_buffer = self.context.draw_command_buffer;
+        let registers = TextRegisters { ndc_from_position };
+
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertices.handle], &[0]);
+            device.cmd_push_constants(
+                command_buffer,
+                self.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                std::slice::from_raw_parts(
+                    &registers as *const _ as *const u8,
+                    std::mem::size_of::<TextRegisters>(),
+                ),
+            );
+            device.cmd_draw(command_buffer, vertex_count, 1, first_vertex, 0);
+        }
+
+        1
+    }
+}
+
+/// Returns the two triangles that make up `quad`, placed with `to_position`.
+fn quad_vertices(
+    quad: &GlyphQuad,
+    colour: Vec4,
+    to_position: impl Fn(Vec2) -> Vec3,
+) -> [TextVertex; 6] {
+    let corner = |x: bool, y: bool| {
+        let select = |min: Vec2, max: Vec2| {
+            Vec2::new(if x { max.x } else { min.x }, if y { max.y } else { min.y })
+        };
+        TextVertex {
+            position: to_position(select(quad.min, quad.max)),
+            uv: select(quad.uv_min, quad.uv_max),
+            colour,
+        }
+    };
+
+    [
+        corner(false, false),
+        corner(false, true),
+        corner(true, true),
+        corner(false, false),
+        corner(true, true),
+        corner(true, false),
+    ]
+}
+
+impl Drop for TextPass {
+    fn drop(&mut self) {
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_pipeline(self.world_pipeline, None);
+            device.destroy_pipeline(self.screen_pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            device.destroy_sampler(self.sampler, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            device.destroy_descriptor_set_layout(self.descriptor_layout, None);
+        }
+    }
+}
+
+/// Creates a pipeline that draws text into a `format` attachment. With `depth_test`, the text is
+/// hidden behind anything nearer in the reverse-Z depth buffer, but doesn't write to it.
+fn create_pipeline(
+    context: &Context,
+    layout: vk::PipelineLayout,
+    format: vk::Format,
+    samples: vk::SampleCountFlags,
+    depth_test: bool,
+    assets: &mut AssetServer,
+) -> Result<vk::Pipeline, ShaderError> {
+    let vertex = load_module(assets, "text.vertex.spv", context)?;
+    let fragment = load_module(assets, "text.fragment.spv", context)?;
+
+    let mut rendering = vk::PipelineRenderingCreateInfo::default()
+        .color_attachment_formats(std::slice::from_ref(&format));
+    if depth_test {
+        rendering = rendering.depth_attachment_format(DEPTH_FORMAT);
+    }
+
+    let pipelines = unsafe {
+        context.device.create_graphics_pipelines(
+            vk::PipelineCache::null(),
+            &[vk::GraphicsPipelineCreateInfo::default()
+                .stages(&[
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(vertex.handle)
+                        .stage(vk::ShaderStageFlags::VERTEX),
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .name(c"main")
+                        .module(fragment.handle)
+                        .stage(vk::ShaderStageFlags::FRAGMENT),
+                ])
+                .vertex_input_state(
+                    &vk::PipelineVertexInputStateCreateInfo::default()
+                        .vertex_binding_descriptions(&[vk::VertexInputBindingDescription::default(
+                        )
+                        .binding(0)
+                        .stride(std::mem::size_of::<TextVertex>() as u32)
+                        .input_rate(vk::VertexInputRate::VERTEX)])
+                        .vertex_attribute_descriptions(&[
+                            // Position
+                            vk::VertexInputAttributeDescription::default()
+                                .location(0)
+                                .format(vk::Format::R32G32B32_SFLOAT)
+                                .offset(0),
+                            // UV
+                            vk::VertexInputAttributeDescription::default()
+                                .location(1)
+                                .format(vk::Format::R32G32_SFLOAT)
+                                .offset(12),
+                            // Colour
+                            vk::VertexInputAttributeDescription::default()
+                                .location(2)
+                                .format(vk::Format::R32G32B32A32_SFLOAT)
+                                .offset(20),
+                        ]),
+                )
+                .input_assembly_state(
+                    &vk::PipelineInputAssemblyStateCreateInfo::default()
+                        .topology(vk::PrimitiveTopology::TRIANGLE_LIST),
+                )
+                .viewport_state(
+                    &vk::PipelineViewportStateCreateInfo::default()
+                        .scissor_count(1)
+                        .viewport_count(1),
+                )
+                .dynamic_state(
+                    &vk::PipelineDynamicStateCreateInfo::default()
+                        .dynamic_states(&[vk::DynamicState::SCISSOR, vk::DynamicState::VIEWPORT]),
+                )
+                .rasterization_state(
+                    &vk::PipelineRasterizationStateCreateInfo::default()
+                        .cull_mode(vk::CullModeFlags::NONE)
+                        .polygon_mode(vk::PolygonMode::FILL)
+                        .line_width(1.0),
+                )
+                .depth_stencil_state(
+                    &vk::PipelineDepthStencilStateCreateInfo::default()
+                        .depth_test_enable(depth_test)
+                        .depth_write_enable(false)
+                        .depth_compare_op(vk::CompareOp::GREATER_OR_EQUAL)
+                        .max_depth_bounds(1.),
+                )
+                .color_blend_state(
+                    &vk::PipelineColorBlendStateCreateInfo::default().attachments(&[
+                        // The fragment shader premultiplies by alpha
+                        vk::PipelineColorBlendAttachmentState::default()
+                            .blend_enable(true)
+                            .src_color_blend_factor(vk::BlendFactor::ONE)
+                            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                            .color_blend_op(vk::BlendOp::ADD)
+                            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                            .alpha_blend_op(vk::BlendOp::ADD)
+                            .color_write_mask(vk::ColorComponentFlags::RGBA),
+                    ]),
+                )
+                .multisample_state(
+                    &vk::PipelineMultisampleStateCreateInfo::default()
+                        .rasterization_samples(samples),
+                )
+                .layout(layout)
+                .push_next(&mut rendering)],
+            None,
+        )
+    }
+    .map_err(|(_, e)| ShaderError::Vulkan(e))?;
+
+    Ok(pipelines[0])
+}
+
+#[repr(C)]
+#[derive(Debug, Clone)]
+struct TextRegisters {
+    ndc_from_position: Mat4,
+}
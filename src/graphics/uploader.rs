@@ -0,0 +1,281 @@
+use std::{collections::VecDeque, sync::Arc};
+
+use ash::vk;
+
+use super::{buffer::ArrayBuffer, context::Context, render_target::RenderTarget};
+
+/// Uploaded images are only sampled, so rendering only waits for uploads before fragment shaders.
+const UPLOAD_WAIT_STAGE: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::FRAGMENT_SHADER;
+
+/// Copies data into images on the context's transfer queue, so uploads don't hold up rendering.
+/// Each batch of copies signals a timeline semaphore when it's done, which the frame that first
+/// uses the images waits on. Where the transfer queue has a family of its own, the images are
+/// handed over to the graphics queue with queue family ownership transfers.
+pub struct Uploader {
+    command_pool: vk::CommandPool,
+    timeline: vk::Semaphore,
+    /// The value the latest batch signals the timeline with once it's finished.
+    last_value: u64,
+    /// Copies waiting for the next batch.
+    queued: Vec<ImageUpload>,
+    /// Batches the GPU may still be working on.
+    in_flight: VecDeque<Batch>,
+    context: Arc<Context>,
+}
+
+struct ImageUpload {
+    staging: ArrayBuffer<u8>,
+    image: vk::Image,
+    subresource_range: vk::ImageSubresourceRange,
+    extent: vk::Extent2D,
+}
+
+struct Batch {
+    /// The timeline value that says the batch has finished.
+    value: u64,
+    command_buffer: vk::CommandBuffer,
+    /// Kept until the copies out of them have finished.
+    #[allow(unused)]
+    staging: Vec<ArrayBuffer<u8>>,
+}
+
+impl Uploader {
+    pub fn new(context: Arc<Context>) -> Self {
+        let device = &context.device;
+
+        let command_pool = unsafe {
+            device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .queue_family_index(context.transfer_queue_family)
+                    .flags(vk::CommandPoolCreateFlags::TRANSIENT),
+                None,
+            )
+        }
+        .unwrap();
+
+        let timeline = unsafe {
+            device.create_semaphore(
+                &vk::SemaphoreCreateInfo::default().push_next(
+                    &mut vk::SemaphoreTypeCreateInfo::default()
+                        .semaphore_type(vk::SemaphoreType::TIMELINE)
+                        .initial_value(0),
+                ),
+                None,
+            )
+        }
+        .unwrap();
+
+        Self {
+            command_pool,
+            timeline,
+            last_value: 0,
+            queued: Vec::new(),
+            in_flight: VecDeque::new(),
+            context,
+        }
+    }
+
+    /// Queues `staging` to be copied into the whole of `image`, whose previous contents are
+    /// discarded. The image is ready to be sampled in fragment shaders once the frame that submits
+    /// the copy starts using it.
+    pub fn upload_image(
+        &mut self,
+        staging: ArrayBuffer<u8>,
+        image: &RenderTarget,
+        extent: vk::Extent2D,
+    ) {
+        self.queued.push(ImageUpload {
+            staging,
+            image: image.image,
+            subresource_range: image.subresource_range(),
+            extent,
+        });
+    }
+
+    /// Submits the queued copies to the transfer queue, then records anything `command_buffer`
+    /// needs to do before it can use their images. Returns what the submission of
+    /// `command_buffer` must wait on, if anything was queued. Batches that have finished since the
+    /// last call are cleaned up.
+    pub fn submit(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+    ) -> Option<vk::SemaphoreSubmitInfo<'static>> {
+        self.free_finished();
+        if self.queued.is_empty() {
+            return None;
+        }
+
+        let device = &self.context.device;
+        let (src_family, dst_family) = if self.context.has_transfer_queue() {
+            (
+                self.context.transfer_queue_family,
+                self.context.graphics_queue_family,
+            )
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        };
+
+        let transfer_command_buffer = unsafe {
+            device.allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(self.command_pool)
+                    .command_buffer_count(1),
+            )
+        }
+        .unwrap()[0];
+
+        let uploads = std::mem::take(&mut self.queued);
+        let to_transfer_dst: Vec<_> = uploads
+            .iter()
+            .map(|upload| {
+                vk::ImageMemoryBarrier2::default()
+                    .subresource_range(upload.subresource_range)
+                    .image(upload.image)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            })
+            .collect();
+        // With a queue of its own, this releases each image to the graphics queue. Otherwise it's
+        // a plain transition, made visible to the graphics queue by the semaphore.
+        let release: Vec<_> = uploads
+            .iter()
+            .map(|upload| {
+                vk::ImageMemoryBarrier2::default()
+                    .subresource_range(upload.subresource_range)
+                    .image(upload.image)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .src_stage_mask(vk::PipelineStageFlags2::COPY)
+                    .src_queue_family_index(src_family)
+                    .dst_queue_family_index(dst_family)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            })
+            .collect();
+
+        unsafe {
+            device
+                .begin_command_buffer(
+                    transfer_command_buffer,
+                    &vk::CommandBufferBeginInfo::default()
+                        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .unwrap();
+
+            device.cmd_pipeline_barrier2(
+                transfer_command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&to_transfer_dst),
+            );
+
+            for upload in &uploads {
+                device.cmd_copy_buffer_to_image(
+                    transfer_command_buffer,
+                    upload.staging.handle,
+                    upload.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[vk::BufferImageCopy::default()
+                        .image_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: upload.subresource_range.aspect_mask,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .image_extent(upload.extent.into())],
+                );
+            }
+
+            device.cmd_pipeline_barrier2(
+                transfer_command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&release),
+            );
+
+            device.end_command_buffer(transfer_command_buffer).unwrap();
+        }
+
+        self.last_value += 1;
+        unsafe {
+            device
+                .queue_submit2(
+                    self.context.transfer_queue,
+                    &[vk::SubmitInfo2::default()
+                        .command_buffer_infos(&[vk::CommandBufferSubmitInfo::default()
+                            .command_buffer(transfer_command_buffer)])
+                        .signal_semaphore_infos(&[vk::SemaphoreSubmitInfo::default()
+                            .semaphore(self.timeline)
+                            .value(self.last_value)
+                            .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)])],
+                    vk::Fence::null(),
+                )
+                .unwrap();
+        }
+
+        // The graphics queue acquires each image with a barrier matching the release. It has
+        // nothing to wait for on this queue: the semaphore wait, at the same stage the barrier
+        // blocks, is what orders it after the copy
+        if self.context.has_transfer_queue() {
+            let acquire: Vec<_> = uploads
+                .iter()
+                .map(|upload| {
+                    vk::ImageMemoryBarrier2::default()
+                        .subresource_range(upload.subresource_range)
+                        .image(upload.image)
+                        .src_stage_mask(vk::PipelineStageFlags2::NONE)
+                        .src_access_mask(vk::AccessFlags2::NONE)
+                        .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+                        .dst_stage_mask(UPLOAD_WAIT_STAGE)
+                        .src_queue_family_index(src_family)
+                        .dst_queue_family_index(dst_family)
+                        .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                })
+                .collect();
+
+            unsafe {
+                device.cmd_pipeline_barrier2(
+                    command_buffer,
+                    &vk::DependencyInfo::default().image_memory_barriers(&acquire),
+                );
+            }
+        }
+
+        self.in_flight.push_back(Batch {
+            value: self.last_value,
+            command_buffer: transfer_command_buffer,
+            staging: uploads.into_iter().map(|upload| upload.staging).collect(),
+        });
+
+        Some(
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(self.timeline)
+                .value(self.last_value)
+                .stage_mask(UPLOAD_WAIT_STAGE),
+        )
+    }
+
+    /// Frees the command buffers and staging buffers of batches the GPU has finished.
+    fn free_finished(&mut self) {
+        let device = &self.context.device;
+        let finished = unsafe { device.get_semaphore_counter_value(self.timeline) }.unwrap();
+
+        while let Some(batch) = self.in_flight.front() {
+            if batch.value > finished {
+                break;
+            }
+            unsafe { device.free_command_buffers(self.command_pool, &[batch.command_buffer]) };
+            self.in_flight.pop_front();
+        }
+    }
+}
+
+impl Drop for Uploader {
+    fn drop(&mut self) {
+        // Destroying the pool frees the batches' command buffers. The owner makes sure the GPU has
+        // finished with them first.
+        let device = &self.context.device;
+        unsafe {
+            device.destroy_command_pool(self.command_pool, None);
+            device.destroy_semaphore(self.timeline, None);
+        }
+    }
+}
@@ -1,46 +1,162 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
 use winit::{
-    event::{ElementState, KeyEvent},
+    event::{ElementState, KeyEvent, MouseButton},
     keyboard::{KeyCode, PhysicalKey},
 };
 
+use crate::actions::{Action, Bindings};
+
+/// A physical key or mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Button {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+/// Everything that can change the state of [`Input`]. Window and device events are converted into
+/// these so they can be recorded and replayed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Button {
+        button: Button,
+        state: ElementState,
+    },
+    /// Raw mouse movement, which is only recorded while the cursor is grabbed.
+    MouseMotion {
+        x: f64,
+        y: f64,
+    },
+    /// The cursor moved to `x`, `y` in physical pixels from the window's top left corner.
+    CursorMoved {
+        x: f64,
+        y: f64,
+    },
+    CursorLeft,
+    /// The wheel scrolled by this many lines; positive is away from the user.
+    Scroll {
+        lines: f32,
+    },
+    /// Everything was released, eg. because the window lost focus.
+    ReleaseAll,
+}
+
+impl InputEvent {
+    /// Returns `None` for keys winit can't identify and for key repeats, which don't change
+    /// anything.
+    pub fn from_keyboard_event(event: &KeyEvent) -> Option<Self> {
+        let PhysicalKey::Code(key_code) = event.physical_key else {
+            return None;
+        };
+        if event.repeat {
+            return None;
+        }
+
+        Some(InputEvent::Button {
+            button: Button::Key(key_code),
+            state: event.state,
+        })
+    }
+}
+
+/// Buttons that went down or up during some period.
+#[derive(Debug, Default, Clone)]
+struct Edges {
+    pressed: HashSet<Button>,
+    released: HashSet<Button>,
+}
+
+impl Edges {
+    fn clear(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+}
+
+/// Tracks which keys and mouse buttons are held, which changed recently, and how far the mouse has
+/// moved, and maps them to [`Action`]s.
+///
+/// Edges are tracked twice: once per frame, for things that happen as soon as a button is
+/// pressed (like toggling vsync), and once per simulation tick, so gameplay sees every press even
+/// when several frames pass between ticks.
 #[derive(Debug, Default, Clone)]
 pub struct Input {
-    left: f32,
-    right: f32,
-    up: f32,
-    down: f32,
-    forward: f32,
-    back: f32,
+    held: HashSet<Button>,
+    frame: Edges,
+    tick: Edges,
+    pub bindings: Bindings,
     pub pitch_degrees: f32,
     pub yaw_degrees: f32,
+    /// Where the cursor is in the window, in physical pixels, or `None` if it's outside it.
+    pub cursor_position: Option<glam::Vec2>,
+    /// How many lines the wheel has scrolled by this tick.
+    pub scroll_lines: f32,
 }
 
 impl Input {
-    pub fn reset(&mut self) {
-        *self = Input::default();
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
     }
 
-    pub fn handle_keyboard_event(&mut self, event: winit::event::KeyEvent) {
-        let KeyEvent {
-            physical_key: PhysicalKey::Code(key_code),
-            state: ElementState::Pressed,
-            ..
-        } = event
-        else {
-            return;
-        };
+    /// Clears the edges seen by [`Input::was_pressed_this_frame`] and friends.
+    pub fn end_frame(&mut self) {
+        self.frame.clear();
+    }
+
+    /// Clears the per-tick state: the edges seen by gameplay, the mouse deltas and the scroll.
+    /// Held keys and buttons stay held until they're released.
+    pub fn end_tick(&mut self) {
+        self.tick.clear();
+        self.pitch_degrees = 0.;
+        self.yaw_degrees = 0.;
+        self.scroll_lines = 0.;
+    }
+
+    pub fn handle_event(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::Button { button, state } => self.handle_button(button, state),
+            InputEvent::MouseMotion { x, y } => self.handle_mouse_motion(x, y),
+            InputEvent::CursorMoved { x, y } => {
+                self.cursor_position = Some(glam::Vec2::new(x as f32, y as f32))
+            }
+            InputEvent::CursorLeft => self.cursor_position = None,
+            InputEvent::Scroll { lines } => self.scroll_lines += lines,
+            InputEvent::ReleaseAll => self.release_all(),
+        }
+    }
+
+    /// Releases everything that's held, eg. when the window loses focus and we'll never see the
+    /// matching release events.
+    fn release_all(&mut self) {
+        for button in std::mem::take(&mut self.held) {
+            self.frame.released.insert(button);
+            self.tick.released.insert(button);
+        }
+    }
 
-        match key_code {
-            KeyCode::KeyW => self.forward += 1.,
-            KeyCode::KeyS => self.back += 1.,
-            KeyCode::KeyA => self.left += 1.,
-            KeyCode::KeyD => self.right += 1.,
-            KeyCode::Space => self.up += 1.,
-            KeyCode::ControlLeft => self.down += 1.,
-            _ => {}
+    fn handle_button(&mut self, button: Button, state: ElementState) {
+        match state {
+            ElementState::Pressed => {
+                // Key repeats are ignored: the key is already held
+                if self.held.insert(button) {
+                    self.frame.pressed.insert(button);
+                    self.tick.pressed.insert(button);
+                }
+            }
+            ElementState::Released => {
+                if self.held.remove(&button) {
+                    self.frame.released.insert(button);
+                    self.tick.released.insert(button);
+                }
+            }
         }
     }
-    pub fn handle_mouse_motion(&mut self, x: f64, y: f64) {
+
+    fn handle_mouse_motion(&mut self, x: f64, y: f64) {
         if x == 0. && y == 0. {
             return;
         }
@@ -49,13 +165,194 @@ impl Input {
         self.yaw_degrees -= x as f32;
     }
 
+    pub fn is_held(&self, action: Action) -> bool {
+        self.bindings.is_active(action, &self.held, None)
+    }
+
+    /// Was the action triggered since the last tick?
+    pub fn was_pressed(&self, action: Action) -> bool {
+        self.bindings
+            .is_active(action, &self.held, Some(&self.tick.pressed))
+    }
+
+    /// Was the action triggered since the last frame?
+    pub fn was_pressed_this_frame(&self, action: Action) -> bool {
+        self.bindings
+            .is_active(action, &self.held, Some(&self.frame.pressed))
+    }
+
     // Returns a normalised (or zero) vector of the movement for this frame
     pub fn get_movement(&self) -> glam::Vec3 {
+        let axis = |positive, negative| {
+            self.is_held(positive) as i32 as f32 - self.is_held(negative) as i32 as f32
+        };
+
         glam::Vec3::new(
-            self.right - self.left,
-            self.up - self.down,
-            self.back - self.forward,
+            axis(Action::MoveRight, Action::MoveLeft),
+            axis(Action::MoveUp, Action::MoveDown),
+            axis(Action::MoveBack, Action::MoveForward),
         )
         .normalize_or_zero()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key_code: KeyCode, state: ElementState) -> InputEvent {
+        InputEvent::Button {
+            button: Button::Key(key_code),
+            state,
+        }
+    }
+
+    fn press(input: &mut Input, key_code: KeyCode) {
+        input.handle_event(key(key_code, ElementState::Pressed));
+    }
+
+    fn release(input: &mut Input, key_code: KeyCode) {
+        input.handle_event(key(key_code, ElementState::Released));
+    }
+
+    /// Runs a frame with `ticks` simulation ticks, returning whether each tick saw `action`
+    /// pressed.
+    fn run_frame(input: &mut Input, ticks: u32, action: Action) -> Vec<bool> {
+        let pressed = (0..ticks)
+            .map(|_| {
+                let pressed = input.was_pressed(action);
+                input.end_tick();
+                pressed
+            })
+            .collect();
+        input.end_frame();
+        pressed
+    }
+
+    #[test]
+    fn held_until_released() {
+        let mut input = Input::default();
+        assert!(!input.is_held(Action::MoveForward));
+
+        press(&mut input, KeyCode::KeyW);
+        assert!(input.is_held(Action::MoveForward));
+        input.end_tick();
+        input.end_frame();
+        assert!(input.is_held(Action::MoveForward));
+
+        release(&mut input, KeyCode::KeyW);
+        assert!(!input.is_held(Action::MoveForward));
+    }
+
+    #[test]
+    fn pressed_this_frame_lasts_one_frame() {
+        let mut input = Input::default();
+        press(&mut input, KeyCode::KeyV);
+        assert!(input.was_pressed_this_frame(Action::ToggleVsync));
+
+        // Ticks don't clear frame edges
+        input.end_tick();
+        assert!(input.was_pressed_this_frame(Action::ToggleVsync));
+
+        input.end_frame();
+        assert!(!input.was_pressed_this_frame(Action::ToggleVsync));
+        assert!(input.is_held(Action::ToggleVsync));
+    }
+
+    #[test]
+    fn a_press_is_seen_by_the_first_of_several_ticks() {
+        let mut input = Input::default();
+        press(&mut input, KeyCode::KeyP);
+        assert_eq!(
+            run_frame(&mut input, 3, Action::TogglePause),
+            [true, false, false]
+        );
+    }
+
+    #[test]
+    fn a_press_waits_for_a_tick_through_frames_without_one() {
+        let mut input = Input::default();
+        press(&mut input, KeyCode::KeyP);
+        assert!(run_frame(&mut input, 0, Action::TogglePause).is_empty());
+        assert!(run_frame(&mut input, 0, Action::TogglePause).is_empty());
+        assert!(!input.was_pressed_this_frame(Action::TogglePause));
+
+        assert_eq!(run_frame(&mut input, 1, Action::TogglePause), [true]);
+        assert_eq!(run_frame(&mut input, 1, Action::TogglePause), [false]);
+    }
+
+    #[test]
+    fn key_repeats_and_unmatched_releases_are_ignored() {
+        let mut input = Input::default();
+        release(&mut input, KeyCode::KeyP);
+        assert!(!input.is_held(Action::TogglePause));
+
+        press(&mut input, KeyCode::KeyP);
+        run_frame(&mut input, 1, Action::TogglePause);
+        press(&mut input, KeyCode::KeyP);
+        assert!(!input.was_pressed(Action::TogglePause));
+        assert!(!input.was_pressed_this_frame(Action::TogglePause));
+    }
+
+    #[test]
+    fn release_all_lets_go_of_everything() {
+        let mut input = Input::default();
+        press(&mut input, KeyCode::KeyW);
+        input.handle_event(InputEvent::Button {
+            button: Button::Mouse(MouseButton::Right),
+            state: ElementState::Pressed,
+        });
+        input.handle_event(InputEvent::ReleaseAll);
+
+        assert!(!input.is_held(Action::MoveForward));
+        assert!(!input.is_held(Action::Look));
+        assert_eq!(input.get_movement(), glam::Vec3::ZERO);
+    }
+
+    #[test]
+    fn mouse_and_scroll_accumulate_until_the_end_of_the_tick() {
+        let mut input = Input::default();
+        input.handle_event(InputEvent::MouseMotion { x: 3., y: -2. });
+        input.handle_event(InputEvent::MouseMotion { x: 1., y: 1. });
+        input.handle_event(InputEvent::Scroll { lines: 1. });
+        input.handle_event(InputEvent::Scroll { lines: 0.5 });
+
+        // A frame without a tick keeps them for the next tick
+        input.end_frame();
+        assert_eq!(input.yaw_degrees, -4.);
+        assert_eq!(input.pitch_degrees, 1.);
+        assert_eq!(input.scroll_lines, 1.5);
+
+        // The first tick uses them up, so the rest of the frame's ticks don't repeat them
+        input.end_tick();
+        assert_eq!(input.yaw_degrees, 0.);
+        assert_eq!(input.pitch_degrees, 0.);
+        assert_eq!(input.scroll_lines, 0.);
+    }
+
+    #[test]
+    fn cursor_position_is_kept_across_ticks() {
+        let mut input = Input::default();
+        input.handle_event(InputEvent::CursorMoved { x: 10., y: 20. });
+        input.end_tick();
+        input.end_frame();
+        assert_eq!(input.cursor_position, Some(glam::Vec2::new(10., 20.)));
+
+        input.handle_event(InputEvent::CursorLeft);
+        assert_eq!(input.cursor_position, None);
+    }
+
+    #[test]
+    fn movement_is_normalised() {
+        let mut input = Input::default();
+        press(&mut input, KeyCode::KeyW);
+        press(&mut input, KeyCode::KeyD);
+        let movement = input.get_movement();
+        assert!((movement.length() - 1.).abs() < 1e-6);
+        assert!(movement.x > 0. && movement.z < 0.);
+
+        // Opposite directions cancel out
+        press(&mut input, KeyCode::KeyA);
+        assert_eq!(input.get_movement().x, 0.);
+    }
+}
@@ -0,0 +1,20 @@
+//! The engine behind the train game: a Vulkan renderer with a camera and scene to draw, assets
+//! loaded in the background, input mapped to rebindable actions, and a fixed-step simulation
+//! whose input can be recorded and replayed, along with the config and benchmark the game runs
+//! with. [`app::App`] is the game itself, which the `train` binary runs after parsing its
+//! arguments, and the examples are other clients.
+
+pub mod actions;
+pub mod app;
+pub mod assets;
+pub mod benchmark;
+pub mod config;
+pub mod graphics;
+pub mod input;
+pub mod locomotive;
+pub mod logging;
+pub mod replay;
+pub mod scene;
+pub mod scene_file;
+pub mod simulation;
+pub mod time;
@@ -0,0 +1,110 @@
+use glam::{Affine3A, Quat, Vec3};
+
+use crate::{
+    actions::Action,
+    graphics::Pose,
+    input::Input,
+    scene::{Entity, EntityId, Material, Mesh, Renderable, Scene},
+};
+
+/// The radius of the circular track, in metres. It runs around the origin.
+const TRACK_RADIUS: f32 = 30.;
+/// In metres per second per second.
+const ACCELERATION: f32 = 4.;
+const BRAKING: f32 = 10.;
+/// In metres per second.
+const MAX_SPEED: f32 = 25.;
+const SIZE: Vec3 = Vec3::new(3., 3., 8.);
+const COLOUR: [f32; 4] = [0.9, 0.6, 0.1, 1.0];
+/// The cab sits on top of the body at the back, relative to the locomotive.
+const CAB_SIZE: Vec3 = Vec3::new(2.6, 1.5, 2.5);
+pub const CAB_POSITION: Vec3 = Vec3::new(0., 2.25, 2.5);
+const CAB_COLOUR: [f32; 4] = [0.3, 0.2, 0.1, 1.0];
+
+/// A stand-in locomotive that runs around a circular track, driven with the throttle and brake.
+#[derive(Debug, Default)]
+pub struct Locomotive {
+    /// How far along the track it's travelled, in metres.
+    distance: f32,
+    /// `distance` at the end of the previous tick.
+    previous_distance: f32,
+    /// In metres per second.
+    speed: f32,
+}
+
+impl Locomotive {
+    /// Advances the locomotive by one simulation tick of `dt` seconds.
+    pub fn update(&mut self, dt: f32, input: &Input) {
+        self.previous_distance = self.distance;
+
+        if input.is_held(Action::Throttle) {
+            self.speed += ACCELERATION * dt;
+        }
+        if input.is_held(Action::Brake) {
+            self.speed -= BRAKING * dt;
+        }
+        self.speed = self.speed.clamp(0., MAX_SPEED);
+
+        self.distance += self.speed * dt;
+    }
+
+    /// In metres per second.
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    /// Where the locomotive is at the end of the latest tick. It faces -Z.
+    pub fn pose(&self) -> Pose {
+        pose_at(self.distance)
+    }
+
+    /// Adds the locomotive to `scene`, returning the entity that [`Locomotive::place`] moves.
+    /// The body and cab are its children.
+    pub fn spawn(&self, scene: &mut Scene) -> EntityId {
+        let root = scene.spawn(Entity::new("Locomotive"));
+
+        for (name, size, position, colour) in [
+            ("Body", SIZE, Vec3::ZERO, COLOUR),
+            ("Cab", CAB_SIZE, CAB_POSITION, CAB_COLOUR),
+        ] {
+            let material = scene.add_material(Material {
+                colour: colour.into(),
+            });
+            scene.spawn_child(
+                root,
+                Entity {
+                    transform: Affine3A::from_scale_rotation_translation(
+                        size,
+                        Quat::IDENTITY,
+                        position,
+                    ),
+                    renderable: Some(Renderable {
+                        mesh: Mesh::Cube,
+                        material,
+                    }),
+                    ..Entity::new(name)
+                },
+            );
+        }
+
+        root
+    }
+
+    /// Moves `entity` to where the locomotive is `alpha` of the way between the last two ticks.
+    pub fn place(&self, scene: &mut Scene, entity: EntityId, alpha: f32) {
+        let Pose { position, rotation } =
+            pose_at(self.previous_distance + (self.distance - self.previous_distance) * alpha);
+
+        scene.entity_mut(entity).transform =
+            Affine3A::from_rotation_translation(rotation, position);
+    }
+}
+
+fn pose_at(distance: f32) -> Pose {
+    // Anticlockwise when seen from above
+    let angle = distance / TRACK_RADIUS;
+    Pose {
+        position: Vec3::new(angle.cos(), 0., -angle.sin()) * TRACK_RADIUS,
+        rotation: Quat::from_rotation_y(angle),
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    fs::File,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing::{span, Subscriber};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, layer::Context, layer::SubscriberExt, registry::LookupSpan,
+    util::SubscriberInitExt, Layer,
+};
+
+use crate::config::LogLevel;
+
+/// Sends logs at `level` and above to stderr, and to `log_file` if there is one. Returns a
+/// [`SpanCapture`] that can record every span, whatever the level, for frame captures.
+pub fn init(level: LogLevel, log_file: Option<&Path>) -> Result<SpanCapture, std::io::Error> {
+    let level = match level {
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+
+    let file_layer = match log_file {
+        Some(path) => Some(
+            fmt::layer()
+                .with_ansi(false)
+                .with_writer(Mutex::new(File::create(path)?))
+                .with_filter(level),
+        ),
+        None => None,
+    };
+    let span_capture = SpanCapture::default();
+
+    tracing_subscriber::registry()
+        .with(fmt::layer().with_writer(std::io::stderr).with_filter(level))
+        .with(file_layer)
+        .with(span_capture.clone())
+        .init();
+
+    Ok(span_capture)
+}
+
+/// A span that was entered and then exited while capturing.
+#[derive(Debug, Clone, Copy)]
+pub struct CapturedSpan {
+    pub name: &'static str,
+    pub start: Instant,
+    pub duration: Duration,
+}
+
+/// Records how long each span is entered for between [`SpanCapture::start`] and
+/// [`SpanCapture::stop`]. Clones share the same recording.
+#[derive(Debug, Clone, Default)]
+pub struct SpanCapture(Arc<Mutex<Option<Vec<CapturedSpan>>>>);
+
+impl SpanCapture {
+    pub fn start(&self) {
+        *self.0.lock().unwrap() = Some(Vec::new());
+    }
+
+    /// Stops recording, and returns every span recorded since the capture started.
+    pub fn stop(&self) -> Vec<CapturedSpan> {
+        self.0.lock().unwrap().take().unwrap_or_default()
+    }
+
+    fn is_capturing(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+}
+
+/// When a span was entered, kept in the span's extensions until it's exited.
+struct Entered(Instant);
+
+impl<S> Layer<S> for SpanCapture
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_enter(&self, id: &span::Id, ctx: Context<'_, S>) {
+        if !self.is_capturing() {
+            return;
+        }
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().replace(Entered(Instant::now()));
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let Some(Entered(start)) = span.extensions_mut().remove::<Entered>() else {
+            return;
+        };
+
+        if let Some(spans) = self.0.lock().unwrap().as_mut() {
+            spans.push(CapturedSpan {
+                name: span.name(),
+                start,
+                duration: start.elapsed(),
+            });
+        }
+    }
+}
@@ -1,72 +1,219 @@
-mod graphics;
-mod input;
-
-use graphics::Graphics;
-use input::Input;
-use winit::{
-    application::ApplicationHandler,
-    event::{DeviceEvent, WindowEvent},
-    event_loop::{ControlFlow, EventLoop},
-    window::Window,
+use std::path::PathBuf;
+
+use tracing::{error, info, warn};
+use train::{
+    actions::Bindings,
+    app::{self, App},
+    benchmark,
+    config::{self, Config, Setting},
+    logging,
+    replay::{self, Recording, Replay},
+    scene_file::SceneFile,
+    simulation::DEFAULT_CAMERA_PATH,
 };
+use winit::event_loop::{ControlFlow, EventLoop};
+
+/// Both relative to the asset root.
+const CONFIG_PATH: &str = "config/train.ron";
+const BINDINGS_PATH: &str = "config/bindings.ron";
+const DEFAULT_BENCHMARK_REPORT: &str = "benchmark.json";
 
-#[derive(Default)]
-struct App {
-    graphics: Option<Graphics>,
-    input: Input,
+#[derive(Debug, Default)]
+struct Args {
+    /// Record input to this file, to be replayed later.
+    record: Option<PathBuf>,
+    /// Replay input from this file instead of using live input.
+    replay: Option<PathBuf>,
+    /// Where camera paths are saved to and played back from.
+    camera_path: Option<PathBuf>,
+    /// Run a benchmark for this many seconds instead of the game.
+    benchmark: Option<f32>,
+    /// Where to write the benchmark report. A `.csv` extension gives CSV, anything else JSON.
+    report: Option<PathBuf>,
+    /// Where to write a trace of the benchmark, to be viewed in `chrome://tracing`.
+    trace: Option<PathBuf>,
+    /// Load the config from this file instead of the default, which doesn't have to exist.
+    config: Option<PathBuf>,
+    /// Settings given on the command line, which override the config file and environment.
+    overrides: Vec<(Setting, String)>,
+    /// Print the config, after applying any overrides, instead of running.
+    print_config: bool,
 }
 
-impl ApplicationHandler for App {
-    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        let window = event_loop
-            .create_window(Window::default_attributes())
-            .unwrap();
-        self.graphics = Some(Graphics::new(window));
-    }
+impl Args {
+    fn parse() -> Self {
+        let mut args = Args::default();
+        let mut iter = std::env::args().skip(1);
 
-    fn window_event(
-        &mut self,
-        event_loop: &winit::event_loop::ActiveEventLoop,
-        _window_id: winit::window::WindowId,
-        event: winit::event::WindowEvent,
-    ) {
-        match event {
-            WindowEvent::CloseRequested => {
-                event_loop.exit();
+        while let Some(arg) = iter.next() {
+            // Settings can be given as `--flag value` or `--flag=value`, and switches on their own
+            let (flag, value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_owned())),
+                None => (arg.as_str(), None),
+            };
+            if let Some(setting) = Setting::ALL
+                .into_iter()
+                .find(|setting| setting.flag() == flag)
+            {
+                let value = match value {
+                    Some(value) => value,
+                    None if setting.is_switch() => String::from("true"),
+                    None => iter.next().unwrap_or_else(|| exit_with_usage()),
+                };
+                args.overrides.push((setting, value));
+                continue;
             }
-            WindowEvent::KeyboardInput { event, .. } => {
-                self.input.handle_keyboard_event(event);
+
+            match arg.as_str() {
+                "--record" => args.record = Some(next_path(&mut iter)),
+                "--replay" => args.replay = Some(next_path(&mut iter)),
+                "--camera-path" => args.camera_path = Some(next_path(&mut iter)),
+                "--benchmark" => {
+                    let seconds = iter.next().and_then(|seconds| seconds.parse().ok());
+                    args.benchmark = Some(seconds.unwrap_or_else(|| exit_with_usage()));
+                }
+                "--report" => args.report = Some(next_path(&mut iter)),
+                "--trace" => args.trace = Some(next_path(&mut iter)),
+                "--config" => args.config = Some(next_path(&mut iter)),
+                "--print-config" => args.print_config = true,
+                _ => {
+                    eprintln!("Unknown argument {arg:?}");
+                    exit_with_usage();
+                }
             }
-            _ => {}
         }
+
+        args
+    }
+}
+
+/// The path following a flag that needs one.
+fn next_path(iter: &mut impl Iterator<Item = String>) -> PathBuf {
+    iter.next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| exit_with_usage())
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!(
+        "Usage: train [options] [--record <file>] [--replay <file> [--headless]] \
+         [--camera-path <file>]"
+    );
+    eprintln!("       train [options] --benchmark <seconds> [--report <file>] [--trace <file>]");
+    eprintln!("       train [options] --print-config");
+    eprintln!();
+    eprintln!("Options, which override the asset root's {CONFIG_PATH} and the environment:");
+    eprintln!("  --config <file>");
+    eprintln!("  --width <pixels> --height <pixels> --fullscreen[=false]");
+    eprintln!("  --gpu <index or name> --present-mode <fifo|mailbox|immediate>");
+    eprintln!("  --image-count <images> --frame-limit <frames per second>");
+    eprintln!("  --msaa-samples <1|2|4|8> --scene <file> --headless[=false]");
+    eprintln!("  --asset-root <directory> --hot-reload[=false]");
+    eprintln!("  --log-level <error|warn|info|debug|trace> --log-file <file>");
+    eprintln!();
+    eprintln!(
+        "Each setting can also be set with an environment variable, such as {}.",
+        Setting::MsaaSamples.env_var()
+    );
+    std::process::exit(1);
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let config_path = args
+        .config
+        .clone()
+        .unwrap_or_else(|| config::initial_asset_root(&args.overrides).join(CONFIG_PATH));
+    let config =
+        Config::load(&config_path, args.config.is_some(), &args.overrides).unwrap_or_else(|e| {
+            eprintln!("Couldn't load config: {e}");
+            std::process::exit(1);
+        });
+    if args.print_config {
+        println!("{}", config.to_ron());
+        return;
     }
 
-    fn device_event(
-        &mut self,
-        _: &winit::event_loop::ActiveEventLoop,
-        _: winit::event::DeviceId,
-        event: winit::event::DeviceEvent,
-    ) {
-        match event {
-            DeviceEvent::MouseMotion { delta: (x, y) } => self.input.handle_mouse_motion(x, y),
-            _ => {}
+    let span_capture =
+        logging::init(config.log_level, config.log_file.as_deref()).unwrap_or_else(|e| {
+            eprintln!("Couldn't open log file: {e}");
+            std::process::exit(1);
+        });
+
+    let scene_file = SceneFile::load(&config.scene_path()).unwrap_or_else(|e| {
+        error!("Couldn't load scene: {e}");
+        std::process::exit(1);
+    });
+
+    if let Some(seconds) = args.benchmark {
+        let (report, trace) = benchmark::run(
+            scene_file,
+            &config.graphics_settings(),
+            seconds,
+            args.trace.is_some(),
+        );
+        print!("{report}");
+
+        if let (Some(trace), Some(path)) = (trace, &args.trace) {
+            match trace.save(path) {
+                Ok(()) => info!("Saved trace to {}", path.display()),
+                Err(e) => error!("Couldn't save trace to {}: {e}", path.display()),
+            }
         }
+
+        let path = args
+            .report
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_BENCHMARK_REPORT));
+        if let Err(e) = report.save(&path) {
+            error!("Couldn't save benchmark report to {}: {e}", path.display());
+            std::process::exit(1);
+        }
+        info!("Saved benchmark report to {}", path.display());
+        return;
     }
 
-    fn about_to_wait(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
-        let Some(graphics) = &mut self.graphics else {
-            return;
-        };
+    let bindings = Bindings::load(&config.asset_root.join(BINDINGS_PATH)).unwrap_or_else(|e| {
+        warn!("Couldn't load bindings, using the defaults: {e}");
+        Bindings::default()
+    });
+    for conflict in bindings.conflicts() {
+        warn!("Binding conflict: {conflict}");
+    }
+
+    let replay = args.replay.map(|path| {
+        Recording::load(&path).unwrap_or_else(|e| {
+            error!("Couldn't load recording from {}: {e}", path.display());
+            std::process::exit(1);
+        })
+    });
 
-        graphics.draw(&self.input);
-        self.input.reset();
+    if config.headless {
+        let Some(recording) = replay else {
+            error!("--headless needs a recording to --replay");
+            std::process::exit(1);
+        };
+        let camera_path = args
+            .camera_path
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CAMERA_PATH));
+        let camera = replay::replay_headless(recording, scene_file.camera, camera_path);
+        app::print_camera(&camera);
+        return;
     }
-}
 
-fn main() {
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::default();
+    let mut app = App::new(
+        config,
+        span_capture,
+        scene_file,
+        bindings,
+        args.record,
+        replay.map(Replay::new),
+    );
+    if let Some(camera_path) = args.camera_path {
+        app.simulation.camera_path = camera_path;
+    }
     event_loop.run_app(&mut app).unwrap();
 }
@@ -0,0 +1,160 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    actions::Bindings,
+    graphics::{Camera, Pose},
+    input::{Input, InputEvent},
+    simulation::Simulation,
+    time::TICK_DURATION,
+};
+
+/// An [`InputEvent`], stamped with when it reached [`Input`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// How many frames had been advanced when the event arrived.
+    pub frame: u64,
+    /// The first tick that saw the event.
+    pub tick: u64,
+    pub event: InputEvent,
+}
+
+/// Everything needed to play a session back: the bindings in use, the input events in the order
+/// they arrived, and how many ticks were run in total.
+///
+/// Ticks run at a fixed rate, so feeding each event to the tick that originally saw it reproduces
+/// the simulation exactly, however fast frames are rendered during playback.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    #[serde(default)]
+    pub bindings: Bindings,
+    pub events: Vec<RecordedEvent>,
+    pub tick_count: u64,
+}
+
+impl Recording {
+    pub fn new(bindings: Bindings) -> Self {
+        Self {
+            bindings,
+            events: Vec::new(),
+            tick_count: 0,
+        }
+    }
+
+    pub fn record(&mut self, frame: u64, tick: u64, event: InputEvent) {
+        self.events.push(RecordedEvent { frame, tick, event });
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RecordingError> {
+        let contents = std::fs::read_to_string(path).map_err(RecordingError::Io)?;
+        ron::from_str(&contents).map_err(|e| RecordingError::Parse(Box::new(e)))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), RecordingError> {
+        let contents = ron::to_string(self).map_err(RecordingError::Serialise)?;
+        std::fs::write(path, contents).map_err(RecordingError::Io)
+    }
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(std::io::Error),
+    Parse(Box<ron::error::SpannedError>),
+    Serialise(ron::Error),
+}
+
+impl fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordingError::Io(e) => write!(f, "{e}"),
+            RecordingError::Parse(e) => write!(f, "{e}"),
+            RecordingError::Serialise(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+/// Feeds a [`Recording`] back into [`Input`] in place of live events.
+#[derive(Debug)]
+pub struct Replay {
+    recording: Recording,
+    /// The index of the next event to feed.
+    next: usize,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Self {
+        Self { recording, next: 0 }
+    }
+
+    pub fn bindings(&self) -> &Bindings {
+        &self.recording.bindings
+    }
+
+    /// Feeds the events from the next recorded frame, as long as they arrived before `tick`.
+    ///
+    /// While the simulation is paused `tick` doesn't move, so this plays back one recorded frame
+    /// per frame; that keeps frame-based actions like unpausing working as they did originally.
+    pub fn feed_frame(&mut self, tick: u64, input: &mut Input) {
+        let Some(frame) = self.peek(tick).map(|event| event.frame) else {
+            return;
+        };
+
+        while let Some(event) = self.peek(tick).filter(|event| event.frame == frame) {
+            input.handle_event(event.event);
+            self.next += 1;
+        }
+    }
+
+    /// How many ticks can run before the next recorded frame's events have to be fed, or before
+    /// the recording ends. Running past this would apply them later than they originally were.
+    pub fn tick_limit(&self) -> u64 {
+        self.recording
+            .events
+            .get(self.next)
+            .map_or(self.recording.tick_count, |event| event.tick)
+    }
+
+    /// Have all the events been fed, and all the recorded ticks run?
+    pub fn is_finished(&self, tick_count: u64) -> bool {
+        self.next == self.recording.events.len() && tick_count >= self.recording.tick_count
+    }
+
+    fn peek(&self, tick: u64) -> Option<RecordedEvent> {
+        self.recording
+            .events
+            .get(self.next)
+            .filter(|event| event.tick <= tick)
+            .copied()
+    }
+}
+
+/// Plays `recording` back as fast as possible without a window, with the camera starting at
+/// `camera_start` and camera paths played from `camera_path`, and returns the camera in its final
+/// state.
+///
+/// Each frame is a tick long, but otherwise the replay runs exactly as it would in a window.
+pub fn replay_headless(recording: Recording, camera_start: Pose, camera_path: PathBuf) -> Camera {
+    let mut simulation = Simulation::new(recording.bindings.clone(), Some(Replay::new(recording)));
+    simulation.camera_path = camera_path;
+    let mut camera = Camera::new(vk::Extent2D {
+        width: 1,
+        height: 1,
+    });
+    camera.set_pose(camera_start);
+
+    while simulation.is_replaying() {
+        simulation.run_frame(&mut camera, Some(Duration::from_secs_f32(TICK_DURATION)));
+        simulation.end_frame();
+    }
+    camera.interpolate(1.);
+
+    camera
+}
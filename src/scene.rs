@@ -0,0 +1,440 @@
+use std::fmt;
+
+use glam::{Affine3A, Vec3, Vec4};
+use serde::Deserialize;
+
+use crate::graphics::Cube;
+
+/// Identifies an entity in a [`Scene`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntityId(usize);
+
+/// Identifies a material in a [`Scene`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialId(usize);
+
+/// The shapes the renderer knows how to draw.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Mesh {
+    /// A unit cube around the origin.
+    Cube,
+}
+
+impl Mesh {
+    /// The mesh's bounds in its own space.
+    pub fn bounds(self) -> Aabb {
+        match self {
+            Mesh::Cube => Aabb {
+                min: Vec3::splat(-0.5),
+                max: Vec3::splat(0.5),
+            },
+        }
+    }
+}
+
+/// How a mesh's surface looks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    /// Linear.
+    pub colour: Vec4,
+}
+
+/// What an entity looks like, for entities that are drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Renderable {
+    pub mesh: Mesh,
+    pub material: MaterialId,
+}
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The smallest box that holds this one after it's been transformed.
+    pub fn transformed(&self, transform: &Affine3A) -> Aabb {
+        let centre = transform.transform_point3((self.min + self.max) / 2.);
+        let half_size = (self.max - self.min) / 2.;
+        let matrix = transform.matrix3;
+        let half_size = matrix.x_axis.abs() * half_size.x
+            + matrix.y_axis.abs() * half_size.y
+            + matrix.z_axis.abs() * half_size.z;
+        let half_size = Vec3::from(half_size);
+
+        Aabb {
+            min: centre - half_size,
+            max: centre + half_size,
+        }
+    }
+}
+
+/// Something in the world. Entities can be moved, drawn, or used to group other entities.
+///
+/// An entity's parent belongs to the [`Scene`], which checks it with [`Scene::set_parent`].
+#[derive(Debug, Clone)]
+pub struct Entity {
+    pub name: String,
+    /// Relative to the parent, or to the world if there isn't one.
+    pub transform: Affine3A,
+    pub renderable: Option<Renderable>,
+}
+
+impl Entity {
+    /// An entity at the world's origin with nothing to draw.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            transform: Affine3A::IDENTITY,
+            renderable: None,
+        }
+    }
+}
+
+/// Everything in the world, which gameplay moves around and the renderer draws.
+#[derive(Debug, Default, Clone)]
+pub struct Scene {
+    entities: Vec<Entity>,
+    /// Each entity's parent, which always comes before it.
+    parents: Vec<Option<EntityId>>,
+    materials: Vec<Material>,
+}
+
+/// Why [`Scene::set_parent`] refused a parent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParentError {
+    /// The parent is the entity itself or one of its descendants.
+    Cycle,
+    /// The parent was spawned after the entity, so its world transform isn't known in time.
+    SpawnedAfter,
+}
+
+impl fmt::Display for ParentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParentError::Cycle => write!(f, "an entity can't be its own ancestor"),
+            ParentError::SpawnedAfter => write!(f, "the parent must be spawned before the child"),
+        }
+    }
+}
+
+impl std::error::Error for ParentError {}
+
+/// The renderer's view of a scene: a cube for each drawn entity, and which entity it's for.
+#[derive(Debug, Default, Clone)]
+pub struct DrawList {
+    pub cubes: Vec<Cube>,
+    pub entities: Vec<EntityId>,
+}
+
+impl DrawList {
+    /// The index of `entity`'s cube, if it's drawn.
+    pub fn index_of(&self, entity: EntityId) -> Option<usize> {
+        self.entities.iter().position(|&id| id == entity)
+    }
+}
+
+impl Scene {
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials.push(material);
+        MaterialId(self.materials.len() - 1)
+    }
+
+    /// Adds `entity` to the scene, without a parent.
+    pub fn spawn(&mut self, entity: Entity) -> EntityId {
+        self.entities.push(entity);
+        self.parents.push(None);
+        EntityId(self.entities.len() - 1)
+    }
+
+    /// Adds `entity` to the scene as a child of `parent`. Panics if `parent` isn't in the scene.
+    pub fn spawn_child(&mut self, parent: EntityId, entity: Entity) -> EntityId {
+        assert!(
+            parent.0 < self.entities.len(),
+            "Parent must be spawned first"
+        );
+
+        let id = self.spawn(entity);
+        self.parents[id.0] = Some(parent);
+        id
+    }
+
+    pub fn entity(&self, id: EntityId) -> &Entity {
+        &self.entities[id.0]
+    }
+
+    pub fn entity_mut(&mut self, id: EntityId) -> &mut Entity {
+        &mut self.entities[id.0]
+    }
+
+    pub fn parent(&self, id: EntityId) -> Option<EntityId> {
+        self.parents[id.0]
+    }
+
+    /// Moves `id` under `parent`, or to the top of the hierarchy for `None`. Its transform is kept,
+    /// so it's now relative to the new parent. Parents must come before their children, which
+    /// also keeps the hierarchy free of cycles.
+    pub fn set_parent(
+        &mut self,
+        id: EntityId,
+        parent: Option<EntityId>,
+    ) -> Result<(), ParentError> {
+        if let Some(parent) = parent {
+            if self.is_descendant(parent, id) {
+                return Err(ParentError::Cycle);
+            }
+            if parent.0 > id.0 {
+                return Err(ParentError::SpawnedAfter);
+            }
+        }
+
+        self.parents[id.0] = parent;
+        Ok(())
+    }
+
+    /// The world-space bounds of `id` and all of its descendants that are drawn, if any are.
+    pub fn world_bounds(&self, id: EntityId) -> Option<Aabb> {
+        let world_transforms = self.world_transforms();
+
+        (id.0..self.entities.len())
+            .filter(|&index| self.is_descendant(EntityId(index), id))
+            .filter_map(|index| {
+                let renderable = self.entities[index].renderable?;
+                Some(
+                    renderable
+                        .mesh
+                        .bounds()
+                        .transformed(&world_transforms[index]),
+                )
+            })
+            .reduce(|a, b| Aabb {
+                min: a.min.min(b.min),
+                max: a.max.max(b.max),
+            })
+    }
+
+    /// Whether `id` is `ancestor`, or one of its descendants.
+    fn is_descendant(&self, id: EntityId, ancestor: EntityId) -> bool {
+        let mut current = Some(id);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self.parent(id);
+        }
+        false
+    }
+
+    /// Every entity's world transform, in the same order as the entities.
+    fn world_transforms(&self) -> Vec<Affine3A> {
+        let mut world_transforms: Vec<Affine3A> = Vec::with_capacity(self.entities.len());
+        for (entity, parent) in self.entities.iter().zip(&self.parents) {
+            // Parents come first, so theirs is already known
+            let world_transform = match *parent {
+                Some(EntityId(parent)) => world_transforms[parent] * entity.transform,
+                None => entity.transform,
+            };
+            world_transforms.push(world_transform);
+        }
+        world_transforms
+    }
+
+    /// Lists everything to be drawn.
+    pub fn draw_list(&self) -> DrawList {
+        let mut draw_list = DrawList::default();
+
+        for (index, world_transform) in self.world_transforms().into_iter().enumerate() {
+            let Some(Renderable { mesh, material }) = self.entities[index].renderable else {
+                continue;
+            };
+
+            match mesh {
+                Mesh::Cube => draw_list.cubes.push(Cube {
+                    transform: world_transform,
+                    colour: self.materials[material.0].colour,
+                }),
+            }
+            draw_list.entities.push(EntityId(index));
+        }
+
+        draw_list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::Quat;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn assert_aabb_eq(aabb: Aabb, min: Vec3, max: Vec3) {
+        assert!(
+            aabb.min.abs_diff_eq(min, EPSILON) && aabb.max.abs_diff_eq(max, EPSILON),
+            "{aabb:?} isn't {min}..{max}"
+        );
+    }
+
+    fn cube(scene: &mut Scene, colour: Vec4) -> Entity {
+        let material = scene.add_material(Material { colour });
+        Entity {
+            renderable: Some(Renderable {
+                mesh: Mesh::Cube,
+                material,
+            }),
+            ..Entity::new("Cube")
+        }
+    }
+
+    #[test]
+    fn transformed_bounds_hold_the_rotated_and_scaled_box() {
+        let bounds = Mesh::Cube.bounds();
+
+        let moved = bounds.transformed(&Affine3A::from_translation(Vec3::new(1., 2., 3.)));
+        assert_aabb_eq(moved, Vec3::new(0.5, 1.5, 2.5), Vec3::new(1.5, 2.5, 3.5));
+
+        let scaled = bounds.transformed(&Affine3A::from_scale(Vec3::new(2., 4., 6.)));
+        assert_aabb_eq(scaled, Vec3::new(-1., -2., -3.), Vec3::new(1., 2., 3.));
+
+        // A quarter turn swaps the X and Z extents
+        let turned = Aabb {
+            min: Vec3::new(-1., -0.5, -0.5),
+            max: Vec3::new(1., 0.5, 0.5),
+        }
+        .transformed(&Affine3A::from_rotation_y(FRAC_PI_2));
+        assert_aabb_eq(turned, Vec3::new(-0.5, -0.5, -1.), Vec3::new(0.5, 0.5, 1.));
+
+        // An eighth turn makes the box as wide as the cube's diagonal
+        let diagonal = bounds.transformed(&Affine3A::from_rotation_y(FRAC_PI_2 / 2.));
+        let half = std::f32::consts::SQRT_2 / 2.;
+        assert_aabb_eq(
+            diagonal,
+            Vec3::new(-half, -0.5, -half),
+            Vec3::new(half, 0.5, half),
+        );
+    }
+
+    #[test]
+    fn children_are_drawn_relative_to_their_ancestors() {
+        let mut scene = Scene::default();
+        let root = scene.spawn(Entity {
+            transform: Affine3A::from_translation(Vec3::new(10., 0., 0.)),
+            ..Entity::new("Root")
+        });
+        let turned = scene.spawn_child(
+            root,
+            Entity {
+                transform: Affine3A::from_rotation_y(FRAC_PI_2),
+                ..Entity::new("Turned")
+            },
+        );
+        let mut leaf = cube(&mut scene, Vec4::ONE);
+        leaf.transform = Affine3A::from_translation(Vec3::new(0., 0., -1.));
+        let leaf = scene.spawn_child(turned, leaf);
+
+        let draw_list = scene.draw_list();
+        assert_eq!(draw_list.entities, [leaf]);
+
+        // -Z turned a quarter about Y is -X, then moved by the root
+        let position = draw_list.cubes[0].transform.translation;
+        assert!(position.abs_diff_eq(Vec3::new(9., 0., 0.).into(), EPSILON));
+        let rotation = Quat::from_affine3(&draw_list.cubes[0].transform);
+        assert!(rotation.abs_diff_eq(Quat::from_rotation_y(FRAC_PI_2), EPSILON));
+    }
+
+    #[test]
+    fn draw_list_has_each_drawn_entity_in_spawn_order() {
+        let mut scene = Scene::default();
+        let red = cube(&mut scene, Vec4::new(1., 0., 0., 1.));
+        let red = scene.spawn(red);
+        scene.spawn(Entity::new("Group"));
+        let blue = cube(&mut scene, Vec4::new(0., 0., 1., 1.));
+        let blue = scene.spawn(blue);
+
+        let draw_list = scene.draw_list();
+        assert_eq!(draw_list.entities, [red, blue]);
+        let colours: Vec<_> = draw_list.cubes.iter().map(|cube| cube.colour).collect();
+        assert_eq!(
+            colours,
+            [Vec4::new(1., 0., 0., 1.), Vec4::new(0., 0., 1., 1.)]
+        );
+        assert_eq!(draw_list.index_of(blue), Some(1));
+        assert_eq!(draw_list.index_of(EntityId(1)), None);
+    }
+
+    #[test]
+    fn world_bounds_cover_every_drawn_descendant() {
+        let mut scene = Scene::default();
+        let root = scene.spawn(Entity {
+            transform: Affine3A::from_translation(Vec3::new(0., 1., 0.)),
+            ..Entity::new("Root")
+        });
+        let mut left = cube(&mut scene, Vec4::ONE);
+        left.transform = Affine3A::from_translation(Vec3::new(-2., 0., 0.));
+        let left = scene.spawn_child(root, left);
+        let mut right = cube(&mut scene, Vec4::ONE);
+        right.transform = Affine3A::from_translation(Vec3::new(2., 0., 0.));
+        scene.spawn_child(root, right);
+        let elsewhere = cube(&mut scene, Vec4::ONE);
+        scene.spawn(elsewhere);
+
+        assert_aabb_eq(
+            scene.world_bounds(root).unwrap(),
+            Vec3::new(-2.5, 0.5, -0.5),
+            Vec3::new(2.5, 1.5, 0.5),
+        );
+        assert_aabb_eq(
+            scene.world_bounds(left).unwrap(),
+            Vec3::new(-2.5, 0.5, -0.5),
+            Vec3::new(-1.5, 1.5, 0.5),
+        );
+
+        let empty = scene.spawn(Entity::new("Empty"));
+        assert_eq!(scene.world_bounds(empty), None);
+    }
+
+    #[test]
+    fn descendants_include_the_entity_and_its_children_only() {
+        let mut scene = Scene::default();
+        let root = scene.spawn(Entity::new("Root"));
+        let child = scene.spawn_child(root, Entity::new("Child"));
+        let grandchild = scene.spawn_child(child, Entity::new("Grandchild"));
+        let other = scene.spawn(Entity::new("Other"));
+
+        assert!(scene.is_descendant(root, root));
+        assert!(scene.is_descendant(child, root));
+        assert!(scene.is_descendant(grandchild, root));
+        assert!(!scene.is_descendant(root, child));
+        assert!(!scene.is_descendant(other, root));
+        assert!(!scene.is_descendant(grandchild, other));
+    }
+
+    #[test]
+    fn set_parent_rejects_later_entities_and_cycles() {
+        let mut scene = Scene::default();
+        let root = scene.spawn(Entity::new("Root"));
+        let child = scene.spawn_child(root, Entity::new("Child"));
+        let other = scene.spawn(Entity::new("Other"));
+
+        assert_eq!(scene.set_parent(root, Some(root)), Err(ParentError::Cycle));
+        assert_eq!(scene.set_parent(root, Some(child)), Err(ParentError::Cycle));
+        assert_eq!(
+            scene.set_parent(child, Some(other)),
+            Err(ParentError::SpawnedAfter)
+        );
+        assert_eq!(scene.parent(child), Some(root));
+
+        assert_eq!(scene.set_parent(other, Some(child)), Ok(()));
+        assert_eq!(scene.parent(other), Some(child));
+        assert!(scene.is_descendant(other, root));
+
+        assert_eq!(scene.set_parent(child, None), Ok(()));
+        assert_eq!(scene.parent(child), None);
+        assert!(!scene.is_descendant(other, root));
+    }
+}
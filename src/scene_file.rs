@@ -0,0 +1,482 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use glam::{Affine3A, EulerRot, Quat, Vec3, Vec4};
+use serde::Deserialize;
+
+use crate::{
+    graphics::{Lighting, Pose},
+    scene::{Entity, Material, Mesh, Renderable, Scene},
+};
+
+/// The scene loaded at startup unless another is chosen, relative to the asset root.
+pub const DEFAULT_SCENE_PATH: &str = "scenes/default.ron";
+
+/// Everything a scene file describes: what's in the world, where the camera starts, and how it's
+/// all lit.
+#[derive(Debug, Clone)]
+pub struct SceneFile {
+    pub scene: Scene,
+    pub camera: Pose,
+    pub lighting: Lighting,
+}
+
+impl SceneFile {
+    /// Loads and checks the scene file at `path`. Errors name the file, and the field that's wrong
+    /// if the file parsed.
+    pub fn load(path: &Path) -> Result<Self, SceneFileError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| SceneFileError::Io(path.to_owned(), e))?;
+        Self::from_ron(&contents, path)
+    }
+
+    /// Parses and checks a scene file's `contents`. `path` is only used to name the file in errors.
+    pub fn from_ron(contents: &str, path: &Path) -> Result<Self, SceneFileError> {
+        let description: SceneDescription = ron::from_str(contents)
+            .map_err(|e| SceneFileError::Parse(path.to_owned(), Box::new(e)))?;
+
+        description
+            .build()
+            .map_err(|Invalid { field, message }| SceneFileError::Invalid {
+                path: path.to_owned(),
+                field,
+                message,
+            })
+    }
+}
+
+/// A scene file as it's written.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    #[serde(default)]
+    camera: CameraDescription,
+    #[serde(default)]
+    lighting: Lighting,
+    /// By name, which entities use to refer to them.
+    #[serde(default)]
+    materials: BTreeMap<String, MaterialDescription>,
+    /// Parents must come before their children.
+    entities: Vec<EntityDescription>,
+}
+
+/// Where the camera starts, flying freely.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct CameraDescription {
+    position: Vec3,
+    /// Turning left from looking down -Z.
+    yaw_degrees: f32,
+    /// Looking up from level.
+    pitch_degrees: f32,
+}
+
+impl Default for CameraDescription {
+    fn default() -> Self {
+        Self {
+            position: Vec3::new(0., 10., 10.),
+            yaw_degrees: 0.,
+            pitch_degrees: 0.,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MaterialDescription {
+    /// Linear.
+    colour: Vec4,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntityDescription {
+    name: String,
+    /// The name of an entity earlier in the file.
+    #[serde(default)]
+    parent: Option<String>,
+    /// The transform is relative to the parent, or to the world if there isn't one.
+    #[serde(default)]
+    position: Vec3,
+    /// Angles about the X, Y and Z axes, applied yaw first, then pitch, then roll.
+    #[serde(default)]
+    rotation_degrees: Vec3,
+    #[serde(default = "unit_scale")]
+    scale: Vec3,
+    /// Entities without a mesh aren't drawn, and are only useful for grouping others.
+    #[serde(default)]
+    mesh: Option<Mesh>,
+    /// The name of a material. Needed if, and only if, there's a mesh.
+    #[serde(default)]
+    material: Option<String>,
+}
+
+fn unit_scale() -> Vec3 {
+    Vec3::ONE
+}
+
+/// A field that parsed but doesn't make sense.
+struct Invalid {
+    field: String,
+    message: String,
+}
+
+/// Fails with `message` about `field` unless `condition` holds.
+fn check(condition: bool, field: impl FnOnce() -> String, message: &str) -> Result<(), Invalid> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Invalid {
+            field: field(),
+            message: message.to_owned(),
+        })
+    }
+}
+
+impl SceneDescription {
+    fn build(self) -> Result<SceneFile, Invalid> {
+        let CameraDescription {
+            position,
+            yaw_degrees,
+            pitch_degrees,
+        } = self.camera;
+        check(
+            position.is_finite(),
+            || "camera.position".into(),
+            "must be finite",
+        )?;
+        check(
+            yaw_degrees.is_finite(),
+            || "camera.yaw_degrees".into(),
+            "must be finite",
+        )?;
+        check(
+            (-90. ..=90.).contains(&pitch_degrees),
+            || "camera.pitch_degrees".into(),
+            "must be between -90 and 90",
+        )?;
+        let camera = Pose {
+            position,
+            rotation: Quat::from_euler(
+                EulerRot::YXZ,
+                yaw_degrees.to_radians(),
+                pitch_degrees.to_radians(),
+                0.,
+            ),
+        };
+
+        let lighting = self.lighting;
+        check(
+            lighting.sun_direction.is_finite() && lighting.sun_direction != Vec3::ZERO,
+            || "lighting.sun_direction".into(),
+            "must be finite and not zero",
+        )?;
+        check(
+            lighting.shadow_distance > 0.,
+            || "lighting.shadow_distance".into(),
+            "must be more than zero",
+        )?;
+        check(
+            (0. ..=1.).contains(&lighting.cascade_split_lambda),
+            || "lighting.cascade_split_lambda".into(),
+            "must be between 0 and 1",
+        )?;
+        for (field, colour) in [
+            ("sun_colour", lighting.sun_colour.extend(0.)),
+            ("ambient_colour", lighting.ambient_colour.extend(0.)),
+            ("clear_colour", lighting.clear_colour),
+        ] {
+            check(
+                is_colour(colour),
+                || format!("lighting.{field}"),
+                "must be finite and not negative",
+            )?;
+        }
+        check(
+            lighting.sun_intensity >= 0.,
+            || "lighting.sun_intensity".into(),
+            "must not be negative",
+        )?;
+
+        let mut scene = Scene::default();
+        let mut materials = HashMap::new();
+        for (name, MaterialDescription { colour }) in self.materials {
+            check(
+                is_colour(colour),
+                || format!("materials[{name:?}].colour"),
+                "must be finite and not negative",
+            )?;
+            materials.insert(name, scene.add_material(Material { colour }));
+        }
+
+        let mut entities = HashMap::new();
+        for (index, entity) in self.entities.into_iter().enumerate() {
+            let field = |name: &str| format!("entities[{index}].{name}");
+
+            check(
+                !entity.name.is_empty(),
+                || field("name"),
+                "must not be empty",
+            )?;
+            check(
+                !entities.contains_key(&entity.name),
+                || field("name"),
+                &format!("there's already an entity named {:?}", entity.name),
+            )?;
+
+            let parent = match &entity.parent {
+                Some(parent) => Some(*entities.get(parent).ok_or_else(|| Invalid {
+                    field: field("parent"),
+                    message: format!("no entity named {parent:?} comes before this one"),
+                })?),
+                None => None,
+            };
+
+            check(
+                entity.position.is_finite(),
+                || field("position"),
+                "must be finite",
+            )?;
+            check(
+                entity.rotation_degrees.is_finite(),
+                || field("rotation_degrees"),
+                "must be finite",
+            )?;
+            check(
+                entity.scale.is_finite() && entity.scale.cmpne(Vec3::ZERO).all(),
+                || field("scale"),
+                "must be finite and not zero on any axis",
+            )?;
+            let rotation = entity.rotation_degrees;
+            let transform = Affine3A::from_scale_rotation_translation(
+                entity.scale,
+                Quat::from_euler(
+                    EulerRot::YXZ,
+                    rotation.y.to_radians(),
+                    rotation.x.to_radians(),
+                    rotation.z.to_radians(),
+                ),
+                entity.position,
+            );
+
+            let renderable = match (entity.mesh, &entity.material) {
+                (Some(mesh), Some(material)) => Some(Renderable {
+                    mesh,
+                    material: *materials.get(material).ok_or_else(|| Invalid {
+                        field: field("material"),
+                        message: format!("there's no material named {material:?}"),
+                    })?,
+                }),
+                (Some(_), None) => {
+                    return Err(Invalid {
+                        field: field("material"),
+                        message: "must be set to draw the mesh".into(),
+                    })
+                }
+                (None, Some(_)) => {
+                    return Err(Invalid {
+                        field: field("mesh"),
+                        message: "must be set to use the material".into(),
+                    })
+                }
+                (None, None) => None,
+            };
+
+            let spawned = Entity {
+                transform,
+                renderable,
+                ..Entity::new(entity.name.clone())
+            };
+            let id = match parent {
+                Some(parent) => scene.spawn_child(parent, spawned),
+                None => scene.spawn(spawned),
+            };
+            entities.insert(entity.name, id);
+        }
+
+        Ok(SceneFile {
+            scene,
+            camera,
+            lighting,
+        })
+    }
+}
+
+fn is_colour(colour: Vec4) -> bool {
+    colour.is_finite() && colour.cmpge(Vec4::ZERO).all()
+}
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, Box<ron::error::SpannedError>),
+    /// The file parsed, but `field` has a value that doesn't make sense.
+    Invalid {
+        path: PathBuf,
+        field: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            // The error starts with the line and column
+            SceneFileError::Parse(path, e) => write!(f, "{}:{e}", path.display()),
+            SceneFileError::Invalid {
+                path,
+                field,
+                message,
+            } => write!(f, "{}: {field}: {message}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(contents: &str) -> Result<SceneFile, String> {
+        SceneFile::from_ron(contents, Path::new("test.ron")).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn builds_the_entity_hierarchy() {
+        let scene_file = parse(
+            r#"(
+                materials: { "red": (colour: (1.0, 0.0, 0.0, 1.0)) },
+                entities: [
+                    (name: "Root", position: (10.0, 0.0, 0.0)),
+                    (
+                        name: "Child",
+                        parent: Some("Root"),
+                        position: (0.0, 2.0, 0.0),
+                        mesh: Some(Cube),
+                        material: Some("red"),
+                    ),
+                ],
+            )"#,
+        )
+        .unwrap();
+
+        let draw_list = scene_file.scene.draw_list();
+        assert_eq!(draw_list.cubes.len(), 1);
+        assert_eq!(
+            draw_list.cubes[0].transform.translation,
+            glam::Vec3A::new(10., 2., 0.)
+        );
+        assert_eq!(draw_list.cubes[0].colour, Vec4::new(1., 0., 0., 1.));
+        assert_eq!(scene_file.scene.entity(draw_list.entities[0]).name, "Child");
+    }
+
+    #[test]
+    fn the_default_scene_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join(crate::assets::DEFAULT_ASSET_ROOT)
+            .join(DEFAULT_SCENE_PATH);
+        if let Err(e) = SceneFile::load(&path) {
+            panic!("{e}");
+        }
+    }
+
+    #[test]
+    fn rejects_an_unknown_parent() {
+        let error = parse(
+            r#"(entities: [
+                (name: "Root"),
+                (name: "Child", parent: Some("Rot")),
+            ])"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            r#"test.ron: entities[1].parent: no entity named "Rot" comes before this one"#
+        );
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        // Parents must come first, so a cycle always refers forwards somewhere
+        let error = parse(
+            r#"(entities: [
+                (name: "A", parent: Some("B")),
+                (name: "B", parent: Some("A")),
+            ])"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            r#"test.ron: entities[0].parent: no entity named "B" comes before this one"#
+        );
+
+        let error = parse(r#"(entities: [(name: "A", parent: Some("A"))])"#).unwrap_err();
+        assert_eq!(
+            error,
+            r#"test.ron: entities[0].parent: no entity named "A" comes before this one"#
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let error = parse(
+            r#"(entities: [
+                (name: "Tree"),
+                (name: "Rock"),
+                (name: "Tree"),
+            ])"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            r#"test.ron: entities[2].name: there's already an entity named "Tree""#
+        );
+    }
+
+    #[test]
+    fn rejects_meshes_without_materials() {
+        let error = parse(r#"(entities: [(name: "Box", mesh: Some(Cube))])"#).unwrap_err();
+        assert_eq!(
+            error,
+            "test.ron: entities[0].material: must be set to draw the mesh"
+        );
+
+        let error = parse(r#"(entities: [(name: "Box", mesh: Some(Cube), material: Some("x"))])"#)
+            .unwrap_err();
+        assert_eq!(
+            error,
+            r#"test.ron: entities[0].material: there's no material named "x""#
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_settings() {
+        let error = parse("(camera: (pitch_degrees: 120.0), entities: [])").unwrap_err();
+        assert_eq!(
+            error,
+            "test.ron: camera.pitch_degrees: must be between -90 and 90"
+        );
+
+        let error = parse(r#"(entities: [(name: "Flat", scale: (1.0, 0.0, 1.0))])"#).unwrap_err();
+        assert_eq!(
+            error,
+            "test.ron: entities[0].scale: must be finite and not zero on any axis"
+        );
+    }
+
+    #[test]
+    fn bad_ron_names_the_line_and_column() {
+        let error = parse("(\n    entities: [\n        (name: \"A\", colour: 1.0),\n    ],\n)")
+            .unwrap_err();
+        assert!(error.starts_with("test.ron:3:"), "{error}");
+        assert!(error.contains("colour"), "{error}");
+
+        let error = parse("(entities: [").unwrap_err();
+        assert!(error.starts_with("test.ron:1:"), "{error}");
+    }
+}
@@ -0,0 +1,368 @@
+use std::{path::PathBuf, time::Duration};
+
+use tracing::{error, info, trace_span};
+
+use crate::{
+    actions::{Action, Bindings},
+    graphics::{Camera, CameraPath},
+    input::{Input, InputEvent},
+    locomotive::Locomotive,
+    replay::{Recording, Replay},
+    time::{Clock, Frame, TICK_DURATION},
+};
+
+/// Where camera paths are saved to and played back from, unless told otherwise.
+pub const DEFAULT_CAMERA_PATH: &str = "camera_path.ron";
+
+/// Everything that advances on the fixed timestep: the clock, the input it sees, and the
+/// locomotive, along with any recording or replay of that input. The windowed game and headless
+/// replays both run frames through [`Simulation::run_frame`], so they end up in the same place.
+#[derive(Debug)]
+pub struct Simulation {
+    pub clock: Clock,
+    pub input: Input,
+    pub locomotive: Locomotive,
+    /// The bindings for live input, which take over again once a replay finishes.
+    bindings: Bindings,
+    /// The input recorded so far, if it's being recorded.
+    recording: Option<Recording>,
+    /// While this is running, live input is ignored.
+    replay: Option<Replay>,
+    /// Where camera paths are saved to and played back from.
+    pub camera_path: PathBuf,
+}
+
+impl Simulation {
+    /// Starts a simulation using `bindings` for live input, or playing back `replay` first if
+    /// there is one.
+    pub fn new(bindings: Bindings, replay: Option<Replay>) -> Self {
+        let input = match &replay {
+            Some(replay) => Input::new(replay.bindings().clone()),
+            None => Input::new(bindings.clone()),
+        };
+
+        Self {
+            clock: Clock::default(),
+            input,
+            locomotive: Locomotive::default(),
+            bindings,
+            recording: None,
+            replay,
+            camera_path: PathBuf::from(DEFAULT_CAMERA_PATH),
+        }
+    }
+
+    /// Records live input from now on, to be collected with [`Simulation::take_recording`].
+    pub fn start_recording(&mut self) {
+        self.recording = Some(Recording::new(self.bindings.clone()));
+    }
+
+    /// Stops recording, and returns everything recorded along with how many ticks were run.
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        let mut recording = self.recording.take()?;
+        recording.tick_count = self.clock.tick_count();
+        Some(recording)
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replay.is_some()
+    }
+
+    /// Passes a live input event on, recording it if need be. Ignored while replaying.
+    pub fn handle_input(&mut self, event: InputEvent) {
+        if self.replay.is_some() {
+            return;
+        }
+
+        if let Some(recording) = &mut self.recording {
+            recording.record(self.clock.frame_count(), self.clock.tick_count(), event);
+        }
+        self.input.handle_event(event);
+    }
+
+    /// Advances the clock by `delta` of real time, or by however long it's been since the last
+    /// frame if that's `None`, and runs the ticks that are due, moving `camera` along.
+    ///
+    /// During a replay, a frame never runs past the tick the next recorded frame's events arrived
+    /// at, so they're fed at exactly the same point however long the frames take.
+    pub fn run_frame(&mut self, camera: &mut Camera, delta: Option<Duration>) -> Frame {
+        if let Some(replay) = &mut self.replay {
+            replay.feed_frame(self.clock.tick_count(), &mut self.input);
+        }
+        self.handle_clock_actions();
+
+        self.clock
+            .set_tick_limit(self.replay.as_ref().map(Replay::tick_limit));
+        let frame = match delta {
+            Some(delta) => self.clock.advance_by(delta),
+            None => self.clock.advance(),
+        };
+
+        // Tick input is only consumed once a tick has seen it; otherwise it carries over to the
+        // next frame so nothing is lost when rendering faster than the tick rate.
+        for tick in frame.first_tick..frame.first_tick + frame.ticks as u64 {
+            let _span = trace_span!("tick", tick).entered();
+            self.tick(camera);
+            self.input.end_tick();
+        }
+
+        if self
+            .replay
+            .as_ref()
+            .is_some_and(|replay| replay.is_finished(self.clock.tick_count()))
+        {
+            info!("Replay finished after {} ticks", self.clock.tick_count());
+            self.replay = None;
+            self.input = Input::new(self.bindings.clone());
+        }
+
+        frame
+    }
+
+    /// Clears the input's per-frame state. Call this once everything has seen the frame's input.
+    pub fn end_frame(&mut self) {
+        self.input.end_frame();
+    }
+
+    /// Handles the actions that control the clock. These respond as soon as they're pressed, even
+    /// while the simulation is paused.
+    fn handle_clock_actions(&mut self) {
+        let input = &self.input;
+
+        if input.was_pressed_this_frame(Action::TogglePause) {
+            self.clock.toggle_pause();
+        }
+        if input.was_pressed_this_frame(Action::StepSimulation) {
+            self.clock.step();
+        }
+        if input.was_pressed_this_frame(Action::SlowDown) {
+            self.clock.set_time_scale(self.clock.time_scale() / 2.);
+        }
+        if input.was_pressed_this_frame(Action::SpeedUp) {
+            self.clock.set_time_scale(self.clock.time_scale() * 2.);
+        }
+    }
+
+    /// Runs a single tick.
+    fn tick(&mut self, camera: &mut Camera) {
+        self.handle_camera_actions(camera);
+        self.locomotive.update(TICK_DURATION, &self.input);
+        camera.update(TICK_DURATION, &self.input, self.locomotive.pose());
+    }
+
+    /// Handles the actions that change what the camera does. Like switching controllers, these
+    /// happen on ticks, so a replay repeats them at the same point in the simulation.
+    fn handle_camera_actions(&mut self, camera: &mut Camera) {
+        let input = &self.input;
+
+        if input.was_pressed(Action::ToggleProjection) {
+            camera.toggle_projection();
+        }
+        if input.was_pressed(Action::RecordCameraPath) {
+            if let Some(path) = camera.stop_recording_path() {
+                match path.save(&self.camera_path) {
+                    Ok(()) => info!(
+                        "Saved {} keyframes to {}",
+                        path.keyframes.len(),
+                        self.camera_path.display()
+                    ),
+                    Err(e) => error!(
+                        "Couldn't save camera path to {}: {e}",
+                        self.camera_path.display()
+                    ),
+                }
+            } else {
+                camera.start_recording_path();
+                info!("Recording camera path");
+            }
+        }
+        if input.was_pressed(Action::PlayCameraPath) {
+            match CameraPath::load(&self.camera_path) {
+                Ok(path) => {
+                    info!("Playing {:.1}s camera path", path.duration());
+                    camera.play_path(path);
+                }
+                Err(e) => error!(
+                    "Couldn't load camera path from {}: {e}",
+                    self.camera_path.display()
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use ash::vk;
+    use glam::Vec3;
+    use winit::{event::ElementState, keyboard::KeyCode};
+
+    use super::*;
+    use crate::{
+        graphics::{Pose, Projection},
+        input::Button,
+        replay::replay_headless,
+    };
+
+    /// Enough to tell where the camera is and how it's projecting.
+    #[derive(Debug, PartialEq)]
+    struct CameraState {
+        position: Vec3,
+        rotation: glam::Quat,
+        projection: Projection,
+    }
+
+    impl CameraState {
+        fn of(camera: &mut Camera) -> Self {
+            camera.interpolate(1.);
+            Self {
+                position: camera.position(),
+                rotation: camera.rotation(),
+                projection: camera.projection,
+            }
+        }
+    }
+
+    fn camera_start() -> Pose {
+        Pose {
+            position: Vec3::new(0., 10., 10.),
+            rotation: glam::Quat::IDENTITY,
+        }
+    }
+
+    fn camera() -> Camera {
+        let mut camera = Camera::new(vk::Extent2D {
+            width: 1,
+            height: 1,
+        });
+        camera.set_pose(camera_start());
+        camera
+    }
+
+    fn key(key_code: KeyCode, pressed: bool) -> InputEvent {
+        InputEvent::Button {
+            button: Button::Key(key_code),
+            state: if pressed {
+                ElementState::Pressed
+            } else {
+                ElementState::Released
+            },
+        }
+    }
+
+    /// Presses `key_code` before `frame`, and releases it before the next one.
+    fn tap(script: &mut [Vec<InputEvent>], frame: usize, key_code: KeyCode) {
+        script[frame].push(key(key_code, true));
+        script[frame + 1].push(key(key_code, false));
+    }
+
+    /// Frame times that vary from a fraction of a tick to several ticks, repeating every
+    /// `pattern.len()` frames.
+    fn frame_delta(frame: usize, pattern: &[f32]) -> Duration {
+        Duration::from_secs_f32(pattern[frame % pattern.len()] * TICK_DURATION)
+    }
+
+    /// A unique camera path file with a short path in it.
+    fn camera_path_file() -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("train-simulation-test-{}.ron", std::process::id()));
+        CameraPath::orbit(Vec3::ZERO, 20., 10., 0.5)
+            .save(&path)
+            .unwrap();
+        path
+    }
+
+    /// Plays a short session with live input, returning its recording and where the camera ended
+    /// up. It drives, looks around, switches controllers, zooms, pauses and steps, changes the
+    /// projection and plays a camera path.
+    fn record_session(camera_path: &Path) -> (Recording, CameraState) {
+        let mut simulation = Simulation::new(Bindings::default(), None);
+        simulation.camera_path = camera_path.to_path_buf();
+        simulation.start_recording();
+        let mut camera = camera();
+
+        // The input that arrives before each frame
+        let mut script: Vec<Vec<InputEvent>> = vec![Vec::new(); 80];
+        script[1].extend([key(KeyCode::KeyW, true), key(KeyCode::ArrowUp, true)]);
+        script[4].push(InputEvent::MouseMotion { x: 12., y: -5. });
+        script[6].push(InputEvent::Scroll { lines: 2. });
+        script[9].push(key(KeyCode::KeyW, false));
+        tap(&mut script, 12, KeyCode::KeyP);
+        tap(&mut script, 14, KeyCode::Period);
+        tap(&mut script, 16, KeyCode::Period);
+        tap(&mut script, 18, KeyCode::KeyP);
+        tap(&mut script, 20, KeyCode::Digit3);
+        tap(&mut script, 26, KeyCode::KeyO);
+        script[27].push(InputEvent::Scroll { lines: -1. });
+        tap(&mut script, 30, KeyCode::Digit1);
+        script[31].push(key(KeyCode::KeyD, true));
+        script[36].extend([key(KeyCode::KeyD, false), key(KeyCode::ArrowUp, false)]);
+        tap(&mut script, 40, KeyCode::F6);
+        tap(&mut script, 55, KeyCode::KeyO);
+        tap(&mut script, 60, KeyCode::Digit2);
+        script[62].push(InputEvent::MouseMotion { x: -30., y: 8. });
+
+        for (frame, events) in script.into_iter().enumerate() {
+            for event in events {
+                simulation.handle_input(event);
+            }
+            simulation.run_frame(&mut camera, Some(frame_delta(frame, &[1., 0.4, 2.3, 0.])));
+            simulation.end_frame();
+        }
+
+        (
+            simulation.take_recording().unwrap(),
+            CameraState::of(&mut camera),
+        )
+    }
+
+    #[test]
+    fn replays_end_where_the_recording_did() {
+        let camera_path = camera_path_file();
+        let (recording, recorded) = record_session(&camera_path);
+        assert!(recording.tick_count > 0);
+
+        let mut headless = replay_headless(recording.clone(), camera_start(), camera_path.clone());
+        assert_eq!(CameraState::of(&mut headless), recorded, "headless");
+
+        // A windowed replay runs at whatever rate the display allows
+        let mut simulation = Simulation::new(Bindings::default(), Some(Replay::new(recording)));
+        simulation.camera_path = camera_path.clone();
+        let mut camera = camera();
+        let mut frame = 0;
+        while simulation.is_replaying() {
+            simulation.run_frame(&mut camera, Some(frame_delta(frame, &[3.1, 0.2, 0.7])));
+            simulation.end_frame();
+            frame += 1;
+        }
+        assert_eq!(CameraState::of(&mut camera), recorded, "windowed");
+
+        std::fs::remove_file(camera_path).unwrap();
+    }
+
+    #[test]
+    fn live_input_is_ignored_during_a_replay() {
+        let recording = Recording {
+            bindings: Bindings::default(),
+            events: Vec::new(),
+            tick_count: 3,
+        };
+        let mut simulation = Simulation::new(Bindings::default(), Some(Replay::new(recording)));
+        simulation.start_recording();
+        let mut camera = camera();
+
+        simulation.handle_input(key(KeyCode::KeyW, true));
+        assert!(!simulation.input.is_held(Action::MoveForward));
+
+        let frame = simulation.run_frame(&mut camera, Some(Duration::from_secs(1)));
+        assert_eq!((frame.first_tick, frame.ticks), (0, 3));
+        assert!(!simulation.is_replaying());
+        assert!(simulation.take_recording().unwrap().events.is_empty());
+
+        // Live input takes over afterwards
+        simulation.handle_input(key(KeyCode::KeyW, true));
+        assert!(simulation.input.is_held(Action::MoveForward));
+    }
+}
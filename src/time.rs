@@ -0,0 +1,270 @@
+use std::time::{Duration, Instant};
+
+/// How many times per second the simulation is advanced.
+pub const TICK_RATE: f32 = 60.;
+/// The simulated time that passes in each tick, in seconds.
+pub const TICK_DURATION: f32 = 1. / TICK_RATE;
+
+/// The most ticks that will be run in a single frame. If the simulation falls further behind than
+/// this (eg. after a breakpoint or a long hitch) the backlog is dropped rather than run all at once.
+const MAX_TICKS_PER_FRAME: u32 = 8;
+
+const MIN_TIME_SCALE: f32 = 1. / 16.;
+const MAX_TIME_SCALE: f32 = 16.;
+
+/// What should happen this frame, according to the [`Clock`].
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    /// The index of the first tick to run this frame.
+    pub first_tick: u64,
+    /// The number of fixed ticks to advance the simulation by.
+    pub ticks: u32,
+    /// How far between the previous and current tick to render, from 0 to 1.
+    pub alpha: f32,
+}
+
+/// Converts real elapsed time into fixed simulation ticks, with pause, single-step and time-scale
+/// controls.
+#[derive(Debug)]
+pub struct Clock {
+    /// How fast simulated time passes relative to real time.
+    time_scale: f32,
+    paused: bool,
+    step_requested: bool,
+    /// Scaled time that hasn't yet been consumed by a tick.
+    accumulator: f32,
+    last_frame: Option<Instant>,
+    /// How many frames have been advanced so far.
+    frame_count: u64,
+    /// How many ticks have been handed out so far, which is also the index of the next one.
+    tick_count: u64,
+    /// The tick count not to go beyond, if any.
+    tick_limit: Option<u64>,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self {
+            time_scale: 1.,
+            paused: false,
+            step_requested: false,
+            accumulator: 0.,
+            last_frame: None,
+            frame_count: 0,
+            tick_count: 0,
+            tick_limit: None,
+        }
+    }
+}
+
+impl Clock {
+    /// Measures the time since the last call and works out how many ticks to run.
+    pub fn advance(&mut self) -> Frame {
+        let now = Instant::now();
+        let delta = self
+            .last_frame
+            .map(|last_frame| now - last_frame)
+            .unwrap_or_default();
+        self.last_frame = Some(now);
+
+        self.advance_by(delta)
+    }
+
+    /// Works out how many ticks to run for a frame that took `delta` of real time.
+    pub fn advance_by(&mut self, delta: Duration) -> Frame {
+        let delta = delta.as_secs_f32();
+        let max_ticks = match self.tick_limit {
+            Some(limit) => limit
+                .saturating_sub(self.tick_count)
+                .min(MAX_TICKS_PER_FRAME as u64) as u32,
+            None => MAX_TICKS_PER_FRAME,
+        };
+        let mut ticks = 0;
+        if self.paused {
+            if self.step_requested && max_ticks > 0 {
+                self.step_requested = false;
+                ticks = 1;
+            }
+        } else {
+            self.accumulator += delta * self.time_scale;
+            while self.accumulator >= TICK_DURATION && ticks < max_ticks {
+                self.accumulator -= TICK_DURATION;
+                ticks += 1;
+            }
+
+            if ticks == max_ticks {
+                self.accumulator %= TICK_DURATION;
+            }
+        }
+
+        let first_tick = self.tick_count;
+        self.tick_count += ticks as u64;
+        self.frame_count += 1;
+
+        Frame {
+            first_tick,
+            ticks,
+            alpha: self.accumulator / TICK_DURATION,
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn tick_count(&self) -> u64 {
+        self.tick_count
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+        self.step_requested = false;
+    }
+
+    /// While paused, runs exactly one tick on the next frame.
+    pub fn step(&mut self) {
+        if self.paused {
+            self.step_requested = true;
+        }
+    }
+
+    /// Stops ticks being handed out once `tick_count` reaches `limit`, until it's raised or
+    /// removed. Like a long frame, any time left over once it's reached is dropped.
+    pub fn set_tick_limit(&mut self, limit: Option<u64>) {
+        self.tick_limit = limit;
+    }
+
+    pub fn time_scale(&self) -> f32 {
+        self.time_scale
+    }
+
+    pub fn set_time_scale(&mut self, time_scale: f32) {
+        self.time_scale = time_scale.clamp(MIN_TIME_SCALE, MAX_TIME_SCALE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(delta: f32) -> Duration {
+        Duration::from_secs_f32(delta * TICK_DURATION)
+    }
+
+    #[test]
+    fn runs_a_tick_for_each_tick_duration() {
+        let mut clock = Clock::default();
+
+        let frame = clock.advance_by(ticks(0.5));
+        assert_eq!((frame.first_tick, frame.ticks), (0, 0));
+
+        let frame = clock.advance_by(ticks(2.));
+        assert_eq!((frame.first_tick, frame.ticks), (0, 2));
+
+        let frame = clock.advance_by(ticks(1.));
+        assert_eq!((frame.first_tick, frame.ticks), (2, 1));
+
+        assert_eq!(clock.tick_count(), 3);
+        assert_eq!(clock.frame_count(), 3);
+    }
+
+    #[test]
+    fn alpha_is_the_leftover_fraction_of_a_tick() {
+        let mut clock = Clock::default();
+
+        let frame = clock.advance_by(ticks(1.25));
+        assert_eq!(frame.ticks, 1);
+        assert!((frame.alpha - 0.25).abs() < 1e-3, "{}", frame.alpha);
+
+        let frame = clock.advance_by(ticks(0.5));
+        assert_eq!(frame.ticks, 0);
+        assert!((frame.alpha - 0.75).abs() < 1e-3, "{}", frame.alpha);
+    }
+
+    #[test]
+    fn drops_the_backlog_beyond_the_most_ticks_per_frame() {
+        let mut clock = Clock::default();
+
+        let frame = clock.advance_by(Duration::from_secs(5));
+        assert_eq!(frame.ticks, MAX_TICKS_PER_FRAME);
+        assert!((0. ..1.).contains(&frame.alpha));
+
+        // The rest of the five seconds isn't made up for later
+        let frame = clock.advance_by(Duration::ZERO);
+        assert_eq!(frame.ticks, 0);
+    }
+
+    #[test]
+    fn pausing_stops_ticks_until_stepped() {
+        let mut clock = Clock::default();
+        clock.toggle_pause();
+
+        assert_eq!(clock.advance_by(ticks(3.)).ticks, 0);
+
+        clock.step();
+        let frame = clock.advance_by(ticks(3.));
+        assert_eq!((frame.first_tick, frame.ticks), (0, 1));
+
+        // Only one tick per step
+        assert_eq!(clock.advance_by(ticks(3.)).ticks, 0);
+
+        // Time that passed while paused isn't made up for either
+        clock.toggle_pause();
+        assert_eq!(clock.advance_by(ticks(1.5)).ticks, 1);
+    }
+
+    #[test]
+    fn stepping_does_nothing_unless_paused() {
+        let mut clock = Clock::default();
+        clock.step();
+        assert_eq!(clock.advance_by(Duration::ZERO).ticks, 0);
+
+        // Nor is the step saved for when it is paused
+        clock.toggle_pause();
+        assert_eq!(clock.advance_by(Duration::ZERO).ticks, 0);
+    }
+
+    #[test]
+    fn ticks_stop_at_the_limit() {
+        let mut clock = Clock::default();
+        clock.set_tick_limit(Some(3));
+
+        let frame = clock.advance_by(ticks(2.));
+        assert_eq!((frame.first_tick, frame.ticks), (0, 2));
+        let frame = clock.advance_by(ticks(5.));
+        assert_eq!((frame.first_tick, frame.ticks), (2, 1));
+        assert_eq!(clock.advance_by(ticks(5.)).ticks, 0);
+
+        // A step waits for the limit to be raised too
+        clock.toggle_pause();
+        clock.step();
+        assert_eq!(clock.advance_by(Duration::ZERO).ticks, 0);
+        clock.set_tick_limit(None);
+        let frame = clock.advance_by(Duration::ZERO);
+        assert_eq!((frame.first_tick, frame.ticks), (3, 1));
+    }
+
+    #[test]
+    fn time_scale_speeds_up_and_slows_down_ticks() {
+        let mut clock = Clock::default();
+
+        clock.set_time_scale(2.);
+        assert_eq!(clock.advance_by(ticks(2.5)).ticks, 5);
+
+        let mut clock = Clock::default();
+        clock.set_time_scale(0.5);
+        assert_eq!(clock.advance_by(ticks(3.)).ticks, 1);
+        assert_eq!(clock.advance_by(ticks(1.)).ticks, 1);
+    }
+
+    #[test]
+    fn time_scale_is_clamped() {
+        let mut clock = Clock::default();
+
+        clock.set_time_scale(1000.);
+        assert_eq!(clock.time_scale(), MAX_TIME_SCALE);
+
+        clock.set_time_scale(0.);
+        assert_eq!(clock.time_scale(), MIN_TIME_SCALE);
+    }
+}